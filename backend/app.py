@@ -1,42 +1,351 @@
 from functools import wraps
-from flask import Flask, jsonify, request, make_response, send_from_directory
+from flask import Flask, Request, jsonify, request, make_response, send_from_directory, Response, stream_with_context, g
 from werkzeug.routing import Rule
 from mongolog import *
 import base64
 import datetime
+import email.policy
+import io
+import email.utils
+from email.message import EmailMessage
+import hashlib
+import hmac
+import ipaddress
 import jwt
-from util import get_random_subdomain
+from util import get_random_subdomain, get_random_correlation_id, get_random_correlation_prefix, get_sequential_correlation_id, CORRELATION_COUNTER_MAX
+from payloads import PAYLOAD_TEMPLATES, render_payloads
+from botscore import bot_score
+from breaker import get_breaker, breakers_status, set_error_handler
+from ingest import ingest_queue
+from events import EventHub
+from compression import negotiate_encoding, compress
+from blocklist import BLOCK_ACTIONS, BLOCKLIST_MAX_ENTRIES, valid_network, valid_country, block_action
+from crawl import CHANGEFREQS, crawl_entries, robots_txt, sitemap_xml
+from language import MAX_VARIANTS, valid_language, negotiate_language
+from cloak import PROFILES, valid_server_header, server_identity, apply_identity
+from settings import setting, reload_settings, settings_status
+from logconfig import LEVELS, LOG_FORMAT, LOG_LEVEL, configured as configured_log_levels, setup_logging, watch_levels
+from gelf import gelf_message, gelf_send
+from elastic import elastic_sink
+from notify import notify, valid_webhook
+from mailer import email_alert, confirmation_email, relay_connection
+from configsign import generate_config_key, config_public_key, sign_config
+from dkim import DKIM_SELECTOR, generate_dkim_key, dkim_record, dkim_sign
+from health import DNS_HEALTH_ADDRESS, check_dns, probe_http, check_pages, run_checks, run_checks_cached
+from schema import SCHEMA
+from certs import CERT_MAX_PER_SESSION, minting_enabled, mint_limited, delete_certificates, watch_certificates
+from bodyparse import parse_body, multipart_files
+from s3 import error_xml, list_bucket_xml, http_date
+from metadata import DEFAULT_ROLE, generate_canaries, canary_values, find_canary_candidates, metadata_response
+from findings import scan_request
+from interactsh import INTERACTSH, INTERACTSH_TOKEN, get_correlation_id, load_public_key, encrypt_interactions
+from encryption import generate_keys, load_private_key, encrypt_fields, decrypt_fields, HTTP_ENCRYPTED_FIELDS
 import re
 import json
+import logging
 import os
+import queue
+import secrets
+import signal
+import socket
+import tempfile
+import time
+import urllib.parse
 
 JWT_SECRET = os.getenv('JWT_SECRET', os.urandom(32))
 DOMAIN = os.getenv('DOMAIN', 'requestrepo.com')
+ADMIN_TOKEN = os.getenv('ADMIN_TOKEN')
+# addresses, networks or host names of the proxies allowed to set
+# Requestrepo-X-Forwarded-For and the forwarded scheme, normally our nginx
+TRUSTED_PROXIES = set(filter(None, os.getenv('TRUSTED_PROXIES', '').split(',')))
+# host names are resolved again after this many seconds
+TRUSTED_PROXIES_TTL = 60
+# answered directly on every host and never captured; empty disables it
+HEALTH_PATH = os.getenv('HEALTH_PATH', '/__health')
+HEALTH_DNS_NAME = os.getenv('HEALTH_DNS_NAME', '_health.requestrepo.com')
+RESERVED_LABELS = set(
+    filter(
+        None,
+        os.getenv(
+            'RESERVED_LABELS',
+            'www,mail,smtp,imap,pop,mx,api,admin,ns,ns1,ns2,dns,ftp,cpanel,'
+            'webmail,autodiscover,autoconfig,_acme-challenge,_dmarc,_domainkey'
+        ).lower().split(',')))
+PATH_ROUTING = os.getenv('PATH_ROUTING', 'true').lower() == 'true'
+PATH_ROUTING_APEX_ONLY = os.getenv('PATH_ROUTING_APEX_ONLY',
+                                   'true').lower() == 'true'
+VERSION = os.getenv('REQUESTREPO_VERSION', 'dev')
+# the listeners in front of the app, advertised through /api/v2/instance
+HTTP_PORTS = [int(x) for x in os.getenv('HTTP_PORTS', '80,8080,3000').split(',')]
+HTTPS_PORTS = [int(x) for x in os.getenv('HTTPS_PORTS', '443,8443').split(',')]
+DNS_PORTS = [int(x) for x in os.getenv('DNS_PORTS', '53').split(',')]
+# base64 encoded size of the response file
+RESPONSE_MAX_SIZE = 2000000
+# paths with a response of their own, the file's response is the catch-all
+PATH_RESPONSES_MAX = 10
 
 app = Flask(__name__, static_url_path='/public/static')
+log = logging.getLogger('app')
+
+
+def get_log_overrides():
+    overrides = log_levels_get()
+    if overrides is None:
+        return {}
+    return {x['module']: x['level'] for x in overrides['levels']}
+
+
+setup_logging()
+watch_levels(get_log_overrides)
+if minting_enabled():
+    watch_certificates()
 app.url_map.add(Rule('/', endpoint='index'))
 app.url_map.add(Rule('/<path:path>', endpoint='catch_all'))
 
 
+def record_capture_error(step, ex):
+    errors_insert(ingest_queue.current_subdomain(), 'http', step, str(ex))
+
+
+set_error_handler(record_capture_error)
+
+
 def check_subdomain(f):
     @wraps(f)
     def decorated_function(*args, **kwargs):
+        if HEALTH_PATH and request.path == HEALTH_PATH:
+            return health_response()
+
+        if INTERACTSH:
+            correlation_id, full_id = get_correlation_id(request.host, DOMAIN)
+            if correlation_id and interactsh_exists(correlation_id):
+                return interactsh_response(request, correlation_id, full_id)
+
         subdomain = get_subdomain_from_hostname(request.host)
         if subdomain:
-            return subdomain_response(request, subdomain)
+            return subdomain_response(request, subdomain, 'subdomain')
 
         return f(*args, **kwargs)
 
     return decorated_function
 
 
+def health_response():
+    resp = make_response('OK')
+    resp.headers['Content-Type'] = 'text/plain'
+    resp.headers['Cache-Control'] = 'no-store'
+    return resp
+
+
+def interactsh_response(request, correlation_id, full_id):
+    body = f'<html><head></head><body>{full_id[::-1]}</body></html>'
+    raw_request = f'{request.method} {request.full_path.rstrip("?")} ' + \
+        f'{request.environ.get("SERVER_PROTOCOL")}\r\n'
+    raw_request += ''.join(f'{k}: {v}\r\n' for k, v in request.headers
+                           if not k.lower().startswith('requestrepo-x-'))
+    raw_request += '\r\n' + request.get_data().decode(errors='replace')
+
+    interactsh_insert_interaction(
+        correlation_id, {
+            'protocol': 'http',
+            'unique-id': full_id,
+            'full-id': full_id,
+            'raw-request': raw_request,
+            'raw-response':
+            'HTTP/1.1 200 OK\r\nContent-Type: text/html\r\n\r\n' + body,
+            'remote-address': get_client_ip(request),
+            'timestamp': datetime.datetime.now(
+                datetime.timezone.utc).isoformat().replace('+00:00', 'Z')
+        })
+
+    resp = make_response(body)
+    resp.headers['Content-Type'] = 'text/html'
+    return resp
+
+
+def get_request_token(request):
+    authorization = request.headers.get('Authorization', '')
+    if authorization.startswith('Bearer '):
+        return authorization[len('Bearer '):]
+    return request.cookies.get('token')
+
+
+def is_management_request(request, subdomain):
+    # sent with the session's own token, e.g. by the CLI or a path routed preview
+    token = get_request_token(request)
+    return bool(token) and verify_jwt(token) == subdomain
+
+
+trusted_networks = {'date': 0, 'networks': []}
+
+
+def get_trusted_networks():
+    if time.time() - trusted_networks['date'] < TRUSTED_PROXIES_TTL:
+        return trusted_networks['networks']
+
+    networks = []
+    for proxy in TRUSTED_PROXIES:
+        try:
+            networks.append(str(ipaddress.ip_network(proxy, strict=False)))
+        except ValueError:
+            try:
+                networks += socket.gethostbyname_ex(proxy)[2]
+            except OSError:
+                log.warning(f'could not resolve trusted proxy {proxy}')
+    trusted_networks.update(date=time.time(), networks=networks)
+    return networks
+
+
+def from_trusted_proxy(request):
+    return bool(TRUSTED_PROXIES) and ip_allowed(request.remote_addr,
+                                                get_trusted_networks())
+
+
+def get_client_ip(request):
+    # anyone reaching the app directly could claim any address
+    if from_trusted_proxy(request):
+        return request.headers.get('Requestrepo-X-Forwarded-For',
+                                   request.remote_addr)
+    return request.remote_addr
+
+
+def ip_allowed(ip, networks):
+    try:
+        address = ipaddress.ip_address(ip)
+    except ValueError:
+        return False
+    return any(address in ipaddress.ip_network(network, strict=False)
+               for network in networks)
+
+
 def verify_jwt(token):
     try:
-        return jwt.decode(token, JWT_SECRET, algorithms=['HS256'])['subdomain']
+        subdomain = jwt.decode(token, JWT_SECRET,
+                               algorithms=['HS256'])['subdomain']
+        return subdomain if session_allowed(subdomain) else None
     except Exception:
         return None
 
 
+def session_allowed(subdomain):
+    user = users_get_subdomain(subdomain)
+    if user is not None and user.get('ip_pinning'):
+        return ip_allowed(get_client_ip(request), user['allowed_ips'])
+    return True
+
+
+def is_admin(request):
+    token = request.headers.get('Authorization', '')
+    return ADMIN_TOKEN is not None and token == 'Bearer ' + ADMIN_TOKEN
+
+
+def enrich_bot_score(dic, headers):
+    last = http_get_last_received(dic['uid'], dic['ip'])
+    return bot_score(headers, dic.get('ja3'),
+                     dic['received'] - last if last is not None else None)
+
+
+def get_request_scheme(request, headers):
+    if not from_trusted_proxy(request):
+        return request.scheme
+
+    # set by our own nginx from the listener the connection arrived on
+    if 'Requestrepo-X-Forwarded-Proto' in headers:
+        return headers['Requestrepo-X-Forwarded-Proto'].lower()
+
+    forwarded = request.headers.get('Forwarded')
+    if forwarded:
+        for element in forwarded.split(',')[0].split(';'):
+            key, _, value = element.strip().partition('=')
+            if key.lower() == 'proto' and value:
+                return value.strip('"').lower()
+    proto = request.headers.get('X-Forwarded-Proto')
+    if proto:
+        return proto.split(',')[0].strip().lower()
+
+    return request.scheme
+
+
+TRACEPARENT_REGEX = '^([0-9a-f]{2})-([0-9a-f]{32})-([0-9a-f]{16})-([0-9a-f]{2})$'
+
+
+def parse_trace_context(headers):
+    traceparent = None
+    tracestate = None
+    for name in headers:
+        if name.lower() == 'traceparent':
+            traceparent = headers[name].strip().lower()
+        elif name.lower() == 'tracestate':
+            tracestate = headers[name]
+
+    if traceparent is None:
+        return None
+
+    match = re.match(TRACEPARENT_REGEX, traceparent)
+    if not match or match.group(2) == '0' * 32 or match.group(3) == '0' * 16:
+        return None
+
+    trace = {
+        'version': match.group(1),
+        'trace_id': match.group(2),
+        'parent_id': match.group(3),
+        'flags': match.group(4),
+        'sampled': int(match.group(4), 16) & 1 == 1
+    }
+    if tracestate:
+        trace['state'] = [
+            member.strip() for member in tracestate.split(',')
+            if member.strip()
+        ]
+    return trace
+
+
+CORRELATION_ID_REGEX = '(?<![0-9a-z])[0-9a-z]{12}(?![0-9a-z])'
+CORRELATION_MAX_CANDIDATES = 32
+
+
+def enrich_correlation_ids(dic, headers):
+    text = ' '.join([request.host, dic['path']] + list(headers.values()))
+    candidates = list(
+        dict.fromkeys(re.findall(CORRELATION_ID_REGEX, text.lower())))
+    if not candidates:
+        return []
+    return payloads_match_hits(dic['uid'],
+                               candidates[:CORRELATION_MAX_CANDIDATES],
+                               dic['date'])
+
+
+def decrypt_requests(request, subdomain, requests):
+    passphrase = request.headers.get('X-Encryption-Passphrase')
+    if not passphrase:
+        return True
+
+    key = encryption_get_key(subdomain)
+    if key is None:
+        return True
+
+    private_key = load_private_key(key['private_key'], passphrase)
+    if private_key is None:
+        return False
+
+    for x in requests:
+        if 'encrypted' in x:
+            decrypt_fields(private_key, x)
+    return True
+
+
+def http_gelf_message(dic):
+    fields = {
+        key: dic.get(key)
+        for key in ('uid', 'ip', 'method', 'path', 'url', 'protocol',
+                    'bot_score', 'ja3', 'ja4', 'routing')
+    }
+    fields['request_id'] = str(dic['_id'])
+    fields['rtype'] = 'HTTP'
+    short_message = f"HTTP {dic['method']} {dic.get('path', '')}"
+    return gelf_message(DOMAIN, short_message, dic['date'], fields)
+
+
 def write_basic_file(subdomain):
     file_data = {
         'headers': [{
@@ -56,18 +365,65 @@ def write_basic_file(subdomain):
         json.dump(file_data, outfile)
 
 
-def log_request(request, subdomain):
+def log_request(request,
+                subdomain,
+                routing,
+                response_encoding=None,
+                timing=None,
+                chain=None,
+                s3=None,
+                metadata=False,
+                language=None,
+                crawl=None,
+                received=None):
     dic = {}
     headers = dict(request.headers)
 
-    dic['raw'] = request.stream.read()
+    dic['raw'] = request.get_data()
     dic['uid'] = subdomain
-    if 'Requestrepo-X-Forwarded-For' in headers:
-        dic['ip'] = headers['Requestrepo-X-Forwarded-For']
-        del headers['Requestrepo-X-Forwarded-For']
-    else:
-        dic['ip'] = request.remote_addr
+    dic['ip'] = get_client_ip(request)
+    headers.pop('Requestrepo-X-Forwarded-For', None)
+    scheme = get_request_scheme(request, headers)
+    if 'Requestrepo-X-Forwarded-Proto' in headers:
+        del headers['Requestrepo-X-Forwarded-Proto']
+    # the listener port, which differs from 80/443 on the extra capture ports
+    port = headers.pop('Requestrepo-X-Forwarded-Port', None)
+    if port is not None and port.isdigit() and from_trusted_proxy(request):
+        dic['port'] = int(port)
+    tls = {}
+    for field in ('Protocol', 'Cipher', 'Session-Reused'):
+        header = 'Requestrepo-X-Tls-' + field
+        if header in headers:
+            tls[field.lower().replace('-', '_')] = headers[header]
+            del headers[header]
+    if scheme == 'https' and tls.get('protocol'):
+        dic['tls'] = {
+            'protocol': tls['protocol'],
+            'cipher': tls.get('cipher'),
+            'resumed': tls.get('session_reused') == 'r'
+        }
+
+    # TLS is terminated by the proxy, which forwards the ClientHello fingerprints
+    for fingerprint in ('ja3', 'ja4'):
+        header = 'Requestrepo-X-' + fingerprint.capitalize()
+        if header in headers:
+            if scheme == 'https' and re.match('^[0-9a-z_]{1,128}$',
+                                              headers[header].lower()):
+                dic[fingerprint] = headers[header].lower()
+            del headers[header]
+    # set by nginx when it is built with the geoip module
+    country = headers.pop('Requestrepo-X-Country', None)
+    if country and from_trusted_proxy(request) and re.match(
+            '^[A-Za-z]{2}$', country):
+        dic['country'] = country.upper()
     dic['headers'] = headers
+    # every header line in order, headers joins the values of repeated names
+    lines = request.environ.get('requestrepo.header_lines')
+    if lines is None:
+        lines = list(request.headers.items())
+    kept = {name.lower() for name in headers}
+    dic['header_list'] = [[name, value] for name, value in lines
+                          if name.lower() in kept]
     dic['method'] = request.method
     dic['protocol'] = request.environ.get('SERVER_PROTOCOL')
     if request.full_path[-1] == '?' and request.url[-1] != '?':
@@ -78,13 +434,167 @@ def log_request(request, subdomain):
         dic['query'] = dic['path'][dic['path'].find('?'):]
     else:
         dic['query'] = ''
-    dic['url'] = request.url
-    dic['date'] = int(datetime.datetime.now(datetime.timezone.utc).timestamp())
+    dic['url'] = scheme + request.url[len(request.scheme):]
+    if received is None:
+        received = time.time()
+    dic['date'] = int(received)
+    # sub-second arrival time, whole seconds are too coarse for timing
+    dic['received'] = round(received, 3)
+    dic['routing'] = routing
+    if response_encoding is not None:
+        dic['response_encoding'] = response_encoding
+    if timing is not None:
+        dic['timing'] = timing
+    if chain is not None:
+        dic['chain'] = chain
+    if s3 is not None:
+        dic['s3'] = s3
+    if metadata:
+        dic['metadata'] = True
+    if language is not None:
+        dic['language'] = language
+    if crawl is not None:
+        dic['crawl'] = crawl
+    if is_management_request(request, subdomain):
+        dic['management'] = True
+
+    collapse = collapse_get_settings(subdomain) or {}
+    if collapse.get('enabled'):
+        dic['collapse_key'] = get_collapse_key(dic)
+        if http_collapse(subdomain, dic['collapse_key'], dic['date'],
+                         collapse['window']):
+            # still a hit, even when it is not stored on its own
+            if not dic.get('management') or setting('FORWARD_MANAGEMENT'):
+                get_breaker('stats').call(
+                    record_stats, dic, request.path,
+                    encryption_get_key(subdomain) is not None)
+            return
+        dic['count'] = 1
+        dic['last'] = dic['date']
+
+    trace = parse_trace_context(headers)
+    if trace is not None:
+        dic['trace'] = trace
+
+    parsed = get_breaker('body_parse').call(parse_body, headers, dic['raw'])
+    if parsed is not None:
+        dic['parsed'] = parsed
+
+    findings = get_breaker('findings').call(scan_request,
+                                            headers,
+                                            dic['path'],
+                                            dic['raw'],
+                                            default=[])
+    if findings:
+        dic['findings'] = findings
+        # stays readable when the findings themselves are encrypted
+        dic['has_findings'] = True
+
+    dic['bot_score'] = get_breaker('bot_score').call(enrich_bot_score, dic,
+                                                     headers)
+    correlation_ids = get_breaker('correlation').call(enrich_correlation_ids,
+                                                      dic,
+                                                      headers,
+                                                      default=[])
+    if correlation_ids:
+        dic['correlation_ids'] = correlation_ids
+
+    canaries = get_breaker('canaries').call(find_canaries,
+                                            dic,
+                                            headers,
+                                            default=[])
+
+    # management traffic stays out of statistics and sinks unless audited
+    forward = not dic.get('management') or setting('FORWARD_MANAGEMENT')
+    key = encryption_get_key(subdomain)
+    if forward:
+        get_breaker('stats').call(record_stats, dic, request.path,
+                                  key is not None)
+    if key is not None:
+        encrypt_fields(key['public_key'], dic, HTTP_ENCRYPTED_FIELDS)
 
     http_insert_into_db(dic)
 
+    for owner, values in canaries:
+        alerts_insert(
+            owner, 'canary_used', {
+                'rtype': 'HTTP',
+                'subdomain': subdomain,
+                'ip': dic['ip'],
+                'request_id': str(dic['_id']),
+                'values': values
+            })
+
+    if not forward:
+        return
+
+    get_breaker('gelf').call(gelf_send, http_gelf_message(dic))
+    if elastic_sink is not None:
+        elastic_sink.index('http', dic)
+    get_breaker('notifications').call(
+        notify, notifications_get, notifications_claim, subdomain, {
+            'rtype': 'HTTP',
+            'subdomain': subdomain,
+            'ip': dic['ip'],
+            'summary': f"{dic['method']} {dic.get('path', '')}"
+        })
+    # the URL is not sent out for encrypted sessions
+    if key is None:
+        summary = f"{request.method} {request.url} from {dic['ip']}"
+    else:
+        summary = f"{request.method} from {dic['ip']}"
+    get_breaker('email_alerts').call(email_alert, email_alerts_get,
+                                     email_alerts_claim, email_budget_claim,
+                                     subdomain, 'HTTP', request.full_path,
+                                     summary)
+
+
+def get_collapse_key(dic):
+    # keyed per session, a plain digest would let anyone reading the database
+    # confirm guesses at the bodies of encrypted captures
+    secret = JWT_SECRET if type(JWT_SECRET) is bytes else JWT_SECRET.encode()
+    key = hmac.new(hmac.new(secret, dic['uid'].encode(),
+                            hashlib.sha256).digest(),
+                   digestmod=hashlib.sha256)
+    for field in ('ip', 'method', 'path'):
+        key.update(dic[field].encode() + b'\x00')
+    key.update(dic['raw'])
+    return key.hexdigest()
+
+
+def find_canaries(dic, headers):
+    text = '\n'.join(list(headers.values()) + [dic['path']])
+    if dic['raw'] and len(dic['raw']) <= 1024 * 1024:
+        text += '\n' + dic['raw'].decode('utf-8', errors='replace')
+    candidates = find_canary_candidates(text)
+    if not candidates:
+        return []
+
+    # canaries are matched in every session, not just the one they came from
+    return [(owner['subdomain'],
+             [x for x in owner['values'] if x in candidates])
+            for owner in metadata_find_canaries(candidates)]
+
+
+def record_stats(dic, path, encrypted):
+    counters = [('protocol', 'HTTP'), ('hour', dic['date'] // 3600 * 3600),
+                ('ip', dic['ip'])]
+    if 'country' in dic:
+        counters.append(('country', dic['country']))
+    # paths are encrypted at rest, so they stay out of the statistics too
+    if not encrypted:
+        counters.append(('path', path))
+    stats_record(dic['uid'], counters)
+
 
 def get_subdomain_from_hostname(host):
+    if host.lower().endswith('.' + DOMAIN):
+        label = host[:-len(DOMAIN) - 1].split('.')[-1].lower()
+        if is_alias_label(label):
+            alias = aliases_get(label)
+            if alias is not None:
+                return alias['subdomain']
+
     subdomain = host[:-len(DOMAIN) - 1][-8:]
     if not subdomain or not subdomain.isalnum():
         return None
@@ -92,8 +602,280 @@ def get_subdomain_from_hostname(host):
     return subdomain.lower()
 
 
-def subdomain_response(request, subdomain):
-    log_request(request, subdomain)
+ALIAS_REGEX = '^[a-z0-9](?:[a-z0-9\\-]{1,30}[a-z0-9])$'
+
+
+def is_alias_label(label):
+    # random subdomains are exactly 8 alphanumeric characters
+    if len(label) == 8 and label.isalnum():
+        return False
+    return re.match(ALIAS_REGEX, label) is not None
+
+
+def path_routing_allowed(host):
+    if not PATH_ROUTING:
+        return False
+    if PATH_ROUTING_APEX_ONLY:
+        return host.split(':')[0].lower() == DOMAIN
+    return True
+
+
+def get_compressed(subdomain, body, encoding, language=None):
+    # compressed variants are kept next to the response, behind the digest
+    # of the body they were made from; bodies such as the server profile's
+    # 404 page change without the page file changing
+    if language is not None:
+        path = f'pages/{subdomain}@{language}.{encoding}'
+    else:
+        path = f'pages/{subdomain}.{encoding}'
+    digest = hashlib.sha256(body).digest()
+    try:
+        with open(path, 'rb') as variant:
+            if variant.read(len(digest)) == digest:
+                return variant.read()
+    except OSError:
+        pass
+
+    compressed = compress(body, encoding)
+    # other workers may read the variant while it is being written
+    try:
+        with tempfile.NamedTemporaryFile(dir='pages',
+                                         prefix='.compress-',
+                                         delete=False) as variant:
+            variant.write(digest + compressed)
+        os.replace(variant.name, path)
+    except OSError:
+        pass
+    return compressed
+
+
+def get_session_path(request, subdomain, routing):
+    # path routed sessions are served below /<subdomain>
+    if routing == 'path':
+        return request.path[len(subdomain) + 1:]
+    return request.path
+
+
+CHAIN_NAME_REGEX = '^[a-z0-9_\\-]{1,32}$'
+CHAIN_MAX_HOPS = 20
+# delayed hops are left to the client with a Refresh header
+CHAIN_MAX_DELAY = 10
+
+
+def chain_response(request, subdomain, routing):
+    path = get_session_path(request, subdomain, routing)
+    match = re.match('^/chain/([a-z0-9_\\-]{1,32})(?:/([0-9]{1,3}))?/?$',
+                     path.lower())
+    if match is None:
+        return None, None
+    name, hop = match.group(1), int(match.group(2) or 0)
+    chain = redirect_chains_get(subdomain, name)
+    # past the last hop the chain lands on the regular response
+    if chain is None or hop >= len(chain['hops']):
+        return None, None
+
+    base = get_request_scheme(request, dict(request.headers)) + '://' + \
+        request.host
+    if routing == 'path':
+        base += '/' + subdomain
+    location = chain['hops'][hop]['location'].replace(
+        '{next}', f'{base}/chain/{name}/{hop + 1}').replace(
+            '{base}', base).replace('{hop}', str(hop))
+
+    resp = make_response('')
+    apply_identity(resp, g.server_headers)
+    resp.headers['Cache-Control'] = 'no-store'
+    delay = chain['hops'][hop]['delay']
+    if delay:
+        # sleeping here would hold a worker for every visitor of the chain
+        resp.headers['Refresh'] = f'{delay}; url={location}'
+        resp.status_code = 200
+    else:
+        resp.headers['Location'] = location
+        resp.status_code = chain['hops'][hop]['status']
+    return resp, {'name': name, 'hop': hop}
+
+
+def crawl_response(request, subdomain, routing):
+    path = get_session_path(request, subdomain, routing)
+    if request.method not in ('GET', 'HEAD') or path not in ('/robots.txt',
+                                                             '/sitemap.xml'):
+        return None, None
+    kind = 'robots' if path == '/robots.txt' else 'sitemap'
+    settings = crawl_get_settings(subdomain) or {}
+    if not settings.get(kind):
+        return None, None
+
+    prefix = '/' + subdomain if routing == 'path' else ''
+    base = get_request_scheme(request, dict(request.headers)) + '://' + \
+        request.host + prefix
+    entries = crawl_entries(settings, redirect_chains_get(subdomain))
+    if kind == 'robots':
+        resp = make_response(robots_txt(settings, entries, base, prefix))
+        resp.headers['Content-Type'] = 'text/plain'
+    else:
+        resp = make_response(sitemap_xml(settings, entries, base))
+        resp.headers['Content-Type'] = 'application/xml'
+    apply_identity(resp, g.server_headers)
+    return resp, kind
+
+
+S3_MAX_OBJECT_SIZE = 5 * 1024 * 1024
+S3_MAX_OBJECTS = 100
+S3_MAX_KEYS = 1000
+
+
+def s3_xml_response(body, status_code):
+    resp = make_response(body, status_code)
+    resp.headers['Content-Type'] = 'application/xml'
+    return resp
+
+
+def s3_response(request, subdomain, routing, encrypted):
+    path = get_session_path(request, subdomain, routing)
+    key = path.lstrip('/')
+
+    if key == '' and request.method in ('GET', 'HEAD'):
+        prefix = request.args.get('prefix', '')
+        objects = s3_list_objects(subdomain, prefix, S3_MAX_KEYS)
+        resp = s3_xml_response(
+            list_bucket_xml(subdomain, prefix, objects, S3_MAX_KEYS,
+                            request.args.get('list-type') == '2'), 200)
+        operation = 'ListObjects'
+    elif key == '':
+        resp = s3_xml_response(
+            error_xml('MethodNotAllowed',
+                      'The specified method is not allowed', path), 405)
+        operation = request.method
+    elif request.method == 'PUT':
+        data = request.get_data()
+        if len(data) > S3_MAX_OBJECT_SIZE:
+            resp = s3_xml_response(
+                error_xml('EntityTooLarge',
+                          'Your proposed upload exceeds the maximum',
+                          path), 400)
+        elif s3_get_object(subdomain, key) is None and s3_count_objects(
+                subdomain) >= S3_MAX_OBJECTS:
+            resp = s3_xml_response(
+                error_xml('TooManyObjects', 'The bucket is full', path), 400)
+        elif encrypted:
+            # the body is kept in the encrypted capture, the server can't
+            # store it in the clear nor serve it back without the passphrase
+            resp = make_response('')
+            resp.headers['ETag'] = f'"{hashlib.md5(data).hexdigest()}"'
+        else:
+            etag = hashlib.md5(data).hexdigest()
+            s3_put_object(
+                subdomain, key, data,
+                request.headers.get('Content-Type',
+                                    'application/octet-stream'), etag)
+            resp = make_response('')
+            resp.headers['ETag'] = f'"{etag}"'
+        operation = 'PutObject'
+    elif request.method in ('GET', 'HEAD'):
+        obj = s3_get_object(subdomain, key)
+        if obj is None:
+            resp = s3_xml_response(
+                error_xml('NoSuchKey', 'The specified key does not exist.',
+                          path), 404)
+        else:
+            resp = make_response(obj['data'])
+            resp.headers['Content-Type'] = obj['content_type']
+            resp.headers['ETag'] = f'"{obj["etag"]}"'
+            resp.headers['Last-Modified'] = http_date(obj['last_modified'])
+        operation = 'GetObject' if request.method == 'GET' else 'HeadObject'
+    elif request.method == 'DELETE':
+        s3_delete_object(subdomain, key)
+        resp = make_response('', 204)
+        operation = 'DeleteObject'
+    else:
+        resp = s3_xml_response(
+            error_xml('MethodNotAllowed',
+                      'The specified method is not allowed', path), 405)
+        operation = request.method
+
+    resp.headers['server'] = 'AmazonS3'
+    resp.headers['x-amz-request-id'] = os.urandom(8).hex().upper()
+    return resp, {'operation': operation, 'key': key}
+
+
+def content_disposition(path):
+    filename = path.rstrip('/').split('/')[-1]
+    filename = re.sub('[\\x00-\\x1f\\x7f"\\\\]', '', filename) or 'download'
+    ascii_filename = filename.encode('ascii', 'replace').decode().replace(
+        '?', '_')
+    return f'attachment; filename="{ascii_filename}"; ' + \
+        f"filename*=UTF-8''{urllib.parse.quote(filename)}"
+
+
+def operator_blocklist():
+    return {
+        'networks': setting('BLOCKLIST_NETWORKS'),
+        'countries': [x.upper() for x in setting('BLOCKLIST_COUNTRIES')],
+        'action': setting('BLOCKLIST_ACTION')
+    }
+
+
+def get_server_identity(subdomain):
+    instance = {
+        'profile': setting('SERVER_PROFILE'),
+        'server': setting('SERVER_HEADER')
+    }
+    session = (get_breaker('session_settings').call(
+        cloak_get_settings, subdomain) or {}) if subdomain else {}
+    return server_identity(instance, session)
+
+
+def subdomain_response(request, subdomain, routing):
+    start = time.perf_counter()
+    request.get_data()
+    body_read = time.perf_counter()
+    g.server_headers, not_found = get_server_identity(subdomain)
+
+    country = request.headers.get(
+        'Requestrepo-X-Country') if from_trusted_proxy(request) else None
+    g.block_action = block_action(get_client_ip(request), country,
+                                  operator_blocklist(),
+                                  get_breaker('session_settings').call(
+                                      blocklist_get, subdomain))
+    if g.block_action == 'drop':
+        resp = make_response('', 403)
+        apply_identity(resp, g.server_headers)
+        return resp
+
+    resp, chain = chain_response(request, subdomain, routing)
+    if resp is not None:
+        store_request(request, subdomain, routing, chain=chain)
+        return resp
+
+    resp, crawl = crawl_response(request, subdomain, routing)
+    if resp is not None:
+        store_request(request, subdomain, routing, crawl=crawl)
+        return resp
+
+    metadata = get_breaker('session_settings').call(metadata_get_settings,
+                                                    subdomain) or {}
+    if metadata.get('enabled'):
+        path = get_session_path(request, subdomain, routing)
+        served = metadata_response(path, request.method, request.headers,
+                                   metadata)
+        if served is not None:
+            resp = make_response(served[0])
+            resp.headers['Content-Type'] = served[1]
+            if path.startswith('/latest'):
+                resp.headers['server'] = 'EC2ws'
+            else:
+                apply_identity(resp, g.server_headers)
+            store_request(request, subdomain, routing, metadata=True)
+            return resp
+
+    if (get_breaker('session_settings').call(s3_get_settings, subdomain)
+            or {}).get('enabled'):
+        resp, s3 = s3_response(request, subdomain, routing,
+                               encryption_get_key(subdomain) is not None)
+        store_request(request, subdomain, routing, s3=s3)
+        return resp
+
     data = {'raw': '', 'headers': [], 'status_code': 200}
     if not os.path.exists('pages/' + subdomain):
         write_basic_file(subdomain)
@@ -102,15 +884,107 @@ def subdomain_response(request, subdomain):
             data = json.load(json_file)
         except:
             pass
+    # a path with a response of its own, every other path gets the catch-all
+    # response of the file and its language variants
+    path = get_session_path(request, subdomain, routing)
+    if path in (data.get('paths') or {}):
+        data = dict(data, variants={}, **data['paths'][path])
+    # the language variant replaces the body, headers and status of the file
+    variants = data.get('variants') or {}
+    language = None
+    if variants:
+        language = negotiate_language(
+            request.headers.get('Accept-Language', ''), variants)
+        if language is not None:
+            data = dict(data, **variants[language])
     try:
         resp = make_response(base64.b64decode(data['raw']))
     except:
         resp = make_response('')
-    resp.headers['server'] = 'requestrepo.com'
+    apply_identity(resp, g.server_headers)
     if 'headers' in data:
         for header in data['headers']:
             resp.headers[header['header']] = header['value']
     resp.status_code = data['status_code']
+    # an empty 404 looks like the missing file page of the impersonated server
+    if resp.status_code == 404 and not resp.get_data():
+        resp.set_data(not_found)
+        if 'Content-Type' not in resp.headers:
+            resp.headers['Content-Type'] = 'text/html'
+    # headers set by the user take precedence over the toggles
+    if data.get('nosniff') and 'X-Content-Type-Options' not in resp.headers:
+        resp.headers['X-Content-Type-Options'] = 'nosniff'
+    if data.get('download') and 'Content-Disposition' not in resp.headers:
+        resp.headers['Content-Disposition'] = content_disposition(
+            get_session_path(request, subdomain, routing))
+    if variants:
+        resp.vary.add('Accept-Language')
+        if language is not None and 'Content-Language' not in resp.headers:
+            resp.headers['Content-Language'] = language
+
+    encoding = None
+    if data.get('compress', True) and resp.get_data() and (
+            'Content-Encoding' not in resp.headers):
+        encoding = negotiate_encoding(request.headers.get('Accept-Encoding', ''))
+        if encoding is not None:
+            resp.set_data(get_compressed(subdomain, resp.get_data(),
+                                         encoding, language))
+            resp.headers['Content-Encoding'] = encoding
+            resp.vary.add('Accept-Encoding')
+
+    served = time.perf_counter()
+    timing = {
+        'read_body_ms': round((body_read - start) * 1000, 3),
+        'serve_ms': round((served - body_read) * 1000, 3),
+        'total_ms': round((served - start) * 1000, 3)
+    }
+
+    store_request(request, subdomain, routing, encoding, timing,
+                  language=language)
+    return resp
+
+
+def snapshot_request(request):
+    # a copy of the request that outlives it, the body is already read
+    environ = dict(request.environ)
+    environ.pop('werkzeug.request', None)
+    environ['wsgi.input'] = io.BytesIO(request.get_data())
+    environ['wsgi.input_terminated'] = True
+    return Request(environ)
+
+
+def store_request(request, subdomain, *args, **kwargs):
+    if g.get('block_action') == 'discard':
+        return
+    # stored in the background, so a slow database or sink never holds up
+    # the response
+    ingest_queue.submit(subdomain, store_capture, snapshot_request(request),
+                        subdomain, time.time(), *args, **kwargs)
+
+
+def store_capture(request, subdomain, received, *args, **kwargs):
+    # the failure goes to the error feed, the response is long gone
+    try:
+        log_request(request, subdomain, *args, received=received, **kwargs)
+    except Exception as ex:
+        log.error(f'storing request failed: {ex}')
+        # with mongo down the error feed is unreachable as well
+        get_breaker('errors').call(errors_insert, subdomain, 'http', 'store',
+                                   str(ex))
+
+
+@app.errorhandler(404)
+def not_found_response(error):
+    resp = make_response(get_server_identity(None)[1], 404)
+    resp.headers['Content-Type'] = 'text/html'
+    return resp
+
+
+@app.after_request
+def add_server_header(resp):
+    # captures already carry the identity of their session
+    if 'Server' not in resp.headers:
+        apply_identity(resp, get_server_identity(None)[0])
     return resp
 
 
@@ -124,8 +998,9 @@ def index():
 @check_subdomain
 def catch_all(path):
     subdomain = request.path[1:8 + 1].lower()
-    if len(subdomain) == 8 and subdomain.isalnum():
-        return subdomain_response(request, subdomain)
+    if len(subdomain) == 8 and subdomain.isalnum() and path_routing_allowed(
+            request.host):
+        return subdomain_response(request, subdomain, 'path')
 
     response = send_from_directory('public', path, as_attachment=False)
 
@@ -142,7 +1017,10 @@ def get_dns_requests():
     if not subdomain:
         return jsonify({'error': 'Unauthorized'}), 401
 
-    return jsonify(dns_get_subdomain(subdomain, time))
+    dns_requests = dns_get_subdomain(subdomain, time)
+    if not decrypt_requests(request, subdomain, dns_requests):
+        return jsonify({'error': 'Invalid passphrase'}), 401
+    return jsonify(dns_requests)
 
 
 @app.route('/api/get_http_requests')
@@ -155,7 +1033,14 @@ def get_http_requests():
     if not subdomain:
         return jsonify({'error': 'Unauthorized'}), 401
 
-    return jsonify(http_get_subdomain(subdomain, time))
+    http_requests = http_get_subdomain(subdomain, time,
+                                       request.args.get('trace_id'))
+    if not decrypt_requests(request, subdomain, http_requests):
+        return jsonify({'error': 'Invalid passphrase'}), 401
+    if request.args.get('sort') == 'bot_score':
+        http_requests.sort(key=lambda x: x.get('bot_score') or 0,
+                           reverse=True)
+    return jsonify(http_requests)
 
 
 @app.route('/api/get_requests')
@@ -168,8 +1053,12 @@ def get_requests():
     time = request.args.get('t')
     if type(time) == str and time.isdigit():
         time = int(time)
-    http_requests = http_get_subdomain(subdomain, time)
-    dns_requests = dns_get_subdomain(subdomain, time)
+    trace_id = request.args.get('trace_id')
+    http_requests = http_get_subdomain(subdomain, time, trace_id)
+    # DNS requests carry no trace context
+    dns_requests = dns_get_subdomain(subdomain, time) if not trace_id else []
+    if not decrypt_requests(request, subdomain, http_requests + dns_requests):
+        return jsonify({'error': 'Invalid passphrase'}), 401
     server_time = int(datetime.datetime.now(datetime.timezone.utc).timestamp())
     return jsonify({
         'http': http_requests,
@@ -178,167 +1067,2503 @@ def get_requests():
     })
 
 
-@app.route('/api/get_token', methods=['POST', 'OPTIONS'])
+@app.route('/api/admin/enrichment')
 @check_subdomain
-def get_token():
-    if request.method == 'OPTIONS':
-        return 'POST'
+def get_enrichment_status():
+    if not is_admin(request):
+        return jsonify({'error': 'Unauthorized'}), 401
 
-    subdomain = get_random_subdomain()
-    while users_get_subdomain(subdomain) != None:
-        subdomain = get_random_subdomain()
+    return jsonify(breakers_status())
 
-    dns_delete_records(subdomain)
-    write_basic_file(subdomain)
 
-    payload = {
-        'iat': datetime.datetime.utcnow(),
-        'exp': datetime.datetime.utcnow() + datetime.timedelta(days=31),
-        'subdomain': subdomain
-    }
-    token = jwt.encode(payload, JWT_SECRET, algorithm='HS256')
-    resp = make_response(token)
-    resp.set_cookie('token', token)
+@app.route('/api/admin/ingest')
+@check_subdomain
+def get_ingest_status():
+    if not is_admin(request):
+        return jsonify({'error': 'Unauthorized'}), 401
 
-    return resp
+    return jsonify(ingest_queue.status())
 
 
-@app.route('/api/get_server_time')
+LOG_OVERRIDE_DEFAULT_TTL = 3600
+LOG_OVERRIDE_MAX_TTL = 24 * 3600
+
+
+@app.route('/api/admin/logging', methods=['GET', 'POST', 'DELETE'])
 @check_subdomain
-def get_server_time():
+def admin_logging():
+    if not is_admin(request):
+        return jsonify({'error': 'Unauthorized'}), 401
+
+    if request.method == 'POST':
+        content = request.get_json(silent=True) or {}
+        levels = content.get('levels')
+        if type(levels) is not dict or not all(
+                type(k) is str and re.match('^[A-Za-z0-9_.]{1,64}$', k) and
+                type(v) is str and v.upper() in LEVELS
+                for k, v in levels.items()):
+            return jsonify({'error': 'Invalid levels'}), 401
+        ttl = content.get('ttl', LOG_OVERRIDE_DEFAULT_TTL)
+        if type(ttl) is not int or not 1 <= ttl <= LOG_OVERRIDE_MAX_TTL:
+            return jsonify({
+                'error':
+                f'ttl should be between 1 and {LOG_OVERRIDE_MAX_TTL} seconds'
+            }), 401
+        # every backend worker and the DNS server pick these up within seconds
+        log_levels_set({k: v.upper() for k, v in levels.items()}, ttl)
+    elif request.method == 'DELETE':
+        log_levels_delete()
+
+    overrides = log_levels_get()
     return jsonify({
-        'date':
-        int(datetime.datetime.now(datetime.timezone.utc).timestamp())
+        'format': LOG_FORMAT,
+        'level': LOG_LEVEL,
+        'configured': configured_log_levels,
+        'overrides': get_log_overrides(),
+        'expires': overrides['_purge_at'].isoformat() if overrides else None
     })
 
 
-@app.route('/api/delete_request', methods=['POST'])
+@app.route('/api/admin/settings')
 @check_subdomain
-def delete_request():
-    subdomain = verify_jwt(request.cookies.get('token'))
-    if not subdomain:
-        return jsonify({"error": "Unauthorized"}), 401
+def get_instance_settings():
+    if not is_admin(request):
+        return jsonify({'error': 'Unauthorized'}), 401
 
-    content = request.json
-    if content:
-        _id = content.get('id')
-        rtype = content.get('type')
-        delete_request_from_db(_id, subdomain, rtype)
-        return jsonify({"rtype": rtype, "_id": _id})
+    return jsonify(settings_status())
 
 
-@app.route('/api/get_file', methods=['GET'])
+STORAGE_STATS_INTERVAL = 300
+STORAGE_TOP_SUBDOMAINS = 20
+storage_stats = {}
+
+
+@app.route('/api/admin/storage')
 @check_subdomain
-def get_file():
-    subdomain = verify_jwt(request.cookies.get('token'))
-    if not subdomain:
-        return jsonify({"raw": "", "headers": [], "status_code": 200})
+def get_storage_stats():
+    global storage_stats
+    if not is_admin(request):
+        return jsonify({'error': 'Unauthorized'}), 401
 
-    if not os.path.exists('pages/' + subdomain):
-        write_basic_file(subdomain)
+    # the per-subdomain sizes scan every capture, so they are kept a while
+    now = int(time.time())
+    if not storage_stats or now - storage_stats[
+            'date'] > STORAGE_STATS_INTERVAL or request.args.get(
+                'refresh') == 'true':
+        collections = storage_collections()
+        storage_stats = {
+            'date': now,
+            'collections': collections,
+            'total': {
+                key: sum(x[key] for x in collections)
+                for key in ('documents', 'size', 'storage_size', 'index_size')
+            },
+            'largest_subdomains':
+            storage_largest_subdomains(STORAGE_TOP_SUBDOMAINS)
+        }
+    return jsonify(storage_stats)
+
+
+@app.route('/api/admin/dnssec')
+@check_subdomain
+def get_dnssec_keys():
+    if not is_admin(request):
+        return jsonify({'error': 'Unauthorized'}), 401
 
-    with open('pages/' + subdomain, 'r') as outfile:
-        return outfile.read()
+    material = dnssec_get()
+    if material is None:
+        return jsonify({'error': 'DNSSEC is not enabled'}), 401
+    return jsonify(material)
 
 
-@app.route('/api/update_file', methods=['POST'])
+@app.route('/api/admin/settings/reload', methods=['POST'])
 @check_subdomain
-def update_file():
-    subdomain = verify_jwt(request.cookies.get('token'))
-    if subdomain:
-        content = request.json
-        status_code = 200
-        if 'status_code' in content:
-            try:
-                try:
-                    if len(content['status_code']) > 9:
-                        return jsonify({"error": "invalid status_code"}), 401
-                    status_code = int(content['status_code'])
-                except:
-                    pass
-            except:
-                return jsonify({"error": "invalid status_code"}), 401
-        raw = ""
-        if 'raw' in content:
-            if len(content['raw']) <= 2000000:
-                try:
-                    base64.b64decode(content['raw'])
-                    raw = content['raw']
-                except:
-                    return jsonify({"error": "invalid response"}), 401
-            else:
-                return jsonify(
-                    {"error": "response should be smaller than 2MB"}), 401
-        headers = []
-        if 'headers' in content:
-            if len(headers) <= 30:
-                for header in content['headers']:
-                    if 'header' in header and 'value' in header:
-                        headers.append({
-                            'header': header['header'],
-                            'value': header['value']
-                        })
-            else:
-                return jsonify({"error": "maximum of 30 headers"}), 401
-            with open('pages/' + subdomain, 'w') as outfile:
-                json.dump(
-                    {
-                        'headers': headers,
-                        'raw': raw,
-                        'status_code': status_code
-                    }, outfile)
-        return jsonify({"msg": "Updated response"})
-    return jsonify({"error": "Unauthorized"}), 401
+def reload_instance_settings():
+    if not is_admin(request):
+        return jsonify({'error': 'Unauthorized'}), 401
 
+    # other workers pick the file up on their own within a few seconds
+    try:
+        changed = reload_settings()
+    except (OSError, ValueError) as ex:
+        return jsonify({'error': f'Invalid settings: {ex}'}), 401
+    return jsonify({'changed': changed})
 
-@app.route('/api/get_dns_records', methods=['GET'])
-@check_subdomain
-def get_dns_records():
-    subdomain = verify_jwt(request.cookies.get('token'))
-    if subdomain:
-        return jsonify(dns_get_records(subdomain))
-    return jsonify({"error": "Unauthorized"}), 401
 
+@app.route('/api/admin/errors')
+@check_subdomain
+def get_instance_errors():
+    if not is_admin(request):
+        return jsonify({'error': 'Unauthorized'}), 401
 
-DNS_RECORDS = ['A', 'AAAA', 'CNAME', 'TXT']
+    time = request.args.get('t')
+    if type(time) == str and time.isdigit():
+        time = int(time)
+    else:
+        time = None
+    return jsonify(errors_get(None, time))
 
 
-@app.route('/api/update_dns_records', methods=['POST'])
+@app.route('/api/v2/errors')
 @check_subdomain
-def update_dns_records():
-    subdomain = verify_jwt(request.cookies.get('token'))
+def get_errors():
+    subdomain = verify_jwt(get_request_token(request))
     if not subdomain:
-        return jsonify({"error": "unauthenticated"}), 401
+        return jsonify({'error': 'Unauthorized'}), 401
 
-    dns_delete_records(subdomain)
-    content = request.json
+    time = request.args.get('t')
+    if type(time) == str and time.isdigit():
+        time = int(time)
+    else:
+        time = None
+    return jsonify(errors_get(subdomain, time))
 
-    if 'records' not in content:
-        return jsonify({"error": "Invalid records"}), 401
 
-    for record in content['records']:
-        if type(record) is not dict:
-            continue
+# how often the event streams poll for changes, and send keep-alives
+EVENTS_POLL_INTERVAL = 1
+EVENTS_KEEPALIVE_INTERVAL = 15
+# streams end after this long, clients reconnect from the id of the last poll
+EVENTS_MAX_DURATION = 25
+# tickets stand in for tokens in query strings, which end up in access logs
+EVENTS_TICKET_SECONDS = 60
 
-        domain = record.get('domain')
-        dtype = record.get('type')
-        value = record.get('value')
 
-        if domain is None or dtype is None or value is None:
-            continue
-        if domain == "" or value == "":
-            continue
+def sse_event(event, data):
+    return f'event: {event}\ndata: {json.dumps(data)}\n\n'
 
-        domain = domain.lower()
 
-        if len(domain) > 63:
-            return jsonify({"error": "Domain too big"}), 401
+def verify_ticket(ticket):
+    try:
+        subdomains = jwt.decode(ticket, JWT_SECRET,
+                                algorithms=['HS256'])['events']
+        if all(session_allowed(x) for x in subdomains):
+            return subdomains
+    except Exception:
+        pass
+    return None
 
-        if len(value) > 255:
-            return jsonify({"error": "Value too big"}), 401
 
-        if type(dtype) is not int:
-            return jsonify({"error": "Invalid type"}), 401
+def get_stream_subdomains(request):
+    ticket = request.args.get('ticket')
+    if ticket:
+        return verify_ticket(ticket)
+    subdomain = verify_jwt(get_request_token(request))
+    return [subdomain] if subdomain else None
+
+
+@app.route('/api/v2/events/ticket', methods=['POST'])
+@check_subdomain
+def create_events_ticket():
+    # several sessions at once, their tokens go in the body
+    content = request.get_json(silent=True) or {}
+    tokens = content.get('tokens')
+    if tokens is None:
+        tokens = [get_request_token(request)]
+    if type(tokens) is not list or not tokens or len(tokens) > setting(
+            'EVENTS_MAX_SUBSCRIPTIONS'):
+        return jsonify({'error': 'Invalid tokens'}), 400
+
+    subdomains = []
+    for token in tokens:
+        subdomain = verify_jwt(token) if type(token) is str else None
+        if not subdomain:
+            return jsonify({'error': 'Unauthorized'}), 401
+        if subdomain not in subdomains:
+            subdomains.append(subdomain)
+
+    expires = int(time.time()) + EVENTS_TICKET_SECONDS
+    payload = {'exp': expires, 'events': subdomains}
+    ticket = jwt.encode(payload, JWT_SECRET, algorithm='HS256')
+    return jsonify({'ticket': ticket, 'expires': expires})
+
+
+def collect_events(subdomain, since):
+    # keys are the same for an event that hasn't changed between polls
+    for rtype, captures in (('HTTP', http_get_subdomain(subdomain, since)),
+                            ('DNS', dns_get_subdomain(subdomain, since))):
+        for x in captures:
+            yield ('new_request', x['_id']), 'new_request', {
+                'subdomain': subdomain,
+                'rtype': rtype,
+                'request': x
+            }
+    for x in get_annotated_since(subdomain, since):
+        yield ('annotation', x['_id'], len(x['annotations'])), 'annotation', {
+            'subdomain': subdomain,
+            'rtype': x['type'],
+            '_id': x['_id'],
+            'annotations': x['annotations']
+        }
+    for x in http_get_collapsed_since(subdomain, since):
+        yield ('request_count', x['_id'],
+               x['count']), 'request_count', dict(x, subdomain=subdomain)
+    for x in uploads_get_updated_since(subdomain, since):
+        status = upload_status(x)
+        yield ('upload_progress', x['upload_id'], status['bytes_received']
+               ), 'upload_progress', dict(status, subdomain=subdomain)
+    for x in errors_get(subdomain, since):
+        yield ('capture_error', x['_id']), 'capture_error', x
+    for x in get_deleted_since(subdomain, since):
+        yield ('delete', x['_id']), 'delete', {
+            'subdomain': subdomain,
+            'rtype': x['type'],
+            '_id': x['_id']
+        }
+
+
+event_hub = EventHub(collect_events, EVENTS_POLL_INTERVAL)
+
+
+@app.route('/api/v2/events')
+@check_subdomain
+def get_events():
+    subdomains = get_stream_subdomains(request)
+    if not subdomains:
+        return jsonify({'error': 'Unauthorized'}), 401
+
+    # EventSource sends the id of the last poll back when it reconnects
+    since = request.headers.get('Last-Event-ID') or request.args.get('t')
+    if type(since) == str and since.isdigit():
+        since = int(since)
+    else:
+        since = int(datetime.datetime.now(datetime.timezone.utc).timestamp())
+
+    def generate():
+        # subscribed before catching up, so nothing falls in between
+        stream = event_hub.subscribe(subdomains)
+        try:
+            sent = set()
+            for subdomain in subdomains:
+                for key, event, data in collect_events(subdomain, since):
+                    sent.add(key)
+                    yield sse_event(event, data)
+
+            started = time.time()
+            while time.time() - started < EVENTS_MAX_DURATION:
+                try:
+                    key, event, data = stream.get(
+                        timeout=EVENTS_KEEPALIVE_INTERVAL)
+                except queue.Empty:
+                    yield ': keep-alive\n\n'
+                    continue
+                if key not in sent:
+                    sent.add(key)
+                    yield sse_event(event, data)
+
+            # what the hub queued before its cursor moved is still sent
+            last = event_hub.cursor(subdomains)
+            while not stream.empty():
+                key, event, data = stream.get_nowait()
+                if key not in sent:
+                    sent.add(key)
+                    yield sse_event(event, data)
+        finally:
+            event_hub.unsubscribe(subdomains, stream)
+
+        # captures in the cursor's second can be sent again after reconnecting
+        yield f'id: {last}\nretry: 1000\n\n'
+
+    resp = Response(stream_with_context(generate()),
+                    mimetype='text/event-stream')
+    resp.headers['Cache-Control'] = 'no-cache'
+    resp.headers['X-Accel-Buffering'] = 'no'
+    return resp
+
+
+def get_fetch_filters(args, rtype):
+    filters = {}
+    date = {}
+    for arg, op in (('since', '$gte'), ('until', '$lte')):
+        value = args.get(arg)
+        if value is not None:
+            if not value.isdigit():
+                return None
+            date[op] = int(value)
+    if date:
+        filters['date'] = date
+    if args.get('ip'):
+        filters['ip'] = args['ip']
+    if args.get('include_management', 'false').lower() != 'true':
+        filters['management'] = {'$ne': True}
+
+    if rtype == 'HTTP':
+        if args.get('method'):
+            filters['method'] = args['method'].upper()
+        if args.get('path_contains'):
+            filters['path'] = {'$regex': re.escape(args['path_contains'])}
+    else:
+        if args.get('qtype'):
+            filters['type'] = args['qtype'].upper()
+        if args.get('name_contains'):
+            filters['name'] = {
+                '$regex': re.escape(args['name_contains'].lower())
+            }
+    return filters
+
+
+@app.route('/api/v2/requests')
+@check_subdomain
+def fetch_requests():
+    # same auth as /api/v2/events, so history and live updates share tokens
+    subdomains = get_stream_subdomains(request)
+    if not subdomains:
+        return jsonify({'error': 'Unauthorized'}), 401
+
+    limit = request.args.get('limit', str(setting('FETCH_DEFAULT_LIMIT')))
+    if not limit.isdigit() or not 1 <= int(limit) <= setting(
+            'FETCH_MAX_LIMIT'):
+        return jsonify({'error': 'Invalid limit'}), 401
+    limit = int(limit)
+
+    rtypes = ['HTTP', 'DNS']
+    if request.args.get('rtype'):
+        if request.args['rtype'].upper() not in rtypes:
+            return jsonify({'error': 'Invalid rtype'}), 401
+        rtypes = [request.args['rtype'].upper()]
+
+    captures = []
+    for rtype in rtypes:
+        filters = get_fetch_filters(request.args, rtype)
+        if filters is None:
+            return jsonify({'error': 'Invalid date'}), 401
+        for subdomain in subdomains:
+            found = requests_search(subdomain, rtype, filters, limit)
+            if not decrypt_requests(request, subdomain, found):
+                return jsonify({'error': 'Invalid passphrase'}), 401
+            captures += [{
+                'subdomain': subdomain,
+                'rtype': rtype,
+                'request': x
+            } for x in found]
+
+    captures.sort(key=lambda x: x['request']['date'], reverse=True)
+    return jsonify({'requests': captures[:limit]})
+
+
+@app.route('/api/v2/requests/sync')
+@check_subdomain
+def sync_requests():
+    subdomains = get_stream_subdomains(request)
+    if not subdomains:
+        return jsonify({'error': 'Unauthorized'}), 401
+
+    since_id = request.args.get('since_id')
+    if since_id is not None and not ObjectId.is_valid(since_id):
+        return jsonify({'error': 'Invalid since_id'}), 401
+    since_id = ObjectId(since_id) if since_id else None
+
+    limit = request.args.get('limit', str(setting('FETCH_DEFAULT_LIMIT')))
+    if not limit.isdigit() or not 1 <= int(limit) <= setting(
+            'FETCH_MAX_LIMIT'):
+        return jsonify({'error': 'Invalid limit'}), 401
+    limit = int(limit)
+
+    server_time = datetime.datetime.now(datetime.timezone.utc).replace(
+        microsecond=0)
+    created = []
+    changed = []
+    deleted = []
+    for subdomain in subdomains:
+        found_created, found_changed = requests_sync(subdomain, since_id,
+                                                     limit + 1)
+        found = [x for _, x in found_created + found_changed]
+        if not decrypt_requests(request, subdomain, found):
+            return jsonify({'error': 'Invalid passphrase'}), 401
+        created += [{
+            'subdomain': subdomain,
+            'rtype': rtype,
+            'request': x
+        } for rtype, x in found_created]
+        changed += [{
+            'subdomain': subdomain,
+            'rtype': rtype,
+            'request': x
+        } for rtype, x in found_changed]
+        if since_id is not None:
+            since = int(since_id.generation_time.timestamp())
+            deleted += [{
+                'subdomain': subdomain,
+                'rtype': x['type'],
+                '_id': x['_id']
+            } for x in get_deleted_since(subdomain, since)]
+
+    # ids grow with time, so the cursor is the last id handed out when the
+    # page is full and the current second otherwise; a few captures and
+    # changes from the same second can come again, clients upsert by id
+    created.sort(key=lambda x: x['request']['_id'])
+    more = len(created) > limit
+    created = created[:limit]
+    if more:
+        cursor = created[-1]['request']['_id']
+    else:
+        cursor = str(ObjectId.from_datetime(server_time))
+
+    return jsonify({
+        'cursor': cursor,
+        'more': more,
+        'created': created,
+        'changed': changed,
+        'deleted': deleted
+    })
+
+
+# pauses at least this long are reported as gaps between bursts of activity
+TIMELINE_GAP_SECONDS = 300
+
+
+@app.route('/api/v2/timeline')
+@check_subdomain
+def get_timeline():
+    subdomains = get_stream_subdomains(request)
+    if not subdomains:
+        return jsonify({'error': 'Unauthorized'}), 401
+
+    ip = request.args.get('ip', '')
+    try:
+        ipaddress.ip_address(ip)
+    except ValueError:
+        return jsonify({'error': 'Invalid ip'}), 401
+
+    gap = request.args.get('gap', str(TIMELINE_GAP_SECONDS))
+    if not gap.isdigit() or int(gap) < 1:
+        return jsonify({'error': 'Invalid gap'}), 401
+    gap = int(gap)
+
+    limit = setting('FETCH_MAX_LIMIT')
+    captures = []
+    for rtype in ('HTTP', 'DNS'):
+        filters = get_fetch_filters(request.args, rtype)
+        if filters is None:
+            return jsonify({'error': 'Invalid date'}), 401
+        for subdomain in subdomains:
+            found = requests_search(subdomain, rtype, filters, limit)
+            if not decrypt_requests(request, subdomain, found):
+                return jsonify({'error': 'Invalid passphrase'}), 401
+            captures += [{
+                'subdomain': subdomain,
+                'rtype': rtype,
+                'request': x
+            } for x in found]
+
+    # the most recent captures, replayed in the order they arrived
+    captures.sort(key=lambda x: x['request']['date'], reverse=True)
+    captures = captures[:limit][::-1]
+
+    gaps = []
+    for i, capture in enumerate(captures):
+        capture['since_previous'] = None
+        if i > 0:
+            previous = captures[i - 1]['request']['date']
+            capture['since_previous'] = capture['request']['date'] - previous
+            if capture['since_previous'] >= gap:
+                gaps.append({
+                    'start': previous,
+                    'end': capture['request']['date'],
+                    'seconds': capture['since_previous']
+                })
+
+    return jsonify({
+        'ip': ip,
+        'first': captures[0]['request']['date'] if captures else None,
+        'last': captures[-1]['request']['date'] if captures else None,
+        'gaps': gaps,
+        'timeline': captures
+    })
+
+
+@app.route('/api/v2/payloads')
+@check_subdomain
+def get_payloads():
+    subdomain = verify_jwt(get_request_token(request))
+    if not subdomain:
+        return jsonify({'error': 'Unauthorized'}), 401
+
+    kind = request.args.get('kind')
+    if kind not in PAYLOAD_TEMPLATES:
+        return jsonify({'error': 'Invalid kind'}), 401
+
+    fmt = request.args.get('format', 'json')
+    if fmt not in ('raw', 'json'):
+        return jsonify({'error': 'Invalid format'}), 401
+
+    note = request.args.get('note', '')[:256]
+
+    cid = get_random_correlation_id()
+    while payloads_get(cid) is not None:
+        cid = get_random_correlation_id()
+    payloads_insert(subdomain, cid, kind, note)
+
+    rendered = render_payloads(kind, cid, f'{subdomain}.{DOMAIN}')
+    if fmt == 'raw':
+        resp = make_response('\n'.join(rendered) + '\n')
+        resp.headers['Content-Type'] = 'text/plain'
+        return resp
+
+    return jsonify({
+        'kind': kind,
+        'correlation_id': cid,
+        'payloads': rendered
+    })
+
+
+LABELS_MAX_COUNT = 1000
+
+
+@app.route('/api/v2/tools/labels', methods=['POST'])
+@check_subdomain
+def issue_labels():
+    subdomain = verify_jwt(get_request_token(request))
+    if not subdomain:
+        return jsonify({'error': 'Unauthorized'}), 401
+
+    count = request.args.get('count', '1')
+    if not count.isdigit() or not 1 <= int(count) <= LABELS_MAX_COUNT:
+        return jsonify({'error': 'Invalid count'}), 401
+    count = int(count)
+    note = request.args.get('note', '')[:256]
+    style = request.args.get('style', 'random')
+    if style not in ('random', 'sequential'):
+        return jsonify({'error': 'Invalid style'}), 401
+
+    # labels are registered as correlation ids, so hits are tracked per label
+    labels = []
+    while len(labels) < count:
+        batch = set()
+        if style == 'sequential':
+            # like collaborator ids, a session prefix followed by a counter
+            prefix, start = payloads_reserve_counter(
+                subdomain, get_random_correlation_prefix(),
+                count - len(labels), CORRELATION_COUNTER_MAX)
+            for counter in range(start, start + count - len(labels)):
+                batch.add(get_sequential_correlation_id(prefix, counter))
+        while len(batch) < count - len(labels):
+            batch.add(get_random_correlation_id())
+        labels += payloads_insert_many(subdomain, list(batch), 'label', note)
+
+    return jsonify({
+        'labels': [{
+            'label': label,
+            'hostname': f'{label}.{subdomain}.{DOMAIN}'
+        } for label in labels]
+    })
+
+
+REBIND_STRATEGIES = {'alternate': '/', 'random': '%'}
+# records can carry their own TTL, the DNS server answers with 1 otherwise
+RECORD_MAX_TTL = 3600
+
+
+def valid_ipv4(value):
+    try:
+        return type(value) is str and ipaddress.ip_address(
+            value).version == 4
+    except ValueError:
+        return False
+
+
+@app.route('/api/v2/tools/rebind', methods=['POST'])
+@check_subdomain
+def rebind():
+    subdomain = verify_jwt(get_request_token(request))
+    if not subdomain:
+        return jsonify({'error': 'Unauthorized'}), 401
+
+    content = request.get_json(silent=True) or {}
+    public = content.get('public')
+    internal = content.get('internal', '127.0.0.1')
+    if not valid_ipv4(public) or not valid_ipv4(internal):
+        return jsonify({'error': 'Invalid address'}), 401
+    ttl = content.get('ttl', 0)
+    if type(ttl) is not int or not 0 <= ttl <= RECORD_MAX_TTL:
+        return jsonify(
+            {'error': f'ttl should be between 0 and {RECORD_MAX_TTL}'}), 401
+    strategy = content.get('strategy', 'alternate')
+    if strategy not in REBIND_STRATEGIES:
+        return jsonify({
+            'error':
+            f'strategy should be one of {", ".join(REBIND_STRATEGIES)}'
+        }), 401
+    label = content.get('label') or 'rebind-' + get_random_correlation_id()
+    if type(label) is not str or not re.match(
+            '^[a-z0-9](?:[a-z0-9\\-]{0,61}[a-z0-9])?$', label):
+        return jsonify({'error': 'Invalid label'}), 401
+
+    # alternate rotates the two addresses on every query, random picks one;
+    # both use the A record syntax of update_dns_records
+    domain = f'{label}.{subdomain}.{DOMAIN}.'
+    dns_delete_record(subdomain, domain, 'A')
+    dns_insert_record(subdomain, domain, 'A',
+                      REBIND_STRATEGIES[strategy].join([public, internal]),
+                      ttl)
+    return jsonify({
+        'hostname': domain.rstrip('.'),
+        'public': public,
+        'internal': internal,
+        'ttl': ttl,
+        'strategy': strategy
+    })
+
+
+# host:port of the HTTP front end (nginx) the self-test sends its request to
+SELFTEST_HTTP_ADDRESS = os.getenv('SELFTEST_HTTP_ADDRESS')
+
+
+@app.route('/api/v2/tools/selftest', methods=['POST'])
+@check_subdomain
+def selftest():
+    subdomain = verify_jwt(get_request_token(request))
+    if not subdomain:
+        return jsonify({'error': 'Unauthorized'}), 401
+
+    # the probes carry a fresh correlation id, their captures are found by it
+    cid = get_random_correlation_id()
+    payloads_insert(subdomain, cid, 'selftest', 'self-test')
+    hostname = f'{cid}.{subdomain}.{DOMAIN}'
+    checks = {}
+    if DNS_HEALTH_ADDRESS:
+        checks['dns'] = lambda: check_dns(hostname)
+    if SELFTEST_HTTP_ADDRESS:
+        checks['http'] = lambda: probe_http(SELFTEST_HTTP_ADDRESS,
+                                            f'{subdomain}.{DOMAIN}',
+                                            f'/selftest/{cid}')
+    _, sent = run_checks(checks)
+
+    results = {}
+    for path in ('dns', 'http', 'smtp'):
+        if path == 'smtp':
+            results[path] = {'status': 'unavailable'}
+        elif path not in checks:
+            results[path] = {'status': 'not configured'}
+        elif sent[path] != 'ok':
+            results[path] = {'status': 'send failed', 'error': sent[path]}
+        else:
+            results[path] = {'status': 'sent'}
+
+    # the probes come from the server itself, not from the internet. Holding
+    # a worker until they are captured is left to the client, which polls
+    # the hits of the correlation id
+    return jsonify({
+        'correlation_id': cid,
+        'vantage': 'loopback',
+        'hits': f'/api/v2/payloads/{cid}/hits',
+        'results': results
+    })
+
+
+@app.route('/api/v2/payloads/<cid>/hits')
+@check_subdomain
+def get_payload_hits(cid):
+    subdomain = verify_jwt(get_request_token(request))
+    if not subdomain:
+        return jsonify({'error': 'Unauthorized'}), 401
+
+    payload = payloads_get(cid.lower())
+    if payload is None or payload['subdomain'] != subdomain:
+        return jsonify({'error': 'Not found'}), 404
+
+    time = request.args.get('t')
+    if type(time) == str and time.isdigit():
+        time = int(time)
+    else:
+        time = None
+    hits = payloads_get_hits(subdomain, payload['cid'], time)
+    if not decrypt_requests(request, subdomain, hits):
+        return jsonify({'error': 'Invalid passphrase'}), 401
+
+    return jsonify({
+        'correlation_id': payload['cid'],
+        'kind': payload['kind'],
+        'note': payload['note'],
+        'created_at': payload['created_at'],
+        'first_hit': payload.get('first_hit'),
+        'last_hit': payload.get('last_hit'),
+        'hits': payload.get('hits', 0),
+        'captures': hits
+    })
+
+
+@app.route('/api/v2/payloads/<cid>', methods=['DELETE'])
+@check_subdomain
+def delete_payload(cid):
+    subdomain = verify_jwt(get_request_token(request))
+    if not subdomain:
+        return jsonify({'error': 'Unauthorized'}), 401
+
+    # past captures keep their correlation ids, new hits are no longer matched
+    if not payloads_delete(subdomain, cid.lower()):
+        return jsonify({'error': 'Not found'}), 404
+    return jsonify({'msg': 'Deregistered payload'})
+
+
+STATS_TOP_COUNT = 10
+STATS_HISTOGRAM_HOURS = 7 * 24
+
+
+@app.route('/api/v2/stats')
+@check_subdomain
+def get_stats():
+    subdomain = verify_jwt(get_request_token(request))
+    if not subdomain:
+        return jsonify({'error': 'Unauthorized'}), 401
+
+    since = (now() // 3600 - STATS_HISTOGRAM_HOURS) * 3600
+    hours = sorted((x for x in stats_get(subdomain, 'hour')
+                    if x['key'] > since),
+                   key=lambda x: x['key'])
+
+    return jsonify({
+        'protocols': {x['key']: x['count']
+                      for x in stats_get(subdomain, 'protocol')},
+        'countries': {x['key']: x['count']
+                      for x in stats_get(subdomain, 'country')},
+        'hours': [{
+            'hour': x['key'],
+            'count': x['count']
+        } for x in hours],
+        'top_paths': [{
+            'path': x['key'],
+            'count': x['count']
+        } for x in stats_get(subdomain, 'path', STATS_TOP_COUNT)],
+        'top_ips': [{
+            'ip': x['key'],
+            'count': x['count']
+        } for x in stats_get(subdomain, 'ip', STATS_TOP_COUNT)]
+    })
+
+
+@app.route('/api/v2/findings')
+@check_subdomain
+def get_findings():
+    subdomain = verify_jwt(get_request_token(request))
+    if not subdomain:
+        return jsonify({'error': 'Unauthorized'}), 401
+
+    time = request.args.get('t')
+    if type(time) == str and time.isdigit():
+        time = int(time)
+    else:
+        time = None
+
+    captures = http_get_findings(subdomain, time)
+    if not decrypt_requests(request, subdomain, captures):
+        return jsonify({'error': 'Invalid passphrase'}), 401
+
+    return jsonify([{
+        '_id': x['_id'],
+        'date': x['date'],
+        'ip': x['ip'],
+        'method': x['method'],
+        'path': x.get('path'),
+        'encrypted': 'encrypted' in x,
+        'findings': x.get('findings', [])
+    } for x in captures])
+
+
+@app.route('/api/v2/certificates', methods=['POST'])
+@check_subdomain
+def create_certificate():
+    subdomain = verify_jwt(get_request_token(request))
+    if not subdomain:
+        return jsonify({'error': 'Unauthorized'}), 401
+    if not minting_enabled():
+        return jsonify({'error': 'Certificate minting is disabled'}), 401
+
+    content = request.get_json(silent=True) or {}
+    hostname = content.get('hostname')
+    if type(hostname) is not str:
+        return jsonify({'error': 'Invalid hostname'}), 400
+    hostname = hostname.lower().rstrip('.')
+
+    suffix = f'.{subdomain}.{DOMAIN}'
+    if not hostname.endswith(suffix) or len(hostname) > 253:
+        return jsonify({'error': 'Invalid hostname'}), 400
+    labels = hostname[:-len(suffix)].split('.')
+    if not all(
+            re.fullmatch('[a-z0-9](?:[a-z0-9\\-_]{0,61}[a-z0-9])?', label)
+            for label in labels):
+        return jsonify({'error': 'Invalid hostname'}), 400
+    if len(labels) < 2:
+        return jsonify(
+            {'error': 'Hostname is covered by the wildcard certificate'}), 400
+
+    expiry = mint_limited(hostname, suffix, CERT_MAX_PER_SESSION)
+    if expiry is None:
+        return jsonify({
+            'error':
+            f'At most {CERT_MAX_PER_SESSION} certificates per session'
+        }), 429
+    return jsonify({'hostname': hostname, 'expires': int(expiry.timestamp())})
+
+
+def interactsh_authorized(request):
+    return INTERACTSH_TOKEN is None or request.headers.get(
+        'Authorization') == INTERACTSH_TOKEN
+
+
+@app.route('/register', methods=['POST'])
+@check_subdomain
+def interactsh_register_route():
+    if not INTERACTSH:
+        return catch_all('register')
+    if not interactsh_authorized(request):
+        return jsonify({'error': 'unauthorized'}), 401
+
+    content = request.get_json(silent=True) or {}
+    public_key = content.get('public-key')
+    secret = content.get('secret-key')
+    correlation_id = content.get('correlation-id')
+    if type(secret) is not str or not secret:
+        return jsonify({'error': 'invalid secret-key'}), 400
+    if type(correlation_id) is not str or len(
+            correlation_id) != 20 or not correlation_id.isalnum():
+        return jsonify({'error': 'invalid correlation-id'}), 400
+    if type(public_key) is not str or load_public_key(public_key) is None:
+        return jsonify({'error': 'invalid public-key'}), 400
+
+    if not interactsh_register(correlation_id.lower(), secret, public_key):
+        return jsonify({'error': 'correlation-id already registered'}), 400
+    return jsonify({'message': 'registration successful'})
+
+
+@app.route('/poll')
+@check_subdomain
+def interactsh_poll_route():
+    if not INTERACTSH:
+        return catch_all('poll')
+    if not interactsh_authorized(request):
+        return jsonify({'error': 'unauthorized'}), 401
+
+    correlation_id = request.args.get('id', '').lower()
+    registration = interactsh_get(correlation_id,
+                                  request.args.get('secret', ''))
+    if registration is None:
+        return jsonify({'error': 'could not get interactions'}), 400
+
+    aes_key, data = encrypt_interactions(registration['public_key'],
+                                         interactsh_poll(correlation_id))
+    return jsonify({
+        'data': data,
+        'extra': [],
+        'aes_key': aes_key,
+        'tlddata': []
+    })
+
+
+@app.route('/deregister', methods=['POST'])
+@check_subdomain
+def interactsh_deregister_route():
+    if not INTERACTSH:
+        return catch_all('deregister')
+    if not interactsh_authorized(request):
+        return jsonify({'error': 'unauthorized'}), 401
+
+    content = request.get_json(silent=True) or {}
+    if not interactsh_deregister(
+            str(content.get('correlation-id', '')).lower(),
+            content.get('secret-key')):
+        return jsonify({'error': 'could not deregister'}), 400
+    return jsonify({'message': 'deregistration successful'})
+
+
+@app.route('/api/v2/schema')
+@check_subdomain
+def get_schema():
+    return jsonify(SCHEMA)
+
+
+@app.route('/healthz')
+@check_subdomain
+def healthz():
+    return jsonify({'status': 'ok'})
+
+
+@app.route('/readyz')
+@check_subdomain
+def readyz():
+    checks = {'mongodb': ping, 'pages': check_pages}
+    if DNS_HEALTH_ADDRESS and HEALTH_DNS_NAME:
+        checks['dns'] = lambda: check_dns(HEALTH_DNS_NAME)
+
+    ready, components = run_checks_cached(checks)
+    body = {'status': 'ok' if ready else 'failing'}
+    # component details can leak topology, so only admins get them
+    if is_admin(request):
+        body['components'] = components
+    return jsonify(body), 200 if ready else 503
+
+
+def instance_info():
+    return {
+        'version': VERSION,
+        'domain': DOMAIN,
+        'services': {
+            'http': {'ports': HTTP_PORTS},
+            'https': {'ports': HTTPS_PORTS},
+            'dns': {'ports': DNS_PORTS},
+            'interactsh': INTERACTSH,
+            'certificates': minting_enabled(),
+            'email_alerts': bool(setting('SMTP_RELAY_HOST'))
+        },
+        'routing': {
+            'subdomain': True,
+            'path': PATH_ROUTING,
+            'path_apex_only': PATH_ROUTING_APEX_ONLY
+        },
+        'limits': {
+            'response_size': RESPONSE_MAX_SIZE,
+            'language_variants': MAX_VARIANTS,
+            'path_responses': PATH_RESPONSES_MAX,
+            'upload_size': setting('UPLOAD_MAX_SIZE'),
+            'upload_part_size': UPLOAD_PART_SIZE,
+            'upload_sessions': setting('UPLOAD_MAX_SESSIONS'),
+            'fetch_default_limit': setting('FETCH_DEFAULT_LIMIT'),
+            'fetch_max_limit': setting('FETCH_MAX_LIMIT'),
+            'event_subscriptions': setting('EVENTS_MAX_SUBSCRIPTIONS'),
+            'chain_hops': CHAIN_MAX_HOPS,
+            's3_object_size': S3_MAX_OBJECT_SIZE,
+            's3_objects': S3_MAX_OBJECTS
+        },
+        'retention': {
+            'trash_days': setting('TRASH_RETENTION_DAYS'),
+            'errors_days': setting('ERRORS_RETENTION_DAYS'),
+            'interactsh_days': setting('INTERACTSH_RETENTION_DAYS'),
+            'upload_hours': setting('UPLOAD_RETENTION_HOURS')
+        },
+        'features': [
+            'aliases', 'blocklist', 'chains', 'collapse', 'crawl',
+            'encryption', 'events', 'findings', 'language_variants',
+            'metadata', 'payloads', 's3', 'stats', 'timeline'
+        ]
+    }
+
+
+def startup_banner():
+    info = instance_info()
+    services = info['services']
+    return '\n'.join([
+        f"requestrepo {info['version']} for {info['domain']}",
+        f"  http {services['http']['ports']}, "
+        f"https {services['https']['ports']}, "
+        f"dns {services['dns']['ports']}",
+        f"  path routing {'on' if PATH_ROUTING else 'off'}, "
+        f"interactsh {'on' if INTERACTSH else 'off'}, "
+        f"certificates {'on' if services['certificates'] else 'off'}",
+    ])
+
+
+@app.route('/api/v2/instance')
+@check_subdomain
+def get_instance():
+    return jsonify(instance_info())
+
+
+@app.route('/api/get_token', methods=['POST', 'OPTIONS'])
+@check_subdomain
+def get_token():
+    if request.method == 'OPTIONS':
+        return 'POST'
+
+    content = request.get_json(silent=True) or {}
+    subdomain = content.get('subdomain')
+    if subdomain is not None:
+        if type(subdomain) is not str or not re.fullmatch(
+                '[a-z0-9]{8}', subdomain.lower()):
+            return jsonify(
+                {"error": "subdomain should be 8 letters or digits"}), 400
+        subdomain = subdomain.lower()
+        if subdomain in RESERVED_LABELS:
+            return jsonify({"error": "Subdomain is reserved"}), 400
+        # sessions from before users were recorded only have their page;
+        # the claim comes before the session is set up, so two requests for
+        # the same name can't both get it
+        if os.path.exists('pages/' + subdomain) or not users_claim(
+                get_client_ip(request), subdomain):
+            return jsonify({"error": "Subdomain already taken"}), 409
+    else:
+        subdomain = get_free_subdomain()
+
+    token = create_session(subdomain, get_client_ip(request))
+    resp = make_response(token)
+    resp.set_cookie('token', token)
+
+    return resp
+
+
+def get_free_subdomain():
+    subdomain = get_random_subdomain()
+    while users_get_subdomain(subdomain) != None:
+        subdomain = get_random_subdomain()
+    return subdomain
+
+
+def create_session(subdomain, ip, expires=None, template=None):
+    dns_delete_records(subdomain)
+    encryption_delete_key(subdomain)
+    aliases_delete(subdomain)
+    dns_delete_settings(subdomain)
+    notifications_delete(subdomain)
+    email_alerts_delete(subdomain)
+    dkim_delete(subdomain)
+    redirect_chains_delete(subdomain)
+    crawl_delete(subdomain)
+    blocklist_delete(subdomain)
+    collapse_delete(subdomain)
+    cloak_delete(subdomain)
+    stats_delete(subdomain)
+    s3_delete(subdomain)
+    metadata_delete(subdomain)
+    if minting_enabled():
+        delete_certificates(f'.{subdomain}.{DOMAIN}')
+    if template is None:
+        write_basic_file(subdomain)
+    else:
+        copy_session(template, subdomain)
+    users_insert_into_db(ip, subdomain)
+    if setting('EMAIL_PROVISION_ENABLED'):
+        provision_email(subdomain)
+
+    if expires is None:
+        expires = datetime.datetime.utcnow() + datetime.timedelta(days=31)
+    payload = {
+        'iat': datetime.datetime.utcnow(),
+        'exp': expires,
+        'subdomain': subdomain
+    }
+    return jwt.encode(payload, JWT_SECRET, algorithm='HS256')
+
+
+def copy_session(template, subdomain):
+    if os.path.exists('pages/' + template):
+        with open('pages/' + template, 'r') as infile:
+            data = infile.read()
+        with open('pages/' + subdomain, 'w') as outfile:
+            outfile.write(data)
+    else:
+        write_basic_file(subdomain)
+
+    suffix = f'.{template}.{DOMAIN}.'
+    for record in dns_get_records(template):
+        if record['domain'].endswith(suffix):
+            dns_insert_record(
+                subdomain, record['domain'][:-len(suffix)] +
+                f'.{subdomain}.{DOMAIN}.', record['type'], record['value'])
+
+    settings = dns_get_settings(template)
+    if settings is not None:
+        dns_set_settings(subdomain, settings)
+    for chain in redirect_chains_get(template):
+        redirect_chains_set(subdomain, chain['name'], chain['hops'])
+    crawl = crawl_get_settings(template)
+    if crawl is not None:
+        crawl_set_settings(subdomain, crawl)
+    blocklist = blocklist_get(template)
+    if blocklist is not None:
+        blocklist_set(subdomain, blocklist)
+    collapse = collapse_get_settings(template)
+    if collapse is not None:
+        collapse_set_settings(subdomain, collapse)
+
+
+@app.route('/api/admin/sessions', methods=['POST'])
+@check_subdomain
+def provision_sessions():
+    if not is_admin(request):
+        return jsonify({'error': 'Unauthorized'}), 401
+
+    content = request.get_json(silent=True) or {}
+    count = content.get('count')
+    max_count = setting('PROVISION_MAX_COUNT')
+    if type(count) is not int or not 1 <= count <= max_count:
+        return jsonify(
+            {'error': f'count should be between 1 and {max_count}'}), 401
+
+    template = content.get('template')
+    if template is not None:
+        if type(template) is not str or users_get_subdomain(
+                template.lower()) is None:
+            return jsonify({'error': 'Invalid template'}), 401
+        template = template.lower()
+
+    # every token of the batch stops working at the same date
+    expires = None
+    if content.get('expires_at') is not None:
+        expires_at = content['expires_at']
+        if type(expires_at) is not int or expires_at <= now():
+            return jsonify({'error': 'Invalid expires_at'}), 401
+        expires = datetime.datetime.utcfromtimestamp(expires_at)
+
+    fmt = content.get('format', 'json')
+    if fmt not in ('json', 'csv'):
+        return jsonify({'error': 'Invalid format'}), 401
+
+    sessions = []
+    for _ in range(count):
+        subdomain = get_free_subdomain()
+        token = create_session(subdomain, get_client_ip(request), expires,
+                               template)
+        sessions.append({
+            'subdomain': subdomain,
+            'hostname': f'{subdomain}.{DOMAIN}',
+            'token': token
+        })
+
+    if fmt == 'csv':
+        resp = make_response(''.join(
+            f"{x['subdomain']},{x['hostname']},{x['token']}\n"
+            for x in [{
+                'subdomain': 'subdomain',
+                'hostname': 'hostname',
+                'token': 'token'
+            }] + sessions))
+        resp.headers['Content-Type'] = 'text/csv'
+        resp.headers[
+            'Content-Disposition'] = 'attachment; filename="sessions.csv"'
+        return resp
+
+    return jsonify({'sessions': sessions})
+
+
+@app.route('/api/enable_encryption', methods=['POST'])
+@check_subdomain
+def enable_encryption():
+    subdomain = verify_jwt(request.cookies.get('token'))
+    if not subdomain:
+        return jsonify({"error": "Unauthorized"}), 401
+
+    content = request.json
+    if not content or type(content.get('passphrase')) is not str:
+        return jsonify({"error": "Invalid passphrase"}), 401
+
+    passphrase = content['passphrase']
+    if len(passphrase) < 8 or len(passphrase) > 1024:
+        return jsonify(
+            {"error": "passphrase should be 8 to 1024 characters"}), 401
+
+    if encryption_get_key(subdomain) is not None:
+        return jsonify({"error": "Encryption already enabled"}), 401
+
+    public_key, private_key = generate_keys(passphrase)
+    encryption_set_key(subdomain, public_key, private_key)
+    return jsonify({"msg": "Enabled encryption"})
+
+
+IP_PINNING_MAX_NETWORKS = 20
+
+
+@app.route('/api/update_ip_pinning', methods=['POST'])
+@check_subdomain
+def update_ip_pinning():
+    subdomain = verify_jwt(request.cookies.get('token'))
+    if not subdomain:
+        return jsonify({"error": "Unauthorized"}), 401
+
+    content = request.get_json(silent=True)
+    if type(content) is not dict or type(content.get('enabled')) is not bool:
+        return jsonify({"error": "Invalid settings"}), 401
+
+    if not content['enabled']:
+        users_set_ip_pinning(subdomain, False, [])
+        return jsonify({"msg": "Disabled IP pinning"})
+
+    networks = content.get('networks', [])
+    if type(networks) is not list or len(networks) > IP_PINNING_MAX_NETWORKS:
+        return jsonify({"error": "Invalid networks"}), 401
+    for network in networks:
+        try:
+            ipaddress.ip_network(network, strict=False)
+        except (TypeError, ValueError):
+            return jsonify({"error": "Invalid networks"}), 401
+
+    user = users_get_subdomain(subdomain)
+    if user is not None and user.get('ip'):
+        networks = [user['ip']] + networks
+
+    # refuse settings that would lock the caller out
+    if not ip_allowed(get_client_ip(request), networks):
+        return jsonify({"error": "Current IP would not be allowed"}), 401
+
+    users_set_ip_pinning(subdomain, True, networks)
+    return jsonify({"msg": "Enabled IP pinning", "networks": networks})
+
+
+COLLAPSE_DEFAULT_WINDOW = 60
+COLLAPSE_MAX_WINDOW = 3600
+
+
+@app.route('/api/v2/collapse', methods=['GET', 'POST'])
+@check_subdomain
+def collapse_settings_route():
+    subdomain = verify_jwt(get_request_token(request))
+    if not subdomain:
+        return jsonify({"error": "Unauthorized"}), 401
+
+    settings = collapse_get_settings(subdomain) or {
+        'enabled': False,
+        'window': COLLAPSE_DEFAULT_WINDOW
+    }
+    if request.method == 'GET':
+        return jsonify(settings)
+
+    content = request.get_json(silent=True) or {}
+    if type(content.get('enabled')) is not bool:
+        return jsonify({"error": "Invalid enabled"}), 401
+    window = content.get('window', settings['window'])
+    if type(window) is not int or not 1 <= window <= COLLAPSE_MAX_WINDOW:
+        return jsonify({
+            "error":
+            f"window should be between 1 and {COLLAPSE_MAX_WINDOW} seconds"
+        }), 401
+
+    collapse_set_settings(subdomain, {
+        'enabled': content['enabled'],
+        'window': window
+    })
+    return jsonify({"msg": "Updated collapse settings"})
+
+
+@app.route('/api/v2/cloak', methods=['GET', 'POST'])
+@check_subdomain
+def cloak_settings_route():
+    subdomain = verify_jwt(get_request_token(request))
+    if not subdomain:
+        return jsonify({"error": "Unauthorized"}), 401
+
+    if request.method == 'GET':
+        return jsonify(
+            cloak_get_settings(subdomain) or {
+                'profile': None,
+                'server': None
+            })
+
+    # null falls back to the instance defaults
+    content = request.get_json(silent=True) or {}
+    profile = content.get('profile')
+    if profile is not None and profile not in PROFILES:
+        return jsonify({
+            "error": f"profile should be one of {', '.join(PROFILES)}"
+        }), 401
+    server = content.get('server')
+    if server is not None and not valid_server_header(server):
+        return jsonify({"error": "Invalid server header"}), 401
+
+    cloak_set_settings(subdomain, {'profile': profile, 'server': server})
+    return jsonify({"msg": "Updated cloak settings"})
+
+
+@app.route('/api/v2/blocklist', methods=['GET', 'POST'])
+@check_subdomain
+def blocklist_route():
+    subdomain = verify_jwt(get_request_token(request))
+    if not subdomain:
+        return jsonify({"error": "Unauthorized"}), 401
+
+    if request.method == 'GET':
+        return jsonify(
+            blocklist_get(subdomain) or {
+                'networks': [],
+                'countries': [],
+                'action': 'discard'
+            })
+
+    content = request.get_json(silent=True) or {}
+    networks = content.get('networks', [])
+    countries = content.get('countries', [])
+    action = content.get('action', 'discard')
+    if type(networks) is not list or len(networks) > BLOCKLIST_MAX_ENTRIES or \
+            not all(valid_network(x) for x in networks):
+        return jsonify({"error": "Invalid networks"}), 401
+    if type(countries) is not list or len(countries) > BLOCKLIST_MAX_ENTRIES or \
+            not all(valid_country(x) for x in countries):
+        return jsonify({"error": "Invalid countries"}), 401
+    if action not in BLOCK_ACTIONS:
+        return jsonify({"error": "Invalid action"}), 401
+
+    blocklist_set(
+        subdomain, {
+            'networks': [
+                str(ipaddress.ip_network(x, strict=False)) for x in networks
+            ],
+            'countries': [x.upper() for x in countries],
+            'action': action
+        })
+    return jsonify({"msg": "Updated blocklist"})
+
+
+@app.route('/api/get_server_time')
+@check_subdomain
+def get_server_time():
+    return jsonify({
+        'date':
+        int(datetime.datetime.now(datetime.timezone.utc).timestamp())
+    })
+
+
+@app.route('/api/delete_request', methods=['POST'])
+@check_subdomain
+def delete_request():
+    subdomain = verify_jwt(request.cookies.get('token'))
+    if not subdomain:
+        return jsonify({"error": "Unauthorized"}), 401
+
+    content = request.json
+    if content:
+        _id = content.get('id')
+        rtype = content.get('type')
+        delete_request_from_db(_id, subdomain, rtype)
+        return jsonify({"rtype": rtype, "_id": _id})
+
+
+@app.route('/api/v2/trash')
+@check_subdomain
+def get_trash_requests():
+    subdomain = verify_jwt(get_request_token(request))
+    if not subdomain:
+        return jsonify({"error": "Unauthorized"}), 401
+
+    trash = get_trash(subdomain)
+    if not decrypt_requests(request, subdomain, trash):
+        return jsonify({'error': 'Invalid passphrase'}), 401
+    return jsonify(trash)
+
+
+@app.route('/api/v2/requests/<_id>/restore', methods=['POST'])
+@check_subdomain
+def restore_request(_id):
+    subdomain = verify_jwt(get_request_token(request))
+    if not subdomain:
+        return jsonify({"error": "Unauthorized"}), 401
+
+    if not ObjectId.is_valid(_id):
+        return jsonify({"error": "Invalid id"}), 401
+
+    rtype = restore_request_from_db(_id, subdomain)
+    if rtype is None:
+        return jsonify({"error": "Not found"}), 404
+    return jsonify({"rtype": rtype, "_id": _id})
+
+
+ANNOTATION_MAX_LABELS = 20
+
+
+@app.route('/api/v2/requests/<_id>/annotations', methods=['POST'])
+@check_subdomain
+def add_annotation(_id):
+    subdomain = verify_jwt(get_request_token(request))
+    if not subdomain:
+        return jsonify({"error": "Unauthorized"}), 401
+
+    if not ObjectId.is_valid(_id):
+        return jsonify({"error": "Invalid id"}), 401
+
+    content = request.get_json(silent=True)
+    if type(content) is not dict:
+        return jsonify({"error": "Invalid annotation"}), 401
+
+    annotation = {}
+    for key in ('verdict', 'note', 'source'):
+        value = content.get(key)
+        if value is None:
+            continue
+        if type(value) is not str or len(value) > 1024:
+            return jsonify({"error": f"Invalid {key}"}), 401
+        annotation[key] = value
+
+    labels = content.get('labels', [])
+    if type(labels) is not list or len(labels) > ANNOTATION_MAX_LABELS or any(
+            type(label) is not str or len(label) > 64 for label in labels):
+        return jsonify({"error": "Invalid labels"}), 401
+    if labels:
+        annotation['labels'] = labels
+
+    if not annotation:
+        return jsonify({"error": "Empty annotation"}), 401
+    annotation['date'] = int(
+        datetime.datetime.now(datetime.timezone.utc).timestamp())
+
+    rtype = annotate_request(_id, subdomain, annotation)
+    if rtype is None:
+        return jsonify({"error": "Not found"}), 404
+    return jsonify({"rtype": rtype, "_id": _id, "annotation": annotation})
+
+
+def request_attachments(_id):
+    subdomain = verify_jwt(get_request_token(request))
+    if not subdomain:
+        return None, (jsonify({"error": "Unauthorized"}), 401)
+
+    if not ObjectId.is_valid(_id):
+        return None, (jsonify({"error": "Invalid id"}), 401)
+
+    dic = http_get_request(_id, subdomain)
+    if dic is None:
+        return None, (jsonify({"error": "Not found"}), 404)
+    if not decrypt_requests(request, subdomain, [dic]):
+        return None, (jsonify({'error': 'Invalid passphrase'}), 401)
+    if 'encrypted' in dic:
+        return None, (jsonify({'error': 'Passphrase required'}), 401)
+
+    raw = dic['raw']
+    # decrypted captures hold the body as base64
+    if type(raw) is str:
+        raw = base64.b64decode(raw)
+    return multipart_files(dic['headers'], raw), None
+
+
+@app.route('/api/v2/requests/<_id>/attachments')
+@check_subdomain
+def list_attachments(_id):
+    files, error = request_attachments(_id)
+    if error:
+        return error
+    return jsonify({
+        'attachments': [{
+            'n': n,
+            'filename': x['filename'],
+            'content_type': x['content_type'],
+            'size': x['size'],
+            'downloadable': x['data'] is not None
+        } for n, x in enumerate(files)]
+    })
+
+
+@app.route('/api/v2/requests/<_id>/attachments/<int:n>')
+@check_subdomain
+def download_attachment(_id, n):
+    files, error = request_attachments(_id)
+    if error:
+        return error
+    if n >= len(files):
+        return jsonify({"error": "Not found"}), 404
+    if files[n]['data'] is None:
+        return jsonify({"error": "Attachment too large"}), 413
+
+    resp = make_response(files[n]['data'])
+    resp.headers['Content-Type'] = files[n]['content_type']
+    resp.headers['Content-Disposition'] = content_disposition(
+        files[n]['filename'])
+    # uploads are attacker controlled, never let the browser render them
+    resp.headers['X-Content-Type-Options'] = 'nosniff'
+    resp.headers['Content-Security-Policy'] = 'sandbox'
+    return resp
+
+
+@app.route('/api/get_file', methods=['GET'])
+@check_subdomain
+def get_file():
+    subdomain = verify_jwt(request.cookies.get('token'))
+    if not subdomain:
+        return jsonify({"raw": "", "headers": [], "status_code": 200})
+
+    if not os.path.exists('pages/' + subdomain):
+        write_basic_file(subdomain)
+
+    with open('pages/' + subdomain, 'r') as outfile:
+        return outfile.read()
+
+
+def parse_response(response):
+    # the body, headers and status of a language variant or a path
+    if type(response) is not dict:
+        return None
+    raw = response.get('raw', '')
+    if type(raw) is not str or len(raw) > RESPONSE_MAX_SIZE:
+        return None
+    try:
+        base64.b64decode(raw)
+        status_code = int(str(response.get('status_code', 200))[:9])
+    except:
+        return None
+    headers = response.get('headers', [])
+    if type(headers) is not list or len(headers) > 30:
+        return None
+    return {
+        'headers': [{
+            'header': header['header'],
+            'value': header['value']
+        } for header in headers if type(header) is dict and
+                    'header' in header and 'value' in header],
+        'raw': raw,
+        'status_code': status_code
+    }
+
+
+def parse_variants(variants):
+    if type(variants) is not dict or len(variants) > MAX_VARIANTS:
+        return None
+    parsed = {}
+    for language, variant in variants.items():
+        language = language.lower()
+        if not valid_language(language):
+            return None
+        parsed[language] = parse_response(variant)
+        if parsed[language] is None:
+            return None
+    return parsed
+
+
+def parse_paths(paths):
+    if type(paths) is not dict or len(paths) > PATH_RESPONSES_MAX:
+        return None
+    parsed = {}
+    for path, response in paths.items():
+        # matched against the path without its query string
+        if not re.fullmatch('/[!-~]{0,254}', path) or '?' in path:
+            return None
+        parsed[path] = parse_response(response)
+        if parsed[path] is None:
+            return None
+    return parsed
+
+
+@app.route('/api/update_file', methods=['POST'])
+@check_subdomain
+def update_file():
+    subdomain = verify_jwt(request.cookies.get('token'))
+    if subdomain:
+        content = request.json
+        status_code = 200
+        if 'status_code' in content:
+            try:
+                try:
+                    if len(content['status_code']) > 9:
+                        return jsonify({"error": "invalid status_code"}), 401
+                    status_code = int(content['status_code'])
+                except:
+                    pass
+            except:
+                return jsonify({"error": "invalid status_code"}), 401
+        raw = ""
+        if 'raw' in content:
+            if len(content['raw']) <= RESPONSE_MAX_SIZE:
+                try:
+                    base64.b64decode(content['raw'])
+                    raw = content['raw']
+                except:
+                    return jsonify({"error": "invalid response"}), 401
+            else:
+                return jsonify(
+                    {"error": "response should be smaller than 2MB"}), 401
+        headers = []
+        if 'headers' in content:
+            if len(headers) <= 30:
+                for header in content['headers']:
+                    if 'header' in header and 'value' in header:
+                        headers.append({
+                            'header': header['header'],
+                            'value': header['value']
+                        })
+            else:
+                return jsonify({"error": "maximum of 30 headers"}), 401
+            variants = parse_variants(content.get('variants', {}))
+            if variants is None:
+                return jsonify({"error": "invalid variants"}), 401
+            paths = parse_paths(content.get('paths', {}))
+            if paths is None:
+                return jsonify({"error": "invalid paths"}), 401
+            with open('pages/' + subdomain, 'w') as outfile:
+                json.dump(
+                    {
+                        'headers': headers,
+                        'raw': raw,
+                        'status_code': status_code,
+                        'compress': content.get('compress', True) is not False,
+                        'nosniff': content.get('nosniff') is True,
+                        'download': content.get('download') is True,
+                        'variants': variants,
+                        'paths': paths
+                    }, outfile)
+        return jsonify({"msg": "Updated response"})
+    return jsonify({"error": "Unauthorized"}), 401
+
+
+# parts stay below nginx's default 1MB request body limit
+UPLOAD_PART_SIZE = 512 * 1024
+
+
+def upload_status(upload):
+    parts = -(-upload['size'] // upload['part_size'])
+    return {
+        'upload_id': upload['upload_id'],
+        'size': upload['size'],
+        'part_size': upload['part_size'],
+        'parts': parts,
+        'received': sorted(int(x) for x in upload['received']),
+        'bytes_received': sum(upload['received'].values())
+    }
+
+
+@app.route('/api/v2/files/upload-session', methods=['POST'])
+@check_subdomain
+def create_upload_session():
+    subdomain = verify_jwt(get_request_token(request))
+    if not subdomain:
+        return jsonify({"error": "Unauthorized"}), 401
+
+    content = request.get_json(silent=True) or {}
+    size = content.get('size')
+    max_size = setting('UPLOAD_MAX_SIZE')
+    if type(size) is not int or not 0 < size <= max_size:
+        return jsonify(
+            {"error": f"size should be between 1 and {max_size} bytes"}), 401
+
+    status_code = content.get('status_code', 200)
+    if type(status_code) is not int or not 100 <= status_code <= 999:
+        return jsonify({"error": "invalid status_code"}), 401
+
+    headers = content.get('headers', [])
+    if type(headers) is not list or len(headers) > 30:
+        return jsonify({"error": "maximum of 30 headers"}), 401
+    if not all(
+            type(header) is dict and type(header.get('header')) is str
+            and type(header.get('value')) is str for header in headers):
+        return jsonify({"error": "invalid headers"}), 401
+
+    if uploads_count(subdomain) >= setting('UPLOAD_MAX_SESSIONS'):
+        return jsonify({
+            "error": "Too many unfinished uploads, complete or wait for them to expire"
+        }), 401
+
+    upload_id = os.urandom(16).hex()
+    uploads_insert(
+        subdomain, upload_id, size, UPLOAD_PART_SIZE, {
+            'headers': [{
+                'header': header['header'],
+                'value': header['value']
+            } for header in headers],
+            'status_code': status_code,
+            'compress': content.get('compress', True) is not False,
+            'nosniff': content.get('nosniff') is True,
+            'download': content.get('download') is True
+        })
+    return jsonify(upload_status(uploads_get(upload_id, subdomain)))
+
+
+@app.route('/api/v2/files/upload-session/<upload_id>', methods=['GET'])
+@check_subdomain
+def get_upload_session(upload_id):
+    subdomain = verify_jwt(get_request_token(request))
+    if not subdomain:
+        return jsonify({"error": "Unauthorized"}), 401
+
+    upload = uploads_get(upload_id, subdomain)
+    if upload is None:
+        return jsonify({"error": "Not found"}), 404
+    return jsonify(upload_status(upload))
+
+
+@app.route('/api/v2/files/upload-session/<upload_id>/parts/<int:part>',
+           methods=['PUT'])
+@check_subdomain
+def put_upload_part(upload_id, part):
+    subdomain = verify_jwt(get_request_token(request))
+    if not subdomain:
+        return jsonify({"error": "Unauthorized"}), 401
+
+    upload = uploads_get(upload_id, subdomain)
+    if upload is None:
+        return jsonify({"error": "Not found"}), 404
+
+    status = upload_status(upload)
+    if part >= status['parts']:
+        return jsonify({"error": "Invalid part"}), 401
+
+    data = request.get_data()
+    expected = min(upload['part_size'],
+                   upload['size'] - part * upload['part_size'])
+    if len(data) != expected:
+        return jsonify({"error": f"part should be {expected} bytes"}), 401
+
+    return jsonify(upload_status(uploads_put_part(upload_id, part, data)))
+
+
+@app.route('/api/v2/files/upload-session/<upload_id>/complete',
+           methods=['POST'])
+@check_subdomain
+def complete_upload_session(upload_id):
+    subdomain = verify_jwt(get_request_token(request))
+    if not subdomain:
+        return jsonify({"error": "Unauthorized"}), 401
+
+    upload = uploads_get(upload_id, subdomain)
+    if upload is None:
+        return jsonify({"error": "Not found"}), 404
+
+    status = upload_status(upload)
+    if status['bytes_received'] != upload['size']:
+        return jsonify({"error": "Upload incomplete", **status}), 401
+
+    # only the main response is uploaded, the page keeps its variants
+    variants = {}
+    if os.path.exists('pages/' + subdomain):
+        with open('pages/' + subdomain, 'r') as infile:
+            variants = json.load(infile).get('variants', {})
+
+    raw = uploads_get_data(upload_id)
+    with open('pages/' + subdomain, 'w') as outfile:
+        json.dump(
+            dict(upload['file'],
+                 raw=str(base64.b64encode(raw), 'utf-8'),
+                 variants=variants), outfile)
+    uploads_delete(upload_id)
+    return jsonify({"msg": "Updated response"})
+
+
+# chunks must fit in a single TXT string and stay valid base64 on their own
+CONFIG_CHUNK_SIZE = 252
+CONFIG_MAX_SIZE = 8192
+
+
+config_key = None
+
+
+def get_config_key():
+    # kept in mongo, so it survives restarts and is the same in every worker
+    global config_key
+    if config_key is None:
+        config_key = server_keys_get_or_create('config',
+                                               generate_config_key())
+    return config_key
+
+
+@app.route('/api/get_config', methods=['GET'])
+@check_subdomain
+def get_config():
+    subdomain = verify_jwt(request.cookies.get('token'))
+    if not subdomain:
+        return jsonify({"error": "Unauthorized"}), 401
+
+    records = dns_get_config_records(subdomain)
+    chunks = [r for r in records if not r['domain'].startswith('cfg.0.')]
+    chunks.sort(key=lambda r: int(r['domain'].split('.')[1]))
+    config = ''.join(r['value'] for r in chunks)
+    return jsonify({
+        'config': str(base64.b64decode(config), 'utf-8', 'replace'),
+        'chunks': len(chunks),
+        'public_key': config_public_key(get_config_key()),
+        'domain': f'cfg.0.{subdomain}.{DOMAIN}'
+    })
+
+
+@app.route('/api/update_config', methods=['POST'])
+@check_subdomain
+def update_config():
+    subdomain = verify_jwt(request.cookies.get('token'))
+    if not subdomain:
+        return jsonify({"error": "Unauthorized"}), 401
+
+    content = request.json
+    if not content or type(content.get('config')) is not str:
+        return jsonify({"error": "Invalid config"}), 401
+
+    config = content['config'].encode()
+    if len(config) > CONFIG_MAX_SIZE:
+        return jsonify({"error": "config should be smaller than 8KB"}), 401
+
+    dns_delete_config_records(subdomain)
+    if not config:
+        return jsonify({"msg": "Deleted config"})
+
+    encoded = str(base64.b64encode(config), 'utf-8')
+    chunks = [
+        encoded[i:i + CONFIG_CHUNK_SIZE]
+        for i in range(0, len(encoded), CONFIG_CHUNK_SIZE)
+    ]
+    # Ed25519 over 'requestrepo-config:<subdomain>\n' and the config
+    signature = sign_config(get_config_key(), subdomain, config)
+
+    dns_insert_config_record(subdomain, f'cfg.0.{subdomain}.{DOMAIN}.',
+                             f'n={len(chunks)};sig={signature}')
+    for i, chunk in enumerate(chunks):
+        dns_insert_config_record(subdomain,
+                                 f'cfg.{i + 1}.{subdomain}.{DOMAIN}.', chunk)
+
+    return jsonify({"msg": "Updated config", "chunks": len(chunks)})
+
+
+@app.route('/api/get_alias', methods=['GET'])
+@check_subdomain
+def get_alias():
+    subdomain = verify_jwt(request.cookies.get('token'))
+    if not subdomain:
+        return jsonify({"error": "Unauthorized"}), 401
+
+    alias = aliases_get_subdomain(subdomain)
+    if alias is None:
+        return jsonify({"alias": None})
+    return jsonify({
+        "alias": alias['alias'],
+        "domain": f"{alias['alias']}.{DOMAIN}"
+    })
+
+
+@app.route('/api/claim_alias', methods=['POST'])
+@check_subdomain
+def claim_alias():
+    subdomain = verify_jwt(request.cookies.get('token'))
+    if not subdomain:
+        return jsonify({"error": "Unauthorized"}), 401
+
+    content = request.json
+    if not content or type(content.get('alias')) is not str:
+        return jsonify({"error": "Invalid alias"}), 401
+
+    alias = content['alias'].lower()
+    if not is_alias_label(alias):
+        return jsonify({"error": "Invalid alias"}), 401
+
+    if alias in RESERVED_LABELS:
+        return jsonify({"error": "Alias is reserved"}), 401
+
+    existing = aliases_get(alias)
+    if existing is not None and existing['subdomain'] != subdomain:
+        return jsonify({"error": "Alias already taken"}), 401
+
+    try:
+        aliases_set(subdomain, alias)
+    except Exception:
+        return jsonify({"error": "Alias already taken"}), 401
+
+    return jsonify({"msg": "Claimed alias", "domain": f"{alias}.{DOMAIN}"})
+
+
+TXT_MODES = ['none', 'nxdomain', 'value']
+
+
+@app.route('/api/get_dns_settings', methods=['GET'])
+@check_subdomain
+def get_dns_settings():
+    subdomain = verify_jwt(request.cookies.get('token'))
+    if not subdomain:
+        return jsonify({"error": "Unauthorized"}), 401
+
+    return jsonify(dns_get_settings(subdomain) or {'txt_mode': 'none'})
+
+
+@app.route('/api/update_dns_settings', methods=['POST'])
+@check_subdomain
+def update_dns_settings():
+    subdomain = verify_jwt(request.cookies.get('token'))
+    if not subdomain:
+        return jsonify({"error": "Unauthorized"}), 401
+
+    content = request.json
+    if type(content) is not dict or content.get('txt_mode') not in TXT_MODES:
+        return jsonify({"error": "Invalid txt_mode"}), 401
+
+    settings = {'txt_mode': content['txt_mode'], 'txt_value': None}
+    if settings['txt_mode'] == 'value':
+        value = content.get('txt_value')
+        if type(value) is not str or value == "" or len(value) > 255:
+            return jsonify({"error": "Invalid txt_value"}), 401
+        if not re.search("^[ -~]+$", value):
+            return jsonify({"error": "Invalid txt_value"}), 401
+        settings['txt_value'] = value
+
+    dns_set_settings(subdomain, settings)
+    return jsonify({"msg": "Updated DNS settings"})
+
+
+@app.route('/api/v2/notifications', methods=['GET', 'POST'])
+@check_subdomain
+def notifications_settings():
+    subdomain = verify_jwt(get_request_token(request))
+    if not subdomain:
+        return jsonify({"error": "Unauthorized"}), 401
+
+    if request.method == 'GET':
+        return jsonify(notifications_get(subdomain) or {})
+
+    content = request.get_json(silent=True)
+    if type(content) is not dict:
+        return jsonify({"error": "Invalid settings"}), 401
+
+    settings = {}
+    for kind in ('slack', 'discord'):
+        if kind in content:
+            url = content[kind]
+            if url is not None and url != "" and not valid_webhook(kind, url):
+                return jsonify({"error": f"Invalid {kind} webhook"}), 401
+            settings[kind] = url or None
+
+    if 'template' in content:
+        template = content['template']
+        if template is not None and (type(template) is not str
+                                     or len(template) > 1024):
+            return jsonify({"error": "Invalid template"}), 401
+        settings['template'] = template or None
+
+    if 'cooldown' in content:
+        cooldown = content['cooldown']
+        if type(cooldown) is not int or not 0 <= cooldown <= 86400:
+            return jsonify({"error": "Invalid cooldown"}), 401
+        settings['cooldown'] = cooldown
+
+    if settings:
+        notifications_set(subdomain, settings)
+    return jsonify({"msg": "Updated notifications"})
+
+
+EMAIL_REGEX = '^[^@\\s]{1,64}@[A-Za-z0-9.\\-]{1,253}\\.[A-Za-z]{2,}$'
+
+
+@app.route('/api/v2/email_alerts', methods=['GET', 'POST'])
+@check_subdomain
+def email_alerts_settings():
+    subdomain = verify_jwt(get_request_token(request))
+    if not subdomain:
+        return jsonify({"error": "Unauthorized"}), 401
+
+    if request.method == 'GET':
+        return jsonify(email_alerts_get(subdomain) or {})
+
+    content = request.get_json(silent=True)
+    if type(content) is not dict:
+        return jsonify({"error": "Invalid alert"}), 401
+
+    email = content.get('email')
+    if not email:
+        email_alerts_delete(subdomain)
+        return jsonify({"msg": "Deleted email alert"})
+    if type(email) is not str or not re.match(EMAIL_REGEX, email):
+        return jsonify({"error": "Invalid email"}), 401
+
+    alert_filter = content.get('filter') or {}
+    if type(alert_filter) is not dict:
+        return jsonify({"error": "Invalid filter"}), 401
+    rtype = alert_filter.get('type')
+    if rtype is not None and rtype not in ('http', 'dns'):
+        return jsonify({"error": "Invalid filter type"}), 401
+    path_contains = alert_filter.get('path_contains')
+    if path_contains is not None and (type(path_contains) is not str
+                                      or len(path_contains) > 256):
+        return jsonify({"error": "Invalid filter path_contains"}), 401
+
+    alert_filter = {'type': rtype, 'path_contains': path_contains}
+    if verified_emails_check(email):
+        email_alerts_set(subdomain, email, alert_filter)
+        return jsonify({"msg": "Updated email alert"})
+
+    # only one confirmation is sent while the same address is pending
+    pending = email_alerts_pending(subdomain, email)
+    if pending is not None:
+        email_alerts_set(subdomain, email, alert_filter, pending)
+        return jsonify({"msg": "Confirmation already sent"})
+
+    confirm_token = secrets.token_urlsafe(32)
+    link = f'https://{DOMAIN}/api/v2/email_alerts/confirm?' + \
+        urllib.parse.urlencode({'token': confirm_token})
+    if not setting('SMTP_RELAY_HOST'):
+        return jsonify({"error": "Email alerts are disabled"}), 503
+    email_alerts_set(subdomain, email, alert_filter, confirm_token)
+    if not confirmation_email(
+            email_budget_claim, email,
+            f'Email alerts for requests on {subdomain} were set up for this address',
+            link):
+        email_alerts_delete(subdomain)
+        return jsonify({"error": "Could not send a confirmation email"}), 503
+    return jsonify({"msg": "Confirmation sent"})
+
+
+@app.route('/api/v2/email_alerts/confirm')
+@check_subdomain
+def confirm_email_alert():
+    confirm_token = request.args.get('token')
+    if type(confirm_token) is not str or not confirm_token:
+        return jsonify({"error": "Invalid token"}), 401
+
+    alert = email_alerts_confirm(confirm_token)
+    if alert is None:
+        return jsonify({"error": "Not found"}), 404
+    verified_emails_add(alert['email'])
+    return jsonify({"msg": f"Email alerts confirmed for {alert['email']}"})
+
+
+EMAIL_LOCAL_PART_REGEX = '^[A-Za-z0-9._+\\-]{1,64}$'
+EMAIL_SUBJECT_MAX_LENGTH = 256
+EMAIL_BODY_MAX_SIZE = 64 * 1024
+
+
+def get_dkim_key(subdomain):
+    # made on first send or an explicit POST, the DNS server publishes the
+    # public half
+    key = dkim_get_key(subdomain)
+    # provisioned sessions start with only their MX record
+    if key is None or 'private_key' not in key:
+        private_key = generate_dkim_key()
+        key = dict(key or {},
+                   selector=DKIM_SELECTOR,
+                   private_key=private_key,
+                   record=dkim_record(private_key))
+        dkim_set_key(subdomain, key['selector'], key['private_key'],
+                     key['record'])
+    return key
+
+
+def provision_email(subdomain):
+    # runs on every get_token, so the slow DKIM key generation is left to
+    # the first send or POST
+    mx = setting('EMAIL_PROVISION_MX_HOST')
+    if mx:
+        dkim_set_mx(subdomain, mx)
+
+
+def dkim_info(subdomain, key):
+    return {
+        'selector': key['selector'],
+        'domain': f'{subdomain}.{DOMAIN}',
+        'name': f'{key["selector"]}._domainkey.{subdomain}.{DOMAIN}',
+        'record': key['record'],
+        'mx': key.get('mx')
+    }
+
+
+@app.route('/api/v2/emails/dkim', methods=['GET', 'POST'])
+@check_subdomain
+def get_email_dkim():
+    subdomain = verify_jwt(get_request_token(request))
+    if not subdomain:
+        return jsonify({"error": "Unauthorized"}), 401
+
+    if request.method == 'POST':
+        return jsonify(dkim_info(subdomain, get_dkim_key(subdomain)))
+
+    key = dkim_get_key(subdomain)
+    if key is None or 'private_key' not in key:
+        return jsonify({"error": "No DKIM key yet, POST to create one"}), 404
+    return jsonify(dkim_info(subdomain, key))
+
+
+@app.route('/api/v2/emails/verify', methods=['POST'])
+@check_subdomain
+def verify_email_recipient():
+    subdomain = verify_jwt(get_request_token(request))
+    if not subdomain:
+        return jsonify({"error": "Unauthorized"}), 401
+    if not setting('EMAIL_SEND_ENABLED') or not setting('SMTP_RELAY_HOST'):
+        return jsonify({"error": "Sending emails is disabled"}), 401
+
+    content = request.get_json(silent=True) or {}
+    to = content.get('email')
+    if type(to) is not str or not re.match(EMAIL_REGEX, to):
+        return jsonify({"error": "Invalid email"}), 401
+    if verified_emails_check(to):
+        return jsonify({"msg": "Already verified"})
+    # confirmations count against the session's hourly limit too
+    if sent_emails_count(subdomain,
+                         now() - 3600) >= setting('EMAIL_SEND_MAX_PER_HOUR'):
+        return jsonify({"error": "Too many emails, try again later"}), 401
+
+    token = secrets.token_urlsafe(32)
+    link = f'https://{DOMAIN}/api/v2/emails/verify/confirm?' + \
+        urllib.parse.urlencode({'token': token})
+    email_verifications_insert(to, token)
+    if not confirmation_email(
+            email_budget_claim, to,
+            f'{subdomain}.{DOMAIN} asked to send test emails to this address',
+            link):
+        return jsonify({"error": "Could not send a confirmation email"}), 503
+    sent_emails_insert(subdomain, {'to': to, 'verification': True})
+    return jsonify({"msg": "Confirmation sent"})
+
+
+@app.route('/api/v2/emails/verify/confirm')
+@check_subdomain
+def confirm_email_recipient():
+    token = request.args.get('token')
+    if type(token) is not str or not token:
+        return jsonify({"error": "Invalid token"}), 401
+
+    verification = email_verifications_confirm(token)
+    if verification is None:
+        return jsonify({"error": "Not found"}), 404
+    verified_emails_add(verification['email'])
+    return jsonify({"msg": f"Confirmed {verification['email']}"})
+
+
+@app.route('/api/v2/emails/provision', methods=['POST'])
+@check_subdomain
+def provision_session_email():
+    subdomain = verify_jwt(get_request_token(request))
+    if not subdomain:
+        return jsonify({"error": "Unauthorized"}), 401
+    if not setting('EMAIL_PROVISION_MX_HOST'):
+        return jsonify({"error": "No mail exchanger configured"}), 403
+
+    provision_email(subdomain)
+    return jsonify(dkim_info(subdomain, get_dkim_key(subdomain)))
+
+
+@app.route('/api/v2/emails/send', methods=['POST'])
+@check_subdomain
+def send_session_email():
+    subdomain = verify_jwt(get_request_token(request))
+    if not subdomain:
+        return jsonify({"error": "Unauthorized"}), 401
+    if not setting('EMAIL_SEND_ENABLED') or not setting('SMTP_RELAY_HOST'):
+        return jsonify({"error": "Sending emails is disabled"}), 401
+
+    content = request.get_json(silent=True) or {}
+    local_part = content.get('from', 'test')
+    if type(local_part) is not str or not re.match(EMAIL_LOCAL_PART_REGEX,
+                                                   local_part):
+        return jsonify({"error": "Invalid from"}), 401
+    to = content.get('to')
+    if type(to) is not str or not re.match(EMAIL_REGEX, to):
+        return jsonify({"error": "Invalid to"}), 401
+    subject = content.get('subject', '')
+    if type(subject) is not str or len(
+            subject) > EMAIL_SUBJECT_MAX_LENGTH or not subject.isprintable():
+        return jsonify({"error": "Invalid subject"}), 401
+    body = content.get('body', '')
+    if type(body) is not str or len(body) > EMAIL_BODY_MAX_SIZE:
+        return jsonify({"error": "Invalid body"}), 401
+
+    # sessions are free to create, so only confirmed addresses are mailed
+    if not verified_emails_check(to):
+        return jsonify({
+            "error": "Recipient not verified, see /api/v2/emails/verify"
+        }), 403
+    if sent_emails_count(subdomain,
+                         now() - 3600) >= setting('EMAIL_SEND_MAX_PER_HOUR'):
+        return jsonify({"error": "Too many emails, try again later"}), 401
+    if not email_budget_claim(setting('EMAIL_MAX_PER_HOUR')):
+        return jsonify({"error": "Too many emails, try again later"}), 401
+
+    domain = f'{subdomain}.{DOMAIN}'
+    sender = f'{local_part}@{domain}'
+    message = EmailMessage()
+    message['From'] = sender
+    message['To'] = to
+    message['Subject'] = subject
+    message['Date'] = email.utils.formatdate()
+    message['Message-ID'] = email.utils.make_msgid(domain=domain)
+    message.set_content(body)
+    data = message.as_bytes(policy=email.policy.SMTP)
+
+    # the signature goes on as a raw line, reformatting it would break it
+    key = get_dkim_key(subdomain)
+    signature = dkim_sign(data, domain, key['selector'], key['private_key'])
+    try:
+        with relay_connection() as smtp:
+            smtp.sendmail(sender, [to],
+                          b'DKIM-Signature: ' + signature.encode() + b'\r\n' +
+                          data)
+    except Exception as ex:
+        return jsonify({"error": f"Sending failed: {ex}"}), 401
+
+    sent_emails_insert(subdomain, {
+        'from': sender,
+        'to': to,
+        'subject': subject,
+        'message_id': message['Message-ID']
+    })
+    return jsonify({
+        'from': sender,
+        'to': to,
+        'message_id': message['Message-ID'],
+        'dkim': dkim_info(subdomain, key)
+    })
+
+
+ANOMALY_SETTINGS = {
+    'spike_factor': (1.0, 1000.0),
+    'min_queries': (1, 100000),
+    'unique_labels': (1, 100000),
+}
+
+
+@app.route('/api/v2/metadata', methods=['GET', 'POST'])
+@check_subdomain
+def metadata_settings_route():
+    subdomain = verify_jwt(get_request_token(request))
+    if not subdomain:
+        return jsonify({"error": "Unauthorized"}), 401
+
+    settings = metadata_get_settings(subdomain) or {'enabled': False}
+    if request.method == 'GET':
+        return jsonify(settings)
+
+    content = request.get_json(silent=True) or {}
+    if type(content.get('enabled')) is not bool:
+        return jsonify({"error": "Invalid enabled"}), 401
+    settings['enabled'] = content['enabled']
+
+    role = content.get('role', settings.get('role', DEFAULT_ROLE))
+    if type(role) is not str or not re.match('^[A-Za-z0-9+=,.@_-]{1,64}$',
+                                             role):
+        return jsonify({"error": "Invalid role"}), 401
+    settings['role'] = role
+
+    if 'canaries' not in settings or content.get('regenerate'):
+        settings['canaries'] = generate_canaries()
+    for field in ('aws_access_key_id', 'aws_secret_access_key'):
+        if field in content:
+            if type(content[field]) is not str or not re.match(
+                    '^[A-Za-z0-9/+]{16,128}$', content[field]):
+                return jsonify({"error": f"Invalid {field}"}), 401
+            settings['canaries'][field] = content[field]
+
+    metadata_set_settings(subdomain, settings,
+                          canary_values(settings['canaries']))
+    return jsonify(settings)
+
+
+@app.route('/api/v2/s3', methods=['GET', 'POST'])
+@check_subdomain
+def s3_settings_route():
+    subdomain = verify_jwt(get_request_token(request))
+    if not subdomain:
+        return jsonify({"error": "Unauthorized"}), 401
+
+    if request.method == 'GET':
+        settings = s3_get_settings(subdomain) or {'enabled': False}
+        settings['objects'] = s3_count_objects(subdomain)
+        return jsonify(settings)
+
+    content = request.get_json(silent=True) or {}
+    if type(content.get('enabled')) is not bool:
+        return jsonify({"error": "Invalid enabled"}), 401
+
+    s3_set_settings(subdomain, {'enabled': content['enabled']})
+    return jsonify({"msg": "Updated S3 settings"})
+
+
+CRAWL_MAX_PATHS = 200
+CRAWL_MAX_DELAY = 3600
+
+
+def parse_crawl_path(entry):
+    if type(entry) is not dict:
+        return None
+    path = entry.get('path')
+    if type(path) is not str or not re.match('^/[^\\s]{0,1023}$', path):
+        return None
+    include = entry.get('include', True)
+    changefreq = entry.get('changefreq')
+    priority = entry.get('priority')
+    if type(include) is not bool:
+        return None
+    if changefreq is not None and changefreq not in CHANGEFREQS:
+        return None
+    if priority is not None and (type(priority) not in (int, float) or
+                                 not 0 <= priority <= 1):
+        return None
+    return {
+        'path': path,
+        'include': include,
+        'changefreq': changefreq,
+        'priority': priority
+    }
+
+
+@app.route('/api/v2/crawl', methods=['GET', 'POST'])
+@check_subdomain
+def crawl_settings_route():
+    subdomain = verify_jwt(get_request_token(request))
+    if not subdomain:
+        return jsonify({"error": "Unauthorized"}), 401
+
+    settings = crawl_get_settings(subdomain) or {
+        'robots': False,
+        'sitemap': False,
+        'crawl_delay': None,
+        'paths': []
+    }
+    if request.method == 'GET':
+        settings['entries'] = crawl_entries(settings,
+                                            redirect_chains_get(subdomain))
+        return jsonify(settings)
+
+    content = request.get_json(silent=True) or {}
+    for field in ('robots', 'sitemap'):
+        if field in content:
+            if type(content[field]) is not bool:
+                return jsonify({"error": f"Invalid {field}"}), 401
+            settings[field] = content[field]
+
+    if 'crawl_delay' in content:
+        delay = content['crawl_delay']
+        if delay is not None and (type(delay) is not int or
+                                  not 1 <= delay <= CRAWL_MAX_DELAY):
+            return jsonify({
+                "error":
+                f"crawl_delay should be between 1 and {CRAWL_MAX_DELAY}"
+            }), 401
+        settings['crawl_delay'] = delay
+
+    if 'paths' in content:
+        paths = content['paths']
+        if type(paths) is not list or len(paths) > CRAWL_MAX_PATHS:
+            return jsonify(
+                {"error": f"at most {CRAWL_MAX_PATHS} paths are allowed"}), 401
+        settings['paths'] = []
+        for entry in paths:
+            parsed = parse_crawl_path(entry)
+            if parsed is None:
+                return jsonify({"error": "Invalid path"}), 401
+            settings['paths'].append(parsed)
+
+    settings['updated'] = int(
+        datetime.datetime.now(datetime.timezone.utc).timestamp())
+    crawl_set_settings(subdomain, settings)
+    return jsonify({"msg": "Updated crawler settings"})
+
+
+@app.route('/api/v2/chains', methods=['GET', 'POST'])
+@check_subdomain
+def redirect_chains_route():
+    subdomain = verify_jwt(get_request_token(request))
+    if not subdomain:
+        return jsonify({"error": "Unauthorized"}), 401
+
+    if request.method == 'GET':
+        return jsonify(redirect_chains_get(subdomain))
+
+    content = request.get_json(silent=True) or {}
+    name = content.get('name')
+    if type(name) is not str or not re.match(CHAIN_NAME_REGEX, name.lower()):
+        return jsonify({"error": "Invalid name"}), 401
+
+    hops = content.get('hops')
+    if type(hops) is not list or not 1 <= len(hops) <= CHAIN_MAX_HOPS:
+        return jsonify(
+            {"error": f"chains should have 1 to {CHAIN_MAX_HOPS} hops"}), 401
+
+    valid_hops = []
+    for hop in hops:
+        if type(hop) is not dict:
+            return jsonify({"error": "Invalid hop"}), 401
+        status = hop.get('status', 302)
+        location = hop.get('location', '{next}')
+        delay = hop.get('delay', 0)
+        if type(status) is not int or not 300 <= status <= 399:
+            return jsonify({"error": "status should be a 3xx code"}), 401
+        if type(location) is not str or not location or len(location) > 2048:
+            return jsonify({"error": "Invalid location"}), 401
+        if type(delay) not in (int, float) or not 0 <= delay <= CHAIN_MAX_DELAY:
+            return jsonify({
+                "error":
+                f"delay should be between 0 and {CHAIN_MAX_DELAY} seconds"
+            }), 401
+        valid_hops.append({
+            'status': status,
+            'location': location,
+            'delay': delay
+        })
+
+    redirect_chains_set(subdomain, name.lower(), valid_hops)
+    return jsonify({"msg": "Updated chain"})
+
+
+@app.route('/api/v2/chains/<name>', methods=['DELETE'])
+@check_subdomain
+def delete_redirect_chain(name):
+    subdomain = verify_jwt(get_request_token(request))
+    if not subdomain:
+        return jsonify({"error": "Unauthorized"}), 401
+
+    if not redirect_chains_delete(subdomain, name.lower()):
+        return jsonify({"error": "Not found"}), 404
+    return jsonify({"msg": "Deleted chain"})
+
+
+@app.route('/api/get_alerts', methods=['GET'])
+@check_subdomain
+def get_alerts():
+    subdomain = verify_jwt(request.cookies.get('token'))
+    if not subdomain:
+        return jsonify({"error": "Unauthorized"}), 401
+
+    time = request.args.get('t')
+    if type(time) == str and time.isdigit():
+        time = int(time)
+    else:
+        time = None
+    return jsonify({
+        'alerts': alerts_get_subdomain(subdomain, time),
+        'settings': anomaly_get_settings(subdomain) or {}
+    })
+
+
+@app.route('/api/update_anomaly_settings', methods=['POST'])
+@check_subdomain
+def update_anomaly_settings():
+    subdomain = verify_jwt(request.cookies.get('token'))
+    if not subdomain:
+        return jsonify({"error": "Unauthorized"}), 401
+
+    content = request.json
+    if type(content) is not dict:
+        return jsonify({"error": "Invalid settings"}), 401
+
+    settings = {}
+    for key, (low, high) in ANOMALY_SETTINGS.items():
+        if key not in content:
+            continue
+        value = content[key]
+        if type(value) not in (int, float) or not low <= value <= high:
+            return jsonify({"error": f"Invalid {key}"}), 401
+        settings[key] = value
+
+    if settings:
+        anomaly_set_settings(subdomain, settings)
+    return jsonify({"msg": "Updated anomaly settings"})
+
+
+@app.route('/api/get_dns_records', methods=['GET'])
+@check_subdomain
+def get_dns_records():
+    subdomain = verify_jwt(request.cookies.get('token'))
+    if subdomain:
+        return jsonify(dns_get_records(subdomain))
+    return jsonify({"error": "Unauthorized"}), 401
+
+
+DNS_RECORDS = ['A', 'AAAA', 'CNAME', 'TXT', 'HTTPS', 'SVCB']
+# priority, absolute target name and key=value parameters, e.g. 1 . alpn=h2
+SVCB_REGEX = '^[0-9]{1,5} (\\.|[A-Za-z0-9\\-_\\.]+\\.)( [a-z0-9\\-]+(=[!-~]+)?)*$'
+
+
+@app.route('/api/update_dns_records', methods=['POST'])
+@check_subdomain
+def update_dns_records():
+    subdomain = verify_jwt(request.cookies.get('token'))
+    if not subdomain:
+        return jsonify({"error": "unauthenticated"}), 401
+
+    dns_delete_records(subdomain)
+    content = request.json
+
+    if 'records' not in content:
+        return jsonify({"error": "Invalid records"}), 401
+
+    for record in content['records']:
+        if type(record) is not dict:
+            continue
+
+        domain = record.get('domain')
+        dtype = record.get('type')
+        value = record.get('value')
+        ttl = record.get('ttl')
+
+        if domain is None or dtype is None or value is None:
+            continue
+        if domain == "" or value == "":
+            continue
+
+        domain = domain.lower()
+
+        if re.match("^cfg\\.[0-9]+$", domain):
+            return jsonify({"error": "Domain reserved for config"}), 401
+
+        if len(domain) > 63:
+            return jsonify({"error": "Domain too big"}), 401
+
+        if len(value) > 255:
+            return jsonify({"error": "Value too big"}), 401
+
+        if type(dtype) is not int:
+            return jsonify({"error": "Invalid type"}), 401
 
         if dtype < 0 or dtype >= len(DNS_RECORDS):
             return jsonify({"error": "Invalid type range"}), 401
@@ -346,21 +3571,43 @@ def update_dns_records():
         if not re.search("^[ -~]+$", value):
             return jsonify({"error": "Invailid regex"}), 401
 
-        if not re.match(
-                "^[A-Za-z0-9](?:[A-Za-z0-9\\-_\\.]{0,61}[A-Za-z0-9])?$",
+        # a leading * matches any name below it that has no record of its own
+        if domain != '*' and not re.match(
+                "^(\\*\\.)?[A-Za-z0-9](?:[A-Za-z0-9\\-_\\.]{0,61}[A-Za-z0-9])?$",
                 domain):
             return jsonify({"error": "invalid regex"}), 401
 
+        if DNS_RECORDS[dtype] in ('HTTPS', 'SVCB') and not re.match(
+                SVCB_REGEX, value):
+            return jsonify({"error": "Invalid SVCB value"}), 401
+
+        # kept from /api/v2/tools/rebind when the page is saved again
+        if ttl is not None and (type(ttl) is not int
+                                or not 0 <= ttl <= RECORD_MAX_TTL):
+            return jsonify({"error": "Invalid TTL"}), 401
+
         domain = f'{domain}.{subdomain}.{DOMAIN}.'
 
         try:
             dtype = DNS_RECORDS[dtype]
-            dns_insert_record(subdomain, domain, dtype, value)
+            dns_insert_record(subdomain, domain, dtype, value, ttl)
         except Exception as e:
             return jsonify({"error": str(e)}), 401
 
     return jsonify({"msg": "Updated records"})
 
 
+def reload_on_signal(signum, frame):
+    try:
+        log.info(f'settings reloaded: {reload_settings()}')
+    except (OSError, ValueError) as ex:
+        log.error(f'settings reload failed: {ex}')
+
+
 if __name__ == '__main__':
-    app.run(host='0.0.0.0', port=21337, debug=True)
+    # gunicorn restarts its workers on SIGHUP, which reloads them as well
+    signal.signal(signal.SIGHUP, reload_on_signal)
+    log.info(startup_banner())
+    app.run(host=os.getenv('HTTP_ADDRESS', '0.0.0.0'),
+            port=int(os.getenv('HTTP_PORT', 21337)),
+            debug=True)
@@ -0,0 +1,168 @@
+import json
+import urllib.parse
+
+# bodies larger than this are left as base64 only
+PARSE_MAX_SIZE = 1024 * 1024
+# multipart parts larger than this only keep their metadata
+PART_MAX_VALUE_SIZE = 64 * 1024
+MAX_FIELDS = 1000
+# uploaded files are cut out of bodies up to this size on every download
+ATTACHMENTS_MAX_BODY_SIZE = 16 * 1024 * 1024
+ATTACHMENT_MAX_SIZE = 10 * 1024 * 1024
+ATTACHMENTS_MAX_COUNT = 100
+
+# leading bytes of common upload formats, checked in order
+MAGIC_NUMBERS = [
+    (b'\x89PNG\r\n\x1a\n', 'image/png'),
+    (b'\xff\xd8\xff', 'image/jpeg'),
+    (b'GIF87a', 'image/gif'),
+    (b'GIF89a', 'image/gif'),
+    (b'%PDF-', 'application/pdf'),
+    (b'PK\x03\x04', 'application/zip'),
+    (b'\x1f\x8b', 'application/gzip'),
+    (b'\x7fELF', 'application/x-elf'),
+    (b'MZ', 'application/x-msdownload'),
+    (b'<?xml', 'application/xml'),
+]
+
+
+def get_content_type(headers):
+    for key, value in headers.items():
+        if key.lower() == 'content-type':
+            media_type, _, params = value.partition(';')
+            options = {}
+            for param in params.split(';'):
+                name, _, option = param.strip().partition('=')
+                if name:
+                    options[name.lower()] = option.strip('"')
+            return media_type.strip().lower(), options
+    return None, {}
+
+
+def decode_text(data):
+    try:
+        return data.decode('utf-8')
+    except UnicodeDecodeError:
+        return None
+
+
+def parse_json(raw):
+    text = decode_text(raw)
+    if text is None:
+        return None
+    try:
+        return {'type': 'json', 'value': json.loads(text)}
+    except ValueError:
+        return None
+
+
+def parse_form(raw):
+    text = decode_text(raw)
+    if text is None:
+        return None
+    fields = urllib.parse.parse_qsl(text,
+                                    keep_blank_values=True,
+                                    max_num_fields=MAX_FIELDS)
+    return {
+        'type': 'form',
+        'fields': [{
+            'name': name,
+            'value': value
+        } for name, value in fields]
+    }
+
+
+def sniff_content_type(data):
+    for magic, content_type in MAGIC_NUMBERS:
+        if data.startswith(magic):
+            return content_type
+    if data[:5].lower() in (b'<!doc', b'<html'):
+        return 'text/html'
+    if decode_text(data) is not None:
+        return 'text/plain'
+    return 'application/octet-stream'
+
+
+def split_multipart(raw, boundary):
+    delimiter = b'--' + boundary.encode()
+    for chunk in raw.split(delimiter)[1:MAX_FIELDS + 1]:
+        if chunk.startswith(b'--'):
+            break
+        head, sep, body = chunk.lstrip(b'\r\n').partition(b'\r\n\r\n')
+        if not sep:
+            continue
+        if body.endswith(b'\r\n'):
+            body = body[:-2]
+
+        headers = {}
+        for line in head.decode('utf-8', errors='replace').split('\r\n'):
+            key, _, value = line.partition(':')
+            headers[key.strip()] = value.strip()
+        _, disposition = get_content_type(
+            {'content-type': headers.get('Content-Disposition', '')})
+        yield headers, disposition, body
+
+
+def parse_multipart(raw, boundary):
+    if not boundary:
+        return None
+
+    parts = []
+    for headers, disposition, body in split_multipart(raw, boundary):
+        part = {
+            'name': disposition.get('name'),
+            'size': len(body),
+        }
+        if 'filename' in disposition:
+            part['filename'] = disposition['filename']
+        if 'Content-Type' in headers:
+            part['content_type'] = headers['Content-Type']
+        if len(body) <= PART_MAX_VALUE_SIZE:
+            value = decode_text(body)
+            if value is not None:
+                part['value'] = value
+        parts.append(part)
+
+    return {'type': 'multipart', 'parts': parts}
+
+
+def parse_body(headers, raw):
+    if not raw or len(raw) > PARSE_MAX_SIZE:
+        return None
+
+    media_type, options = get_content_type(headers)
+    if media_type is None:
+        return None
+    if media_type == 'application/json' or media_type.endswith('+json'):
+        return parse_json(raw)
+    if media_type == 'application/x-www-form-urlencoded':
+        return parse_form(raw)
+    if media_type == 'multipart/form-data':
+        return parse_multipart(raw, options.get('boundary'))
+    return None
+
+
+def multipart_files(headers, raw):
+    # the uploaded files of a multipart body, in the order they were sent
+    media_type, options = get_content_type(headers)
+    if media_type != 'multipart/form-data' or not options.get('boundary'):
+        return []
+    if len(raw) > ATTACHMENTS_MAX_BODY_SIZE:
+        return []
+
+    files = []
+    for part_headers, disposition, body in split_multipart(
+            raw, options['boundary']):
+        if 'filename' not in disposition:
+            continue
+        # files over the limit are listed but can't be downloaded
+        files.append({
+            'filename': disposition['filename'],
+            'content_type': part_headers.get('Content-Type') or
+            sniff_content_type(body),
+            'size': len(body),
+            'data': body if len(body) <= ATTACHMENT_MAX_SIZE else None
+        })
+        if len(files) == ATTACHMENTS_MAX_COUNT:
+            break
+    return files
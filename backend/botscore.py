@@ -0,0 +1,67 @@
+import json
+import logging
+import os
+import re
+
+log = logging.getLogger(__name__)
+
+DEFAULT_WEIGHTS = {
+    'user_agent': 0.4,
+    'missing_headers': 0.3,
+    'timing': 0.2,
+    'ja3': 0.1,
+}
+
+
+def load_weights(value):
+    # a typo in the variable shouldn't keep the backend from starting
+    try:
+        weights = json.loads(value)
+        if type(weights) is not dict or not all(
+                key in DEFAULT_WEIGHTS and type(weight) in (int, float)
+                and weight >= 0 for key, weight in weights.items()):
+            raise ValueError('expected an object of non-negative numbers '
+                             'keyed by ' + ', '.join(DEFAULT_WEIGHTS))
+    except ValueError as ex:
+        log.error(f'invalid BOT_SCORE_WEIGHTS, using the defaults: {ex}')
+        return dict(DEFAULT_WEIGHTS)
+    return dict(DEFAULT_WEIGHTS, **weights)
+
+
+BOT_SCORE_WEIGHTS = load_weights(os.getenv('BOT_SCORE_WEIGHTS', '{}'))
+BOT_JA3 = set(filter(None, os.getenv('BOT_JA3', '').lower().split(',')))
+
+BOT_USER_AGENT = re.compile(
+    'bot|crawl|spider|curl|wget|python|go-http|java|okhttp|libwww|httpclient|'
+    'scan|nmap|masscan|zgrab|nuclei|headless', re.I)
+
+BROWSER_HEADERS = ['accept', 'accept-language', 'accept-encoding']
+
+# requests from the same ip arriving faster than this are considered automated
+BOT_TIMING_SECONDS = 1
+
+
+def bot_score(headers, ja3=None, seconds_since_last=None):
+    names = [name.lower() for name in headers]
+    user_agent = ''
+    for name in headers:
+        if name.lower() == 'user-agent':
+            user_agent = headers[name]
+
+    signals = {
+        'user_agent':
+        1.0 if not user_agent or BOT_USER_AGENT.search(user_agent) else 0.0,
+        'missing_headers':
+        sum(h not in names for h in BROWSER_HEADERS) / len(BROWSER_HEADERS),
+        'timing':
+        1.0 if seconds_since_last is not None
+        and seconds_since_last < BOT_TIMING_SECONDS else 0.0,
+        'ja3':
+        1.0 if ja3 and ja3.lower() in BOT_JA3 else 0.0,
+    }
+
+    total = sum(BOT_SCORE_WEIGHTS.values())
+    if total <= 0:
+        return 0.0
+    score = sum(BOT_SCORE_WEIGHTS.get(k, 0) * v for k, v in signals.items())
+    return round(score / total, 2)
@@ -0,0 +1,116 @@
+import logging
+import os
+import threading
+import time
+
+# consecutive failures before a step is disabled, and for how long
+BREAKER_THRESHOLD = 5
+BREAKER_COOLDOWN = 60
+# calls taking longer than this count as failures, so a step that hangs
+# instead of raising is disabled as well
+BREAKER_SLOW_SECONDS = 1
+
+# breakers live in the memory of each worker process, so every worker trips
+# on its own and /api/admin/breakers shows the worker that answered
+
+log = logging.getLogger(__name__)
+
+
+# called with the step name and exception whenever a step fails
+error_handler = None
+
+
+def set_error_handler(handler):
+    global error_handler
+    error_handler = handler
+
+
+class CircuitBreaker:
+    def __init__(self, name, threshold=BREAKER_THRESHOLD,
+                 cooldown=BREAKER_COOLDOWN, slow=BREAKER_SLOW_SECONDS):
+        self.name = name
+        self.threshold = threshold
+        self.cooldown = cooldown
+        self.slow = slow
+        self.failures = 0
+        self.total_failures = 0
+        self.total_slow = 0
+        self.total_calls = 0
+        self.skipped = 0
+        self.opened_at = None
+        self.lock = threading.Lock()
+
+    @property
+    def is_open(self):
+        return self.opened_at is not None and time.time(
+        ) - self.opened_at < self.cooldown
+
+    def call(self, f, *args, default=None, **kwargs):
+        with self.lock:
+            if self.opened_at is not None:
+                if time.time() - self.opened_at < self.cooldown:
+                    self.skipped += 1
+                    return default
+                # half-open: let this call through to probe the step
+                self.opened_at = None
+                self.failures = self.threshold - 1
+            self.total_calls += 1
+
+        started = time.monotonic()
+        try:
+            result = f(*args, **kwargs)
+        except Exception as ex:
+            log.warning(f'enrichment step {self.name} failed: {ex}')
+            if error_handler is not None:
+                try:
+                    error_handler(self.name, ex)
+                except Exception:
+                    pass
+            self.record_failure()
+            return default
+
+        elapsed = time.monotonic() - started
+        if elapsed > self.slow:
+            log.warning(f'enrichment step {self.name} took {elapsed:.1f}s')
+            with self.lock:
+                self.total_slow += 1
+            self.record_failure()
+            return result
+
+        with self.lock:
+            self.failures = 0
+        return result
+
+    def record_failure(self):
+        with self.lock:
+            self.failures += 1
+            self.total_failures += 1
+            if self.failures >= self.threshold:
+                self.opened_at = time.time()
+
+    def status(self):
+        return {
+            'name': self.name,
+            'open': self.is_open,
+            'failures': self.failures,
+            'total_calls': self.total_calls,
+            'total_failures': self.total_failures,
+            'total_slow': self.total_slow,
+            'skipped': self.skipped,
+            'pid': os.getpid(),
+        }
+
+
+breakers = {}
+breakers_lock = threading.Lock()
+
+
+def get_breaker(name):
+    with breakers_lock:
+        if name not in breakers:
+            breakers[name] = CircuitBreaker(name)
+        return breakers[name]
+
+
+def breakers_status():
+    return [breaker.status() for breaker in breakers.values()]
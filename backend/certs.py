@@ -0,0 +1,165 @@
+import datetime
+import fcntl
+import os
+import tempfile
+import threading
+import time
+from cryptography import x509
+from cryptography.x509.oid import NameOID
+from cryptography.hazmat.primitives import hashes, serialization
+from cryptography.hazmat.primitives.asymmetric import ec
+
+# written here and read by nginx, which picks the certificate by SNI
+CERTS_DIR = os.getenv('CERTS_DIR', 'certs')
+# internal CA used to sign certificates for names the wildcard can't cover;
+# unset disables minting
+CERT_CA_CERT = os.getenv('CERT_CA_CERT')
+CERT_CA_KEY = os.getenv('CERT_CA_KEY')
+CERT_VALIDITY_DAYS = int(os.getenv('CERT_VALIDITY_DAYS', 30))
+# certificates closer than this to expiry are minted again
+CERT_RENEW_DAYS = 7
+CERT_MAX_PER_SESSION = int(os.getenv('CERT_MAX_PER_SESSION', 20))
+# how often expired certificates are removed
+CERT_PURGE_INTERVAL = int(os.getenv('CERT_PURGE_INTERVAL', 3600))
+
+_ca = None
+
+
+def minting_enabled():
+    return bool(CERT_CA_CERT and CERT_CA_KEY)
+
+
+def load_ca():
+    global _ca
+    if _ca is None:
+        with open(CERT_CA_CERT, 'rb') as f:
+            ca_cert = x509.load_pem_x509_certificate(f.read())
+        with open(CERT_CA_KEY, 'rb') as f:
+            ca_key = serialization.load_pem_private_key(f.read(), None)
+        _ca = (ca_cert, ca_key)
+    return _ca
+
+
+def cert_path(hostname):
+    # one file with the chain and the key, so nginx never sees a stale pair
+    return os.path.join(CERTS_DIR, hostname + '.pem')
+
+
+def get_certificate_expiry(hostname):
+    try:
+        with open(cert_path(hostname), 'rb') as f:
+            data = f.read()
+        cert = x509.load_pem_x509_certificate(data)
+    except (OSError, ValueError):
+        return None
+    # left over from when the key had a file of its own
+    if b'PRIVATE KEY' not in data:
+        return None
+    return cert.not_valid_after_utc
+
+
+def count_certificates(suffix):
+    try:
+        names = os.listdir(CERTS_DIR)
+    except OSError:
+        return 0
+    return sum(1 for name in names if name.endswith(suffix + '.pem'))
+
+
+def remove_certificate_file(name):
+    try:
+        os.remove(os.path.join(CERTS_DIR, name))
+    except OSError:
+        pass
+
+
+def delete_certificates(suffix):
+    # every certificate under suffix, only the names are looked at
+    try:
+        names = os.listdir(CERTS_DIR)
+    except OSError:
+        return
+    for name in names:
+        if name.endswith(suffix + '.pem'):
+            remove_certificate_file(name)
+
+
+def purge_certificates():
+    # expired certificates, parses every file so it runs off the request path
+    try:
+        names = os.listdir(CERTS_DIR)
+    except OSError:
+        return
+    now = datetime.datetime.now(datetime.timezone.utc)
+    for name in names:
+        hostname, ext = os.path.splitext(name)
+        if ext == '.key':
+            remove_certificate_file(name)
+        elif ext == '.pem':
+            expiry = get_certificate_expiry(hostname)
+            if expiry is None or expiry < now:
+                remove_certificate_file(name)
+
+
+def watch_certificates():
+    def run():
+        while True:
+            try:
+                purge_certificates()
+            except Exception:
+                pass
+            time.sleep(CERT_PURGE_INTERVAL)
+
+    threading.Thread(target=run, daemon=True).start()
+
+
+def mint_limited(hostname, suffix, limit):
+    # the count and the write happen under one lock shared by every worker,
+    # so parallel requests can't both take the last slot; renewals don't
+    # count against the limit. Returns None when the session is full
+    os.makedirs(CERTS_DIR, exist_ok=True)
+    with open(os.path.join(CERTS_DIR, '.lock'), 'w') as lock:
+        fcntl.flock(lock, fcntl.LOCK_EX)
+        try:
+            if get_certificate_expiry(hostname) is None and count_certificates(
+                    suffix) >= limit:
+                return None
+            return mint_certificate(hostname)
+        finally:
+            fcntl.flock(lock, fcntl.LOCK_UN)
+
+
+def mint_certificate(hostname):
+    expiry = get_certificate_expiry(hostname)
+    now = datetime.datetime.now(datetime.timezone.utc)
+    if expiry is not None and expiry - now > datetime.timedelta(
+            days=CERT_RENEW_DAYS):
+        return expiry
+
+    ca_cert, ca_key = load_ca()
+    key = ec.generate_private_key(ec.SECP256R1())
+    expiry = now + datetime.timedelta(days=CERT_VALIDITY_DAYS)
+    cert = x509.CertificateBuilder().subject_name(
+        x509.Name([x509.NameAttribute(NameOID.COMMON_NAME, hostname)])
+    ).issuer_name(ca_cert.subject).public_key(key.public_key()).serial_number(
+        x509.random_serial_number()).not_valid_before(
+            now - datetime.timedelta(minutes=5)).not_valid_after(
+                expiry).add_extension(
+                    x509.SubjectAlternativeName([x509.DNSName(hostname)]),
+                    critical=False).add_extension(
+                        x509.BasicConstraints(ca=False, path_length=None),
+                        critical=True).sign(ca_key, hashes.SHA256())
+
+    os.makedirs(CERTS_DIR, exist_ok=True)
+    # replaced in one step, nginx reads the file on every handshake
+    with tempfile.NamedTemporaryFile(dir=CERTS_DIR, delete=False) as f:
+        f.write(
+            cert.public_bytes(serialization.Encoding.PEM) +
+            ca_cert.public_bytes(serialization.Encoding.PEM) +
+            key.private_bytes(serialization.Encoding.PEM,
+                              serialization.PrivateFormat.PKCS8,
+                              serialization.NoEncryption()))
+    # nginx workers run as another user
+    os.chmod(f.name, 0o644)
+    os.replace(f.name, cert_path(hostname))
+    return expiry
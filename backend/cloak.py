@@ -0,0 +1,61 @@
+# what a response looks like to a scanner: the Server header, headers the
+# impersonated stack adds, and its page for missing files
+PROFILES = {
+    'requestrepo': {
+        'server': 'requestrepo.com',
+        'headers': {},
+        'not_found': '<h1>Not Found</h1>\n'
+    },
+    'nginx': {
+        'server': 'nginx',
+        'headers': {},
+        'not_found': '<html>\r\n<head><title>404 Not Found</title></head>\r\n'
+        '<body>\r\n<center><h1>404 Not Found</h1></center>\r\n'
+        '<hr><center>nginx</center>\r\n</body>\r\n</html>\r\n'
+    },
+    'apache': {
+        'server': 'Apache',
+        'headers': {},
+        'not_found': '<!DOCTYPE HTML PUBLIC "-//IETF//DTD HTML 2.0//EN">\n'
+        '<html><head>\n<title>404 Not Found</title>\n</head><body>\n'
+        '<h1>Not Found</h1>\n'
+        '<p>The requested URL was not found on this server.</p>\n'
+        '</body></html>\n'
+    },
+    'iis': {
+        'server': 'Microsoft-IIS/10.0',
+        'headers': {'X-Powered-By': 'ASP.NET'},
+        'not_found': '<!DOCTYPE html PUBLIC "-//W3C//DTD XHTML 1.0 Strict//EN" '
+        '"http://www.w3.org/TR/xhtml1/DTD/xhtml1-strict.dtd">\r\n'
+        '<html xmlns="http://www.w3.org/1999/xhtml">\r\n<head>\r\n'
+        '<meta http-equiv="Content-Type" content="text/html; '
+        'charset=iso-8859-1"/>\r\n<title>404 - File or directory not found.'
+        '</title>\r\n</head>\r\n<body>\r\n<div id="header"><h1>Server Error'
+        '</h1></div>\r\n<div id="content">\r\n <div class="content-container">'
+        '<fieldset>\r\n  <h2>404 - File or directory not found.</h2>\r\n'
+        '  <h3>The resource you are looking for might have been removed, had '
+        'its name changed, or is temporarily unavailable.</h3>\r\n'
+        ' </fieldset></div>\r\n</div>\r\n</body>\r\n</html>\r\n'
+    },
+}
+SERVER_HEADER_MAX_LENGTH = 128
+
+
+def valid_server_header(value):
+    return type(value) is str and 0 < len(
+        value) <= SERVER_HEADER_MAX_LENGTH and value.isprintable()
+
+
+def server_identity(instance, session):
+    # the session's choices win over the instance, the header over the profile
+    profile = PROFILES.get(session.get('profile') or instance['profile'],
+                           PROFILES['requestrepo'])
+    headers = dict(profile['headers'])
+    headers['Server'] = session.get('server') or instance.get(
+        'server') or profile['server']
+    return headers, profile['not_found']
+
+
+def apply_identity(resp, headers):
+    for name, value in headers.items():
+        resp.headers[name] = value
@@ -0,0 +1,32 @@
+import base64
+
+from cryptography.hazmat.primitives import serialization
+from cryptography.hazmat.primitives.asymmetric import ed25519
+
+# config records are signed with one Ed25519 key per instance; clients only
+# ever get the public half, so they can check a config but not forge one
+
+
+def generate_config_key():
+    key = ed25519.Ed25519PrivateKey.generate()
+    return key.private_bytes(serialization.Encoding.PEM,
+                             serialization.PrivateFormat.PKCS8,
+                             serialization.NoEncryption()).decode()
+
+
+def config_public_key(private_key):
+    public_key = serialization.load_pem_private_key(
+        private_key.encode(), None).public_key().public_bytes(
+            serialization.Encoding.Raw, serialization.PublicFormat.Raw)
+    return base64.b64encode(public_key).decode()
+
+
+def config_signed_data(subdomain, config):
+    # bound to the session, so a config can't be replayed under another one
+    return b'requestrepo-config:' + subdomain.encode() + b'\n' + config
+
+
+def sign_config(private_key, subdomain, config):
+    key = serialization.load_pem_private_key(private_key.encode(), None)
+    return base64.b64encode(key.sign(config_signed_data(subdomain,
+                                                        config))).decode()
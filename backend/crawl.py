@@ -0,0 +1,49 @@
+import datetime
+from xml.sax.saxutils import escape
+
+SITEMAP_NAMESPACE = 'http://www.sitemaps.org/schemas/sitemap/0.9'
+CHANGEFREQS = [
+    'always', 'hourly', 'daily', 'weekly', 'monthly', 'yearly', 'never'
+]
+
+
+def crawl_entries(settings, chains):
+    # the response file and the chain entry points, overridden by the flags
+    entries = {'/': {'path': '/', 'include': True}}
+    for chain in chains:
+        path = f'/chain/{chain["name"]}'
+        entries[path] = {'path': path, 'include': True}
+    for entry in settings.get('paths', []):
+        entries[entry['path']] = entry
+    return sorted(entries.values(), key=lambda x: x['path'])
+
+
+def robots_txt(settings, entries, base, prefix):
+    lines = ['User-agent: *']
+    lines += [
+        f'Disallow: {prefix}{x["path"]}' for x in entries if not x['include']
+    ] or ['Disallow:']
+    if settings.get('crawl_delay'):
+        lines.append(f'Crawl-delay: {settings["crawl_delay"]}')
+    if settings.get('sitemap'):
+        lines.append(f'Sitemap: {base}/sitemap.xml')
+    return '\n'.join(lines) + '\n'
+
+
+def sitemap_url(entry, base, lastmod):
+    url = f'<url><loc>{escape(base + entry["path"])}</loc>'
+    url += f'<lastmod>{lastmod}</lastmod>'
+    if entry.get('changefreq'):
+        url += f'<changefreq>{entry["changefreq"]}</changefreq>'
+    if entry.get('priority') is not None:
+        url += f'<priority>{entry["priority"]:.1f}</priority>'
+    return url + '</url>'
+
+
+def sitemap_xml(settings, entries, base):
+    lastmod = datetime.datetime.fromtimestamp(
+        settings.get('updated', 0), datetime.timezone.utc).strftime('%Y-%m-%d')
+    urls = ''.join(
+        sitemap_url(x, base, lastmod) for x in entries if x['include'])
+    return ('<?xml version="1.0" encoding="UTF-8"?>\n'
+            f'<urlset xmlns="{SITEMAP_NAMESPACE}">{urls}</urlset>')
@@ -0,0 +1,74 @@
+import base64
+import hashlib
+import re
+import time
+
+from cryptography.hazmat.primitives import hashes, serialization
+from cryptography.hazmat.primitives.asymmetric import padding, rsa
+
+DKIM_SELECTOR = 'requestrepo'
+SIGNED_HEADERS = ['from', 'to', 'subject', 'date', 'message-id']
+
+
+def generate_dkim_key():
+    key = rsa.generate_private_key(public_exponent=65537, key_size=2048)
+    return key.private_bytes(serialization.Encoding.PEM,
+                             serialization.PrivateFormat.PKCS8,
+                             serialization.NoEncryption()).decode()
+
+
+def dkim_record(private_key):
+    # what goes in the TXT record of <selector>._domainkey.<domain>
+    public_key = serialization.load_pem_private_key(
+        private_key.encode(), None).public_key().public_bytes(
+            serialization.Encoding.DER,
+            serialization.PublicFormat.SubjectPublicKeyInfo)
+    return 'v=DKIM1; k=rsa; p=' + base64.b64encode(public_key).decode()
+
+
+def relaxed_header(name, value):
+    value = re.sub('\r\n(?=[ \t])', '', value)
+    value = re.sub('[ \t]+', ' ', value).strip()
+    return f'{name.lower().strip()}:{value}'
+
+
+def relaxed_body(body):
+    lines = body.replace(b'\r\n', b'\n').split(b'\n')
+    lines = [re.sub(b'[ \t]+', b' ', line).rstrip(b' ') for line in lines]
+    while lines and lines[-1] == b'':
+        lines.pop()
+    return b''.join(line + b'\r\n' for line in lines)
+
+
+def parse_headers(block):
+    headers = []
+    for line in block.split('\r\n'):
+        if line[:1] in (' ', '\t') and headers:
+            headers[-1][1] += '\r\n' + line
+        else:
+            name, _, value = line.partition(':')
+            headers.append([name, value])
+    return headers
+
+
+def dkim_sign(message, domain, selector, private_key):
+    # relaxed/relaxed rsa-sha256 over a message with CRLF line endings,
+    # returns the value of the DKIM-Signature header to put in front of it
+    block, _, body = message.partition(b'\r\n\r\n')
+    headers = parse_headers(block.decode())
+    signed = []
+    for name in SIGNED_HEADERS:
+        found = [x for x in headers if x[0].strip().lower() == name]
+        if found:
+            signed.append(relaxed_header(*found[-1]))
+    names = [x.split(':', 1)[0] for x in signed]
+
+    body_hash = base64.b64encode(hashlib.sha256(
+        relaxed_body(body)).digest()).decode()
+    value = f'v=1; a=rsa-sha256; c=relaxed/relaxed; d={domain}; ' + \
+        f's={selector}; t={int(time.time())}; h={":".join(names)}; ' + \
+        f'bh={body_hash}; b='
+    data = '\r\n'.join(signed + [relaxed_header('DKIM-Signature', value)])
+    key = serialization.load_pem_private_key(private_key.encode(), None)
+    signature = key.sign(data.encode(), padding.PKCS1v15(), hashes.SHA256())
+    return value + base64.b64encode(signature).decode()
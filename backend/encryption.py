@@ -0,0 +1,76 @@
+import base64
+import json
+import os
+from cryptography.hazmat.primitives import hashes, serialization
+from cryptography.hazmat.primitives.asymmetric import padding, rsa
+from cryptography.hazmat.primitives.ciphers.aead import AESGCM
+
+OAEP = padding.OAEP(mgf=padding.MGF1(algorithm=hashes.SHA256()),
+                    algorithm=hashes.SHA256(),
+                    label=None)
+
+# everything taken from what the client sent; trace ids come from headers,
+# the S3 key, chain name and crawl file from the path
+HTTP_ENCRYPTED_FIELDS = ['raw', 'headers', 'header_list', 'path', 'query',
+                         'url', 'parsed', 'findings', 'trace', 's3', 'chain',
+                         'crawl']
+DNS_ENCRYPTED_FIELDS = ['raw', 'name', 'answers']
+
+
+def generate_keys(passphrase):
+    private_key = rsa.generate_private_key(public_exponent=65537,
+                                           key_size=2048)
+    private_pem = private_key.private_bytes(
+        serialization.Encoding.PEM, serialization.PrivateFormat.PKCS8,
+        serialization.BestAvailableEncryption(passphrase.encode()))
+    public_pem = private_key.public_key().public_bytes(
+        serialization.Encoding.PEM,
+        serialization.PublicFormat.SubjectPublicKeyInfo)
+    return public_pem, private_pem
+
+
+def load_private_key(private_pem, passphrase):
+    try:
+        return serialization.load_pem_private_key(private_pem,
+                                                  passphrase.encode())
+    except (ValueError, TypeError):
+        return None
+
+
+def encrypt_fields(public_pem, dic, fields):
+    payload = {}
+    for field in fields:
+        if field not in dic:
+            continue
+        value = dic[field]
+        if type(value) is bytes:
+            value = str(base64.b64encode(value), 'utf-8')
+        payload[field] = value
+        del dic[field]
+
+    data_key = AESGCM.generate_key(bit_length=256)
+    nonce = os.urandom(12)
+    data = AESGCM(data_key).encrypt(nonce, json.dumps(payload).encode(), None)
+    wrapped_key = serialization.load_pem_public_key(public_pem).encrypt(
+        data_key, OAEP)
+
+    dic['raw'] = b''
+    dic['encrypted'] = {
+        'key': str(base64.b64encode(wrapped_key), 'utf-8'),
+        'nonce': str(base64.b64encode(nonce), 'utf-8'),
+        'data': str(base64.b64encode(data), 'utf-8')
+    }
+
+
+def decrypt_fields(private_key, dic):
+    envelope = dic['encrypted']
+    try:
+        data_key = private_key.decrypt(base64.b64decode(envelope['key']),
+                                       OAEP)
+        payload = AESGCM(data_key).decrypt(
+            base64.b64decode(envelope['nonce']),
+            base64.b64decode(envelope['data']), None)
+    except Exception:
+        return
+    dic.update(json.loads(payload))
+    del dic['encrypted']
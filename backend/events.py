@@ -0,0 +1,84 @@
+import datetime
+import logging
+import queue
+import threading
+import time
+
+# one thread in each worker process polls mongo for every subscribed session,
+# so open event streams don't each poll on their own
+log = logging.getLogger(__name__)
+
+
+def now():
+    return int(datetime.datetime.now(datetime.timezone.utc).timestamp())
+
+
+class EventHub:
+    def __init__(self, collect, interval):
+        # collect(subdomain, since) returns (key, event, data) tuples, the key
+        # is the same for an event that hasn't changed since the last poll
+        self.collect = collect
+        self.interval = interval
+        self.lock = threading.Lock()
+        self.subscribers = {}
+        self.cursors = {}
+        self.started = False
+
+    def subscribe(self, subdomains):
+        stream = queue.Queue()
+        with self.lock:
+            if not self.started:
+                threading.Thread(target=self.run, daemon=True).start()
+                self.started = True
+            for subdomain in subdomains:
+                self.subscribers.setdefault(subdomain, set()).add(stream)
+                self.cursors.setdefault(subdomain, (now(), set()))
+        return stream
+
+    def unsubscribe(self, subdomains, stream):
+        with self.lock:
+            for subdomain in subdomains:
+                streams = self.subscribers.get(subdomain, set())
+                streams.discard(stream)
+                if not streams:
+                    self.subscribers.pop(subdomain, None)
+                    self.cursors.pop(subdomain, None)
+
+    def cursor(self, subdomains):
+        # everything dated before this has been handed to the streams
+        with self.lock:
+            return min(
+                (self.cursors[x][0] for x in subdomains if x in self.cursors),
+                default=now())
+
+    def poll(self, subdomain, last, seen):
+        server_time = now()
+        events, new_seen = [], set()
+        for key, event, data in self.collect(subdomain, last):
+            new_seen.add(key)
+            if key not in seen:
+                events.append((key, event, data))
+        # requests in the same second as the cursor are re-read next poll
+        return events, (server_time, new_seen)
+
+    def run(self):
+        while True:
+            time.sleep(self.interval)
+            self.poll_all()
+
+    def poll_all(self):
+        with self.lock:
+            cursors = dict(self.cursors)
+        for subdomain, (last, seen) in cursors.items():
+            try:
+                events, cursor = self.poll(subdomain, last, seen)
+            except Exception as ex:
+                log.warning(f'could not poll events of {subdomain}: {ex}')
+                continue
+            with self.lock:
+                if subdomain not in self.cursors:
+                    continue
+                for stream in self.subscribers[subdomain]:
+                    for event in events:
+                        stream.put(event)
+                self.cursors[subdomain] = cursor
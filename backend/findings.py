@@ -0,0 +1,70 @@
+import re
+
+# (kind, pattern), the first group is reported when the pattern has one
+PATTERNS = [
+    ('jwt', re.compile(
+        r'\beyJ[A-Za-z0-9_-]{5,}\.eyJ[A-Za-z0-9_-]{5,}\.[A-Za-z0-9_-]*')),
+    ('aws_access_key_id',
+     re.compile(r'\b((?:AKIA|ASIA|AGPA|AIDA|AROA)[0-9A-Z]{16})\b')),
+    ('aws_secret_access_key',
+     re.compile(
+         r'(?i)aws.{0,20}?(?:secret|key).{0,20}?[\'"=:\s]([A-Za-z0-9/+]{40})\b')),
+    ('github_token', re.compile(r'\b(gh[pousr]_[A-Za-z0-9]{36,})\b')),
+    ('slack_token', re.compile(r'\b(xox[abpors]-[A-Za-z0-9-]{10,})\b')),
+    ('google_api_key', re.compile(r'\b(AIza[0-9A-Za-z_-]{35})\b')),
+    ('private_key', re.compile(r'-----BEGIN [A-Z ]*PRIVATE KEY-----')),
+]
+
+# bodies larger than this are not scanned
+SCAN_MAX_SIZE = 1024 * 1024
+
+
+def mask(value):
+    if len(value) <= 12:
+        return value[:2] + '...'
+    return value[:6] + '...' + value[-4:]
+
+
+def scan_text(text, location):
+    findings = []
+    for kind, pattern in PATTERNS:
+        for match in pattern.finditer(text):
+            value = match.group(1) if pattern.groups else match.group(0)
+            findings.append({
+                'kind': kind,
+                'location': location,
+                'preview': mask(value)
+            })
+    return findings
+
+
+def scan_request(headers, path, raw):
+    findings = []
+    for key, value in headers.items():
+        if key.lower() == 'authorization':
+            scheme, _, credentials = value.partition(' ')
+            findings.append({
+                'kind': 'authorization_' + scheme.lower()
+                if credentials else 'authorization',
+                'location': 'header:' + key,
+                'preview': mask(credentials or value)
+            })
+        elif key.lower() == 'cookie':
+            for cookie in value.split(';'):
+                name, _, cookie_value = cookie.strip().partition('=')
+                if cookie_value:
+                    findings.append({
+                        'kind': 'cookie',
+                        'location': 'header:' + key,
+                        'name': name,
+                        'preview': mask(cookie_value)
+                    })
+        findings += [
+            x for x in scan_text(value, 'header:' + key)
+            if key.lower() != 'authorization' or x['kind'] != 'jwt'
+        ]
+
+    findings += scan_text(path, 'path')
+    if raw and len(raw) <= SCAN_MAX_SIZE:
+        findings += scan_text(raw.decode('utf-8', errors='replace'), 'body')
+    return findings
@@ -0,0 +1,34 @@
+import gunicorn.http.wsgi
+
+bind = '0.0.0.0:21337'
+workers = 4
+# event streams stay open, threads keep them from taking whole workers
+worker_class = 'gthread'
+threads = 16
+
+
+class Response(gunicorn.http.wsgi.Response):
+    def default_headers(self):
+        # gunicorn has no setting for its Server line and every response
+        # brings its own, see cloak.py
+        return [
+            x for x in super().default_headers()
+            if not x.lower().startswith('server:')
+        ]
+
+
+create = gunicorn.http.wsgi.create
+
+
+def create_with_header_lines(req, *args, **kwargs):
+    resp, environ = create(req, *args, **kwargs)
+    # the HTTP_* keys join repeated headers into one, captures keep each line
+    environ['requestrepo.header_lines'] = [
+        (name.title(), value) for name, value in req.headers
+    ]
+    return resp, environ
+
+
+def on_starting(server):
+    gunicorn.http.wsgi.Response = Response
+    gunicorn.http.wsgi.create = create_with_header_lines
@@ -0,0 +1,76 @@
+import http.client
+import os
+import socket
+import struct
+import tempfile
+import threading
+import time
+
+# host:port of the DNS server to probe; unset skips the check
+DNS_HEALTH_ADDRESS = os.getenv('DNS_HEALTH_ADDRESS')
+# seconds a readiness result is reused, so probes can't be used to hammer
+# mongo and the DNS server
+READY_CACHE_TTL = int(os.getenv('READY_CACHE_TTL', '5'))
+
+ready_cache = {'at': 0, 'result': None}
+ready_lock = threading.Lock()
+
+
+def check_dns(name):
+    host, _, port = DNS_HEALTH_ADDRESS.rpartition(':')
+    query_id = struct.unpack('>H', os.urandom(2))[0]
+    question = b''.join(
+        bytes([len(label)]) + label.encode()
+        for label in name.split('.')) + b'\x00'
+    packet = struct.pack('>HHHHHH', query_id, 0x0100, 1, 0, 0,
+                         0) + question + struct.pack('>HH', 1, 1)
+
+    with socket.socket(socket.AF_INET, socket.SOCK_DGRAM) as sock:
+        sock.settimeout(2)
+        sock.sendto(packet, (host, int(port)))
+        response = sock.recv(512)
+    return len(response) >= 12 and struct.unpack('>H', response[:2])[0] == query_id
+
+
+def probe_http(address, host, path):
+    # any answer will do, what matters is that the request got captured
+    hostname, _, port = address.rpartition(':')
+    conn = http.client.HTTPConnection(hostname, int(port), timeout=2)
+    try:
+        conn.request('GET',
+                     path,
+                     headers={
+                         'Host': host,
+                         'User-Agent': 'requestrepo-selftest'
+                     })
+        conn.getresponse().read()
+    finally:
+        conn.close()
+    return True
+
+
+def check_pages():
+    # a unique name per probe, concurrent checks must not trip over each other
+    with tempfile.NamedTemporaryFile(dir='pages', prefix='.health-') as outfile:
+        outfile.write(b'ok')
+    return True
+
+
+def run_checks(checks):
+    components = {}
+    for name, check in checks.items():
+        try:
+            components[name] = 'ok' if check() else 'failing'
+        except Exception as ex:
+            components[name] = 'failing: ' + str(ex)
+    ready = all(status == 'ok' for status in components.values())
+    return ready, components
+
+
+def run_checks_cached(checks):
+    with ready_lock:
+        if ready_cache['result'] is None or \
+                time.time() - ready_cache['at'] >= READY_CACHE_TTL:
+            ready_cache['result'] = run_checks(checks)
+            ready_cache['at'] = time.time()
+        return ready_cache['result']
@@ -0,0 +1,68 @@
+import logging
+import os
+import queue
+import threading
+
+# captures are stored after their response is sent, by a few threads in each
+# worker process; when mongo falls behind new captures are dropped instead of
+# holding up responses
+INGEST_THREADS = int(os.getenv('INGEST_THREADS', '4'))
+INGEST_QUEUE_SIZE = int(os.getenv('INGEST_QUEUE_SIZE', '10000'))
+# a dropped capture is logged once every this many
+INGEST_DROP_LOG_EVERY = 1000
+
+log = logging.getLogger(__name__)
+
+
+class IngestQueue:
+    def __init__(self, threads, size):
+        self.queue = queue.Queue(maxsize=size)
+        self.threads = threads
+        self.started = False
+        self.dropped = 0
+        self.lock = threading.Lock()
+        self.local = threading.local()
+
+    def submit(self, subdomain, f, *args, **kwargs):
+        with self.lock:
+            if not self.started:
+                for _ in range(self.threads):
+                    threading.Thread(target=self.run, daemon=True).start()
+                self.started = True
+        try:
+            self.queue.put_nowait((subdomain, f, args, kwargs))
+        except queue.Full:
+            with self.lock:
+                self.dropped += 1
+                dropped = self.dropped
+            if dropped % INGEST_DROP_LOG_EVERY == 1:
+                log.warning(f'ingest queue full, {dropped} captures dropped')
+            return False
+        return True
+
+    def run(self):
+        while True:
+            subdomain, f, args, kwargs = self.queue.get()
+            self.local.subdomain = subdomain
+            try:
+                f(*args, **kwargs)
+            except Exception as ex:
+                log.error(f'ingest job failed: {ex}')
+            finally:
+                self.local.subdomain = None
+
+    def current_subdomain(self):
+        # the session whose capture the calling thread is storing
+        return getattr(self.local, 'subdomain', None)
+
+    def status(self):
+        return {
+            'queued': self.queue.qsize(),
+            'capacity': self.queue.maxsize,
+            'threads': self.threads,
+            'dropped': self.dropped,
+            'pid': os.getpid(),
+        }
+
+
+ingest_queue = IngestQueue(INGEST_THREADS, INGEST_QUEUE_SIZE)
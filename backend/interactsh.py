@@ -0,0 +1,56 @@
+import base64
+import json
+import os
+from cryptography.hazmat.primitives import hashes, serialization
+from cryptography.hazmat.primitives.asymmetric import padding
+from cryptography.hazmat.primitives.ciphers import Cipher, algorithms
+try:
+    from cryptography.hazmat.decrepit.ciphers.modes import CFB
+except ImportError:
+    from cryptography.hazmat.primitives.ciphers.modes import CFB
+
+# the poll protocol of projectdiscovery/interactsh, so nuclei and the
+# interactsh client can use this instance as their OAST server
+INTERACTSH = os.getenv('INTERACTSH', 'false').lower() == 'true'
+# clients send it in the Authorization header when set
+INTERACTSH_TOKEN = os.getenv('INTERACTSH_TOKEN')
+CORRELATION_ID_LENGTH = 20
+NONCE_LENGTH = 13
+
+OAEP = padding.OAEP(mgf=padding.MGF1(algorithm=hashes.SHA256()),
+                    algorithm=hashes.SHA256(),
+                    label=None)
+
+
+def get_correlation_id(host, domain):
+    host = host.split(':')[0].lower().rstrip('.')
+    if not host.endswith('.' + domain):
+        return None, None
+    for label in host[:-len(domain) - 1].split('.'):
+        if len(label) == CORRELATION_ID_LENGTH + NONCE_LENGTH and \
+                label.isalnum():
+            return label[:CORRELATION_ID_LENGTH], label
+    return None, None
+
+
+def load_public_key(public_key):
+    try:
+        return serialization.load_pem_public_key(base64.b64decode(public_key))
+    except (ValueError, TypeError):
+        return None
+
+
+def encrypt_interactions(public_key, interactions):
+    key = os.urandom(32)
+    aes_key = load_public_key(public_key).encrypt(key, OAEP)
+
+    data = []
+    for interaction in interactions:
+        iv = os.urandom(16)
+        encryptor = Cipher(algorithms.AES(key), CFB(iv)).encryptor()
+        data.append(
+            str(
+                base64.b64encode(iv + encryptor.update(
+                    json.dumps(interaction).encode()) + encryptor.finalize()),
+                'utf-8'))
+    return str(base64.b64encode(aes_key), 'utf-8'), data
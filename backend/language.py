@@ -0,0 +1,46 @@
+import re
+
+LANGUAGE_TAG = re.compile('^[a-z]{1,8}(-[a-z0-9]{1,8})*$')
+MAX_VARIANTS = 10
+
+
+def valid_language(tag):
+    return type(tag) is str and LANGUAGE_TAG.match(tag) is not None
+
+
+def parse_accept_language(header):
+    accepted = []
+    for item in header.split(','):
+        parts = item.strip().split(';')
+        tag = parts[0].strip().lower()
+        if not tag:
+            continue
+        q = 1.0
+        for param in parts[1:]:
+            key, _, value = param.strip().partition('=')
+            if key == 'q':
+                try:
+                    q = float(value)
+                except ValueError:
+                    q = 0.0
+        if q > 0:
+            accepted.append((tag, q))
+    # stable sort keeps the client's order between equal weights
+    accepted.sort(key=lambda x: -x[1])
+    return [tag for tag, _ in accepted]
+
+
+def negotiate_language(header, available):
+    for tag in parse_accept_language(header):
+        if tag == '*':
+            return None
+        if tag in available:
+            return tag
+        # de-AT falls back to de, and de to the first de-* variant
+        primary = tag.split('-')[0]
+        if primary in available:
+            return primary
+        for variant in available:
+            if variant.split('-')[0] == primary:
+                return variant
+    return None
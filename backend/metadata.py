@@ -0,0 +1,208 @@
+import base64
+import datetime
+import json
+import os
+import random
+import re
+import string
+
+# fake AWS, Azure and GCP instance metadata for SSRF testing; the credentials
+# handed out are canaries that raise an alert when they show up in a capture
+DEFAULT_ROLE = 'ec2-instance-role'
+
+CANARY_PATTERNS = [
+    re.compile(r'\b(?:AKIA|ASIA)[0-9A-Z]{16}\b'),
+    re.compile(r'\bya29\.[A-Za-z0-9_-]{20,}'),
+    re.compile(r'\beyJ[A-Za-z0-9_-]{5,}\.eyJ[A-Za-z0-9_-]{5,}\.[A-Za-z0-9_-]*'),
+]
+
+AWS_META_DATA = {
+    'ami-id': 'ami-0c55b159cbfafe1f0',
+    'hostname': 'ip-172-31-16-42.ec2.internal',
+    'instance-id': 'i-0a1b2c3d4e5f67890',
+    'instance-type': 't3.micro',
+    'local-hostname': 'ip-172-31-16-42.ec2.internal',
+    'local-ipv4': '172.31.16.42',
+    'mac': '0e:3a:4b:5c:6d:7e',
+    'placement/availability-zone': 'us-east-1a',
+    'placement/region': 'us-east-1',
+    'public-ipv4': '54.210.18.7',
+}
+
+
+def random_string(alphabet, length):
+    return ''.join(random.choices(alphabet, k=length))
+
+
+def b64url(data):
+    return str(base64.urlsafe_b64encode(data), 'utf-8').rstrip('=')
+
+
+def generate_canaries():
+    header = b64url(json.dumps({'typ': 'JWT', 'alg': 'RS256'}).encode())
+    claims = b64url(
+        json.dumps({
+            'aud': 'https://management.azure.com/',
+            'iss': 'https://sts.windows.net/' + os.urandom(16).hex() + '/',
+            'oid': os.urandom(16).hex()
+        }).encode())
+    return {
+        'aws_access_key_id':
+        'ASIA' + random_string(string.ascii_uppercase + string.digits, 16),
+        'aws_secret_access_key':
+        random_string(string.ascii_letters + string.digits + '/+', 40),
+        'aws_session_token':
+        str(base64.b64encode(os.urandom(96)), 'utf-8'),
+        'azure_access_token':
+        f'{header}.{claims}.{b64url(os.urandom(64))}',
+        'gcp_access_token':
+        'ya29.' + b64url(os.urandom(48)),
+    }
+
+
+def canary_values(canaries):
+    # the values a capture is matched against
+    return [
+        canaries['aws_access_key_id'], canaries['azure_access_token'],
+        canaries['gcp_access_token']
+    ]
+
+
+def find_canary_candidates(text):
+    candidates = set()
+    for pattern in CANARY_PATTERNS:
+        candidates.update(pattern.findall(text))
+    return candidates
+
+
+def iso_date(date):
+    return date.strftime('%Y-%m-%dT%H:%M:%SZ')
+
+
+def aws_response(path, method, settings):
+    canaries = settings['canaries']
+    role = settings.get('role', DEFAULT_ROLE)
+
+    if path == '/latest/api/token':
+        if method != 'PUT':
+            return None
+        return 'AQAEA' + b64url(os.urandom(40)), 'text/plain'
+
+    if path in ('/latest', '/latest/'):
+        return 'dynamic\nmeta-data\nuser-data', 'text/plain'
+    if path == '/latest/dynamic/instance-identity/document':
+        return json.dumps(
+            {
+                'accountId': '123456789012',
+                'architecture': 'x86_64',
+                'availabilityZone': AWS_META_DATA['placement/availability-zone'],
+                'imageId': AWS_META_DATA['ami-id'],
+                'instanceId': AWS_META_DATA['instance-id'],
+                'instanceType': AWS_META_DATA['instance-type'],
+                'privateIp': AWS_META_DATA['local-ipv4'],
+                'region': AWS_META_DATA['placement/region'],
+            },
+            indent=2), 'application/json'
+
+    prefix = '/latest/meta-data/'
+    if not path.startswith(prefix) and path != prefix[:-1]:
+        return None
+    key = path[len(prefix):].rstrip('/')
+
+    if key == '':
+        entries = sorted(
+            set(x.split('/')[0] + ('/' if '/' in x else '')
+                for x in AWS_META_DATA) | {'iam/'})
+        return '\n'.join(entries), 'text/plain'
+    if key in AWS_META_DATA:
+        return AWS_META_DATA[key], 'text/plain'
+    if key == 'placement':
+        return 'availability-zone\nregion', 'text/plain'
+    if key == 'iam':
+        return 'info\nsecurity-credentials/', 'text/plain'
+    if key == 'iam/security-credentials':
+        return role, 'text/plain'
+    if key == 'iam/security-credentials/' + role:
+        now = datetime.datetime.now(datetime.timezone.utc)
+        return json.dumps(
+            {
+                'Code': 'Success',
+                'LastUpdated': iso_date(now),
+                'Type': 'AWS-HMAC',
+                'AccessKeyId': canaries['aws_access_key_id'],
+                'SecretAccessKey': canaries['aws_secret_access_key'],
+                'Token': canaries['aws_session_token'],
+                'Expiration': iso_date(now + datetime.timedelta(hours=6)),
+            },
+            indent=2), 'text/plain'
+    return None
+
+
+def azure_response(path, headers, settings):
+    if headers.get('Metadata', '').lower() != 'true':
+        return None
+
+    if path.rstrip('/') == '/metadata/instance':
+        return json.dumps({
+            'compute': {
+                'location': 'eastus',
+                'name': 'vm-prod-01',
+                'osType': 'Linux',
+                'resourceGroupName': 'rg-prod',
+                'subscriptionId': '8d5b1c3e-2f4a-4b6c-9d7e-1a2b3c4d5e6f',
+                'vmId': '02aab8a4-74ef-476e-8182-f6d2ba4166a6',
+                'vmSize': 'Standard_B1s'
+            },
+            'network': {
+                'interface': [{
+                    'ipv4': {
+                        'ipAddress': [{
+                            'privateIpAddress': '10.0.0.4',
+                            'publicIpAddress': '20.62.134.11'
+                        }]
+                    }
+                }]
+            }
+        }), 'application/json'
+    if path.rstrip('/') == '/metadata/identity/oauth2/token':
+        now = int(datetime.datetime.now(datetime.timezone.utc).timestamp())
+        return json.dumps({
+            'access_token': settings['canaries']['azure_access_token'],
+            'client_id': '5e8bd5f1-6b1e-4c2e-9a7d-3f2b1c0d9e8a',
+            'expires_in': '86399',
+            'expires_on': str(now + 86399),
+            'not_before': str(now),
+            'resource': 'https://management.azure.com/',
+            'token_type': 'Bearer'
+        }), 'application/json'
+    return None
+
+
+def gcp_response(path, headers, settings):
+    if headers.get('Metadata-Flavor', '') != 'Google':
+        return None
+
+    prefix = '/computeMetadata/v1/'
+    if path == prefix + 'instance/service-accounts/default/token':
+        return json.dumps({
+            'access_token': settings['canaries']['gcp_access_token'],
+            'expires_in': 3599,
+            'token_type': 'Bearer'
+        }), 'application/json'
+    if path == prefix + 'instance/service-accounts/default/email':
+        return 'default@prod-project.iam.gserviceaccount.com', 'text/plain'
+    if path == prefix + 'project/project-id':
+        return 'prod-project', 'text/plain'
+    if path == prefix + 'instance/hostname':
+        return 'vm-prod-01.c.prod-project.internal', 'text/plain'
+    return None
+
+
+def metadata_response(path, method, headers, settings):
+    if path.startswith('/latest'):
+        return aws_response(path, method, settings)
+    if path.startswith('/metadata/'):
+        return azure_response(path, headers, settings)
+    if path.startswith('/computeMetadata/'):
+        return gcp_response(path, headers, settings)
+    return None
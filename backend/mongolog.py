@@ -1,9 +1,13 @@
 import os
 import pymongo
+from pymongo.errors import BulkWriteError, DuplicateKeyError
+from pymongo import ReturnDocument, UpdateOne
 from bson.objectid import ObjectId
 import urllib.parse
 import base64
+import re
 import datetime
+from settings import setting
 
 if 'MONGODB_DATABASE' in os.environ:
     MONGODB_DATABASE = os.environ['MONGODB_DATABASE']
@@ -32,12 +36,27 @@ client = pymongo.MongoClient(
     'mongodb://%s:%s@%s' % (username, password, MONGODB_HOSTNAME), 27017)
 db = client[MONGODB_DATABASE]
 
+def now():
+    return int(datetime.datetime.now(datetime.timezone.utc).timestamp())
+
+
+def purge_date():
+    return datetime.datetime.now(datetime.timezone.utc) + datetime.timedelta(
+        days=setting('TRASH_RETENTION_DAYS'))
+
+
+def ping():
+    return client.admin.command('ping').get('ok') == 1
+
+
 # DNS Database
 collection = db['dns_requests']
 ddns = db['ddns']
 
 # create indexes
 collection.create_index([('uid', 1), ('_deleted', 1), ('date', 1)], background=True)
+# deleted requests stay in the trash until mongo expires them
+collection.create_index([('_purge_at', 1)], expireAfterSeconds=0, background=True)
 
 
 
@@ -52,25 +71,80 @@ def dns_get_from_db():
 
 def dns_get_records(subdomain):
     l = []
-    for x in ddns.find({'subdomain': subdomain}):
+    for x in ddns.find({'subdomain': subdomain, 'config': {'$ne': True}}):
         x['_id'] = str(x['_id'])
         l.append(x)
     return l
 
 
 def dns_delete_records(subdomain):
-    ddns.delete_many({'subdomain': subdomain})
+    ddns.delete_many({'subdomain': subdomain, 'config': {'$ne': True}})
 
 
-def dns_insert_record(subdomain, domain, dtype, val):
-    ddns.insert_one({
+def dns_insert_record(subdomain, domain, dtype, val, ttl=None):
+    record = {
         'subdomain': subdomain,
         'domain': domain,
         'type': dtype,
         'value': val
+    }
+    if ttl is not None:
+        record['ttl'] = ttl
+    ddns.insert_one(record)
+
+
+def dns_delete_record(subdomain, domain, dtype):
+    ddns.delete_many({
+        'subdomain': subdomain,
+        'domain': domain,
+        'type': dtype,
+        'config': {
+            '$ne': True
+        }
+    })
+
+
+def dns_get_config_records(subdomain):
+    l = []
+    for x in ddns.find({'subdomain': subdomain, 'config': True}):
+        x['_id'] = str(x['_id'])
+        l.append(x)
+    return l
+
+
+def dns_delete_config_records(subdomain):
+    ddns.delete_many({'subdomain': subdomain, 'config': True})
+
+
+def dns_insert_config_record(subdomain, domain, val):
+    ddns.insert_one({
+        'subdomain': subdomain,
+        'domain': domain,
+        'type': 'TXT',
+        'value': val,
+        'config': True
     })
 
 
+dns_settings = db['dns_settings']
+
+
+def dns_get_settings(subdomain):
+    return dns_settings.find_one({'subdomain': subdomain}, {
+        '_id': False,
+        'subdomain': False
+    })
+
+
+def dns_set_settings(subdomain, settings):
+    dns_settings.update_one({'subdomain': subdomain}, {'$set': settings},
+                            upsert=True)
+
+
+def dns_delete_settings(subdomain):
+    dns_settings.delete_many({'subdomain': subdomain})
+
+
 def dns_get_subdomain(subdomain, time):
     l = []
 
@@ -89,7 +163,7 @@ def dns_get_subdomain(subdomain, time):
 
 
 def dns_delete_records(subdomain):
-    ddns.delete_many({'subdomain': subdomain})
+    ddns.delete_many({'subdomain': subdomain, 'config': {'$ne': True}})
 
 
 def dns_delete_request(_id, subdomain):
@@ -97,7 +171,9 @@ def dns_delete_request(_id, subdomain):
         'uid': subdomain,
         '_id': ObjectId(_id)
     }, {'$set': {
-        '_deleted': True
+        '_deleted': True,
+        '_deleted_date': now(),
+        '_purge_at': purge_date()
     }})
 
 
@@ -105,6 +181,10 @@ def dns_delete_request(_id, subdomain):
 
 http = db['http']
 http.create_index([('uid', 1), ('_deleted', 1), ('date', 1)], background=True)
+http.create_index([('_purge_at', 1)], expireAfterSeconds=0, background=True)
+http.create_index([('uid', 1), ('collapse_key', 1), ('last', 1)],
+                  background=True,
+                  sparse=True)
 
 
 def http_insert_into_db(dic):
@@ -121,7 +201,7 @@ def http_get_from_db():
     return l
 
 
-def http_get_subdomain(subdomain, time):
+def http_get_subdomain(subdomain, time, trace_id=None):
     l = []
 
     find = {'uid': subdomain, '_deleted': False}
@@ -130,6 +210,8 @@ def http_get_subdomain(subdomain, time):
             find['date'] = {'$gte': time}
     except:
         pass
+    if trace_id:
+        find['trace.trace_id'] = trace_id.lower()
 
     #for x in http.find(find, {'_id': False}):
     for x in http.find(find, {'_deleted': False}):
@@ -139,12 +221,86 @@ def http_get_subdomain(subdomain, time):
     return l
 
 
+def requests_search(subdomain, rtype, filters, limit):
+    l = []
+    find = dict(filters, uid=subdomain, _deleted=False)
+    db_collection = http if rtype == 'HTTP' else collection
+    for x in db_collection.find(find, {'_deleted': False},
+                                sort=[('date', -1)],
+                                limit=limit):
+        x['_id'] = str(x['_id'])
+        x['raw'] = str(base64.b64encode(x['raw']), 'utf-8')
+        l.append(x)
+    return l
+
+
+def http_get_findings(subdomain, time):
+    l = []
+    find = {'uid': subdomain, '_deleted': False, 'has_findings': True}
+    if time != None:
+        find['date'] = {'$gte': time}
+    for x in http.find(find, {'_deleted': False}, sort=[('date', -1)]):
+        x['_id'] = str(x['_id'])
+        x['raw'] = str(base64.b64encode(x['raw']), 'utf-8')
+        l.append(x)
+    return l
+
+
+def http_collapse(subdomain, collapse_key, date, window):
+    # counts the request on an identical capture still inside the window
+    return http.find_one_and_update(
+        {
+            'uid': subdomain,
+            'collapse_key': collapse_key,
+            'last': {'$gte': date - window},
+            '_deleted': False
+        }, {
+            '$inc': {'count': 1},
+            '$set': {'last': date}
+        },
+        projection={'_id': True}) is not None
+
+
+def http_get_request(_id, subdomain):
+    return http.find_one({
+        '_id': ObjectId(_id),
+        'uid': subdomain,
+        '_deleted': False
+    })
+
+
+def http_get_collapsed_since(subdomain, time):
+    # collapsed captures keep their date, only count and last move
+    l = []
+    find = {
+        'uid': subdomain,
+        '_deleted': False,
+        'last': {'$gte': time},
+        'count': {'$gt': 1}
+    }
+    for x in http.find(find, {'_id': True, 'count': True, 'last': True}):
+        x['_id'] = str(x['_id'])
+        l.append(x)
+    return l
+
+
+def http_get_last_received(subdomain, ip):
+    last = http.find_one({'uid': subdomain, 'ip': ip},
+                         sort=[('date', -1), ('_id', -1)])
+    if last is None:
+        return None
+    # captures stored before 'received' existed only have whole seconds
+    return last.get('received', last['date'])
+
+
 def http_delete_request(_id, subdomain):
     http.update_one({
         '_id': ObjectId(_id),
         'uid': subdomain
     }, {'$set': {
-        '_deleted': True
+        '_deleted': True,
+        '_deleted_date': now(),
+        '_purge_at': purge_date()
     }})
 
 
@@ -154,13 +310,1129 @@ users = db['users']
 
 
 def users_insert_into_db(ip, subdomain):
-    collection.insert_one({'ip': ip, 'subdomain': subdomain})
+    # a subdomain claimed with users_claim already has its document
+    users.update_one({'subdomain': subdomain},
+                     {'$setOnInsert': {'ip': ip}},
+                     upsert=True)
+
+
+def users_claim(ip, subdomain):
+    # false when the subdomain already belongs to a session
+    result = users.update_one({'subdomain': subdomain},
+                              {'$setOnInsert': {'ip': ip}},
+                              upsert=True)
+    return result.upserted_id is not None
 
 
 def users_get_subdomain(subdomain):
     return users.find_one({'subdomain': subdomain})
 
 
+def users_set_ip_pinning(subdomain, enabled, allowed_ips):
+    settings = {'ip_pinning': enabled, 'allowed_ips': allowed_ips}
+    users.update_one({'subdomain': subdomain}, {'$set': settings},
+                     upsert=True)
+
+
+def get_deleted_since(subdomain, time):
+    l = []
+    find = {'uid': subdomain, '_deleted': True, '_deleted_date': {'$gte': time}}
+    for x in http.find(find, {'_id': True}):
+        l.append({'_id': str(x['_id']), 'type': 'HTTP'})
+    for x in collection.find(find, {'_id': True}):
+        l.append({'_id': str(x['_id']), 'type': 'DNS'})
+    return l
+
+
+def requests_sync(subdomain, since_id, limit):
+    # captures added after the cursor, and older ones changed since it was made
+    created = []
+    changed = []
+    find = {'uid': subdomain, '_deleted': False}
+    for rtype, db_collection in (('HTTP', http), ('DNS', collection)):
+        new = dict(find)
+        if since_id is not None:
+            new['_id'] = {'$gt': since_id}
+        for x in db_collection.find(new, {'_deleted': False},
+                                    sort=[('_id', 1)],
+                                    limit=limit):
+            created.append((rtype, x))
+        if since_id is None:
+            continue
+
+        date = int(since_id.generation_time.timestamp())
+        updated = dict(find, _id={'$lte': since_id})
+        updated['$or'] = [{
+            field: {
+                '$gte': date
+            }
+        } for field in ('_annotated_date', '_restored_date', 'last')]
+        for x in db_collection.find(updated, {'_deleted': False}):
+            changed.append((rtype, x))
+
+    for _, x in created + changed:
+        x['_id'] = str(x['_id'])
+        x['raw'] = str(base64.b64encode(x['raw']), 'utf-8')
+    return created, changed
+
+
+def annotate_request(_id, subdomain, annotation):
+    find = {'_id': ObjectId(_id), 'uid': subdomain, '_deleted': False}
+    update = {
+        '$push': {
+            'annotations': annotation
+        },
+        '$set': {
+            '_annotated_date': annotation['date']
+        }
+    }
+    if http.update_one(find, update).matched_count:
+        return 'HTTP'
+    if collection.update_one(find, update).matched_count:
+        return 'DNS'
+    return None
+
+
+def get_annotated_since(subdomain, time):
+    l = []
+    find = {
+        'uid': subdomain,
+        '_deleted': False,
+        '_annotated_date': {
+            '$gte': time
+        }
+    }
+    projection = {'_id': True, 'annotations': True}
+    for rtype, db_collection in (('HTTP', http), ('DNS', collection)):
+        for x in db_collection.find(find, projection):
+            l.append({
+                '_id': str(x['_id']),
+                'type': rtype,
+                'annotations': x['annotations']
+            })
+    return l
+
+
+# Aliases database
+
+aliases = db['aliases']
+aliases.create_index([('alias', 1)], unique=True, background=True)
+
+
+def aliases_get(alias):
+    return aliases.find_one({'alias': alias})
+
+
+def aliases_get_subdomain(subdomain):
+    return aliases.find_one({'subdomain': subdomain})
+
+
+def aliases_set(subdomain, alias):
+    aliases.delete_many({'subdomain': subdomain})
+    aliases.insert_one({'alias': alias, 'subdomain': subdomain})
+
+
+def aliases_delete(subdomain):
+    aliases.delete_many({'subdomain': subdomain})
+
+
+# Payloads database
+
+payloads = db['payloads']
+payloads.create_index([('cid', 1)], unique=True, background=True)
+payload_counters = db['payload_counters']
+payload_counters.create_index([('subdomain', 1)], unique=True, background=True)
+
+
+def payloads_insert(subdomain, cid, kind, note):
+    payloads.insert_one({
+        'subdomain': subdomain,
+        'cid': cid,
+        'kind': kind,
+        'note': note,
+        'created_at': now()
+    })
+
+
+def payloads_insert_many(subdomain, cids, kind, note):
+    date = now()
+    try:
+        documents = [{
+            'subdomain': subdomain,
+            'cid': cid,
+            'kind': kind,
+            'note': note,
+            'created_at': date
+        } for cid in cids]
+        payloads.insert_many(documents, ordered=False)
+    except BulkWriteError as ex:
+        failed = {error['op']['cid'] for error in ex.details['writeErrors']}
+        return [cid for cid in cids if cid not in failed]
+    return cids
+
+
+def payloads_get(cid):
+    return payloads.find_one({'cid': cid})
+
+
+def payloads_match_hits(subdomain, candidates, date):
+    cids = [
+        x['cid'] for x in payloads.find(
+            {
+                'subdomain': subdomain,
+                'cid': {
+                    '$in': candidates
+                }
+            }, {'cid': True})
+    ]
+    if cids:
+        payloads.update_many({'cid': {
+            '$in': cids
+        }}, {
+            '$min': {
+                'first_hit': date
+            },
+            '$max': {
+                'last_hit': date
+            },
+            '$inc': {
+                'hits': 1
+            }
+        })
+    return cids
+
+
+def payloads_delete(subdomain, cid):
+    return payloads.delete_one({
+        'subdomain': subdomain,
+        'cid': cid
+    }).deleted_count > 0
+
+
+def payloads_reserve_counter(subdomain, prefix, count, limit):
+    # returns the prefix and the first counter of the reserved range; a range
+    # that would run past the limit starts over at 0 under the new prefix,
+    # in the same update so concurrent requests never share a range
+    current = {'$ifNull': ['$counter', 0]}
+    wraps = {'$gt': [{'$add': [current, count]}, limit]}
+    counter = payload_counters.find_one_and_update(
+        {'subdomain': subdomain}, [{
+            '$set': {
+                'prefix': {
+                    '$cond': [wraps, prefix, {'$ifNull': ['$prefix', prefix]}]
+                },
+                'counter': {
+                    '$cond': [wraps, count, {'$add': [current, count]}]
+                }
+            }
+        }],
+        upsert=True,
+        return_document=ReturnDocument.AFTER)
+    return counter['prefix'], counter['counter'] - count
+
+
+def payloads_get_hits(subdomain, cid, time=None):
+    l = []
+    find = {'uid': subdomain, '_deleted': False, 'correlation_ids': cid}
+    if time != None:
+        find['date'] = {'$gte': time}
+    for x in http.find(find, {'_deleted': False}):
+        x['_id'] = str(x['_id'])
+        x['raw'] = str(base64.b64encode(x['raw']), 'utf-8')
+        x['rtype'] = 'HTTP'
+        l.append(x)
+    for x in collection.find(find, {'_deleted': False}):
+        x['_id'] = str(x['_id'])
+        x['raw'] = str(base64.b64encode(x['raw']), 'utf-8')
+        x['rtype'] = 'DNS'
+        l.append(x)
+    return l
+
+
+# Cloud metadata simulation database
+
+metadata_settings = db['metadata_settings']
+metadata_settings.create_index([('subdomain', 1)], unique=True, background=True)
+metadata_settings.create_index([('values', 1)], background=True)
+
+
+def metadata_get_settings(subdomain):
+    return metadata_settings.find_one({'subdomain': subdomain}, {
+        '_id': False,
+        'subdomain': False,
+        'values': False
+    })
+
+
+def metadata_set_settings(subdomain, settings, values):
+    metadata_settings.update_one({'subdomain': subdomain},
+                                 {'$set': dict(settings, values=values)},
+                                 upsert=True)
+
+
+def metadata_delete(subdomain):
+    metadata_settings.delete_many({'subdomain': subdomain})
+
+
+def metadata_find_canaries(candidates):
+    return list(
+        metadata_settings.find({'values': {
+            '$in': list(candidates)
+        }}, {
+            '_id': False,
+            'subdomain': True,
+            'values': True
+        }))
+
+
+# S3 emulation database
+
+s3_settings = db['s3_settings']
+s3_objects = db['s3_objects']
+s3_objects.create_index([('subdomain', 1), ('key', 1)],
+                        unique=True,
+                        background=True)
+
+
+def s3_get_settings(subdomain):
+    return s3_settings.find_one({'subdomain': subdomain}, {
+        '_id': False,
+        'subdomain': False
+    })
+
+
+def s3_set_settings(subdomain, settings):
+    s3_settings.update_one({'subdomain': subdomain}, {'$set': settings},
+                           upsert=True)
+
+
+def s3_delete(subdomain):
+    s3_settings.delete_many({'subdomain': subdomain})
+    s3_objects.delete_many({'subdomain': subdomain})
+
+
+def s3_put_object(subdomain, key, data, content_type, etag):
+    s3_objects.update_one(
+        {'subdomain': subdomain, 'key': key},
+        {'$set': {
+            'data': data,
+            'content_type': content_type,
+            'etag': etag,
+            'size': len(data),
+            'last_modified': now()
+        }},
+        upsert=True)
+
+
+def s3_get_object(subdomain, key):
+    return s3_objects.find_one({'subdomain': subdomain, 'key': key})
+
+
+def s3_delete_object(subdomain, key):
+    s3_objects.delete_one({'subdomain': subdomain, 'key': key})
+
+
+def s3_count_objects(subdomain):
+    return s3_objects.count_documents({'subdomain': subdomain})
+
+
+def s3_list_objects(subdomain, prefix, limit):
+    find = {'subdomain': subdomain}
+    if prefix:
+        find['key'] = {'$regex': '^' + re.escape(prefix)}
+    return list(
+        s3_objects.find(find, {'data': False},
+                        sort=[('key', 1)],
+                        limit=limit))
+
+
+# Statistics database
+
+# one counter per subdomain, kind and key, incremented as captures arrive
+stats = db['stats']
+stats.create_index([('subdomain', 1), ('kind', 1), ('key', 1)],
+                   unique=True,
+                   background=True)
+stats.create_index([('subdomain', 1), ('kind', 1), ('count', -1)],
+                   background=True)
+
+
+def stats_record(subdomain, counters):
+    requests = [
+        UpdateOne({'subdomain': subdomain, 'kind': kind, 'key': key},
+                  {'$inc': {'count': 1}},
+                  upsert=True) for kind, key in counters
+    ]
+    stats.bulk_write(requests, ordered=False)
+
+
+def stats_get(subdomain, kind, limit=0):
+    find = {'subdomain': subdomain, 'kind': kind}
+    return [{
+        'key': x['key'],
+        'count': x['count']
+    } for x in stats.find(find, sort=[('count', -1)], limit=limit)]
+
+
+def stats_delete(subdomain):
+    stats.delete_many({'subdomain': subdomain})
+
+
+# Capture errors database
+
+ERRORS_MAX_RESULTS = 500
+
+capture_errors = db['capture_errors']
+capture_errors.create_index([('subdomain', 1), ('date', 1)], background=True)
+capture_errors.create_index([('_purge_at', 1)],
+                            expireAfterSeconds=0,
+                            background=True)
+
+
+def errors_insert(subdomain, source, step, error):
+    capture_errors.insert_one({
+        'subdomain': subdomain,
+        'source': source,
+        'step': step,
+        'error': error,
+        'date': now(),
+        '_purge_at': datetime.datetime.now(datetime.timezone.utc) +
+        datetime.timedelta(days=setting('ERRORS_RETENTION_DAYS'))
+    })
+
+
+def errors_get(subdomain, time):
+    # no subdomain returns the instance-wide feed
+    find = {}
+    if subdomain is not None:
+        find['subdomain'] = subdomain
+    if time != None:
+        find['date'] = {'$gte': time}
+    l = []
+    for x in capture_errors.find(find, {'_purge_at': False},
+                                 sort=[('date', -1)],
+                                 limit=ERRORS_MAX_RESULTS):
+        x['_id'] = str(x['_id'])
+        l.append(x)
+    return l
+
+
+# Redirect chains database
+
+redirect_chains = db['redirect_chains']
+redirect_chains.create_index([('subdomain', 1), ('name', 1)],
+                             unique=True,
+                             background=True)
+
+
+def redirect_chains_get(subdomain, name=None):
+    if name is not None:
+        return redirect_chains.find_one({
+            'subdomain': subdomain,
+            'name': name
+        }, {
+            '_id': False,
+            'subdomain': False
+        })
+    return list(
+        redirect_chains.find({'subdomain': subdomain}, {
+            '_id': False,
+            'subdomain': False
+        }))
+
+
+def redirect_chains_set(subdomain, name, hops):
+    redirect_chains.update_one(
+        {'subdomain': subdomain, 'name': name},
+        {'$set': {'hops': hops}},
+        upsert=True)
+
+
+def redirect_chains_delete(subdomain, name=None):
+    find = {'subdomain': subdomain}
+    if name is not None:
+        find['name'] = name
+    return redirect_chains.delete_many(find).deleted_count > 0
+
+
+# Crawler directives database
+
+crawl_settings = db['crawl_settings']
+crawl_settings.create_index([('subdomain', 1)], unique=True, background=True)
+
+
+def crawl_get_settings(subdomain):
+    return crawl_settings.find_one({'subdomain': subdomain}, {
+        '_id': False,
+        'subdomain': False
+    })
+
+
+def crawl_set_settings(subdomain, settings):
+    crawl_settings.update_one({'subdomain': subdomain}, {'$set': settings},
+                              upsert=True)
+
+
+def crawl_delete(subdomain):
+    crawl_settings.delete_many({'subdomain': subdomain})
+
+
+# Server identity database
+
+cloak_settings = db['cloak_settings']
+cloak_settings.create_index([('subdomain', 1)], unique=True, background=True)
+
+
+def cloak_get_settings(subdomain):
+    return cloak_settings.find_one({'subdomain': subdomain}, {
+        '_id': False,
+        'subdomain': False
+    })
+
+
+def cloak_set_settings(subdomain, settings):
+    cloak_settings.update_one({'subdomain': subdomain}, {'$set': settings},
+                              upsert=True)
+
+
+def cloak_delete(subdomain):
+    cloak_settings.delete_many({'subdomain': subdomain})
+
+
+# Storage introspection
+
+
+def storage_collections():
+    # size is the uncompressed data, storage_size what it takes on disk
+    result = []
+    for name in sorted(db.list_collection_names()):
+        stats = db.command('collStats', name)
+        result.append({
+            'collection': name,
+            'documents': stats.get('count', 0),
+            'size': stats.get('size', 0),
+            'storage_size': stats.get('storageSize', 0),
+            'index_size': stats.get('totalIndexSize', 0)
+        })
+    return result
+
+
+def storage_largest_subdomains(limit):
+    return [{
+        'subdomain': x['_id'],
+        'documents': x['documents'],
+        'size': x['size']
+    } for x in http.aggregate([{
+        '$unionWith': collection.name
+    }, {
+        '$group': {
+            '_id': '$uid',
+            'documents': {
+                '$sum': 1
+            },
+            'size': {
+                '$sum': {
+                    '$bsonSize': '$$ROOT'
+                }
+            }
+        }
+    }, {
+        '$sort': {
+            'size': -1
+        }
+    }, {
+        '$limit': limit
+    }])]
+
+
+# DNSSEC keys database
+
+dnssec = db['dnssec']
+
+
+def dnssec_get():
+    # published by the DNS server when it starts with DNSSEC enabled
+    return dnssec.find_one({'_id': 'keys'}, {'_id': False})
+
+
+# Log level overrides database
+
+log_levels = db['log_levels']
+log_levels.create_index([('_purge_at', 1)],
+                        expireAfterSeconds=0,
+                        background=True)
+
+
+def log_levels_get():
+    # mongo removes expired documents only once a minute
+    return log_levels.find_one(
+        {
+            '_id': 'overrides',
+            '_purge_at': {
+                '$gt': datetime.datetime.now(datetime.timezone.utc)
+            }
+        }, {'_id': False})
+
+
+def log_levels_set(levels, ttl):
+    purge_at = datetime.datetime.now(
+        datetime.timezone.utc) + datetime.timedelta(seconds=ttl)
+    log_levels.replace_one(
+        {'_id': 'overrides'}, {
+            'levels': [{'module': k, 'level': v} for k, v in levels.items()],
+            '_purge_at': purge_at
+        },
+        upsert=True)
+
+
+def log_levels_delete():
+    log_levels.delete_many({})
+
+
+# Burst collapsing database
+
+collapse_settings = db['collapse_settings']
+collapse_settings.create_index([('subdomain', 1)], unique=True, background=True)
+
+
+def collapse_get_settings(subdomain):
+    return collapse_settings.find_one({'subdomain': subdomain}, {
+        '_id': False,
+        'subdomain': False
+    })
+
+
+def collapse_set_settings(subdomain, settings):
+    collapse_settings.update_one({'subdomain': subdomain}, {'$set': settings},
+                                 upsert=True)
+
+
+def collapse_delete(subdomain):
+    collapse_settings.delete_many({'subdomain': subdomain})
+
+
+# Capture blocklists database
+
+blocklists = db['blocklists']
+blocklists.create_index([('subdomain', 1)], unique=True, background=True)
+
+
+def blocklist_get(subdomain):
+    return blocklists.find_one({'subdomain': subdomain}, {
+        '_id': False,
+        'subdomain': False
+    })
+
+
+def blocklist_set(subdomain, blocklist):
+    blocklists.update_one({'subdomain': subdomain}, {'$set': blocklist},
+                          upsert=True)
+
+
+def blocklist_delete(subdomain):
+    blocklists.delete_many({'subdomain': subdomain})
+
+
+# Anomaly detection database
+
+anomaly_settings = db['anomaly_settings']
+alerts = db['alerts']
+alerts.create_index([('subdomain', 1), ('date', 1)], background=True)
+
+
+def anomaly_get_settings(subdomain):
+    return anomaly_settings.find_one({'subdomain': subdomain}, {
+        '_id': False,
+        'subdomain': False
+    })
+
+
+def anomaly_set_settings(subdomain, settings):
+    anomaly_settings.update_one({'subdomain': subdomain},
+                                {'$set': settings},
+                                upsert=True)
+
+
+def alerts_insert(subdomain, kind, details):
+    alerts.insert_one({
+        'subdomain': subdomain,
+        'kind': kind,
+        'details': details,
+        'date': now()
+    })
+
+
+def alerts_get_subdomain(subdomain, time):
+    l = []
+    find = {'subdomain': subdomain}
+    if time != None:
+        find['date'] = {'$gte': time}
+    for x in alerts.find(find):
+        x['_id'] = str(x['_id'])
+        l.append(x)
+    return l
+
+
+# Notifications database
+
+notifications = db['notifications']
+
+
+def notifications_get(subdomain):
+    return notifications.find_one({'subdomain': subdomain}, {
+        '_id': False,
+        'subdomain': False,
+        'last_sent': False,
+        'suppressed': False
+    })
+
+
+def notifications_set(subdomain, settings):
+    notifications.update_one({'subdomain': subdomain}, {'$set': settings},
+                             upsert=True)
+
+
+def notifications_delete(subdomain):
+    notifications.delete_many({'subdomain': subdomain})
+
+
+def notifications_claim(subdomain, cooldown):
+    return claim_cooldown(notifications, subdomain, cooldown)
+
+
+def claim_cooldown(db_collection, subdomain, cooldown):
+    date = now()
+    # only one capture per cooldown window wins the right to notify
+    find = {
+        'subdomain': subdomain,
+        '$or': [{'last_sent': {'$exists': False}},
+                {'last_sent': {'$lte': date - cooldown}}]
+    }
+    update = {'$set': {'last_sent': date, 'suppressed': 0}}
+    previous = db_collection.find_one_and_update(
+        find, update, return_document=ReturnDocument.BEFORE)
+    if previous is None:
+        db_collection.update_one({'subdomain': subdomain},
+                                 {'$inc': {'suppressed': 1}})
+        return None
+    return previous.get('suppressed', 0)
+
+
+# Email alerts database
+
+email_alerts = db['email_alerts']
+
+
+email_alerts.create_index([('confirm_token', 1)], sparse=True, background=True)
+
+
+def email_alerts_get(subdomain):
+    return email_alerts.find_one({'subdomain': subdomain}, {
+        '_id': False,
+        'subdomain': False,
+        'last_sent': False,
+        'suppressed': False,
+        'confirm_token': False
+    })
+
+
+def email_alerts_set(subdomain, email, alert_filter, confirm_token=None):
+    update = {
+        '$set': {
+            'email': email,
+            'filter': alert_filter,
+            'confirmed': confirm_token is None
+        }
+    }
+    if confirm_token is None:
+        update['$unset'] = {'confirm_token': ''}
+    else:
+        update['$set']['confirm_token'] = confirm_token
+    email_alerts.update_one({'subdomain': subdomain}, update, upsert=True)
+
+
+def email_alerts_pending(subdomain, email):
+    alert = email_alerts.find_one(
+        {
+            'subdomain': subdomain,
+            'email': email,
+            'confirm_token': {'$exists': True}
+        }, {'confirm_token': True})
+    return alert['confirm_token'] if alert else None
+
+
+def email_alerts_confirm(confirm_token):
+    return email_alerts.find_one_and_update(
+        {'confirm_token': confirm_token}, {
+            '$set': {'confirmed': True},
+            '$unset': {'confirm_token': ''}
+        })
+
+
+def email_alerts_delete(subdomain):
+    email_alerts.delete_many({'subdomain': subdomain})
+
+
+def email_alerts_claim(subdomain, cooldown):
+    return claim_cooldown(email_alerts, subdomain, cooldown)
+
+
+# Verified email addresses database
+
+# addresses whose owner followed a confirmation link, shared by all sessions
+verified_emails = db['verified_emails']
+verified_emails.create_index([('email', 1)], unique=True, background=True)
+
+
+def verified_emails_add(email):
+    verified_emails.update_one({'email': email.lower()},
+                               {'$setOnInsert': {'date': now()}},
+                               upsert=True)
+
+
+def verified_emails_check(email):
+    return verified_emails.find_one({'email': email.lower()}) is not None
+
+
+# Email verifications database
+
+# confirmation links sent to recipients of session emails
+email_verifications = db['email_verifications']
+email_verifications.create_index([('token', 1)], unique=True, background=True)
+email_verifications.create_index([('_purge_at', 1)],
+                                 expireAfterSeconds=0,
+                                 background=True)
+
+
+def email_verifications_insert(email, token):
+    email_verifications.insert_one({
+        'email': email,
+        'token': token,
+        '_purge_at': datetime.datetime.now(datetime.timezone.utc) +
+        datetime.timedelta(days=1)
+    })
+
+
+def email_verifications_confirm(token):
+    return email_verifications.find_one_and_delete({'token': token})
+
+
+# Email budget database
+
+# one counter per hour for every email the instance sends
+email_budget = db['email_budget']
+email_budget.create_index([('_purge_at', 1)],
+                          expireAfterSeconds=0,
+                          background=True)
+
+
+def email_budget_claim(limit):
+    hour = now() // 3600
+    purge_at = datetime.datetime.now(
+        datetime.timezone.utc) + datetime.timedelta(hours=2)
+    email_budget.update_one(
+        {'_id': hour},
+        {'$setOnInsert': {'count': 0, '_purge_at': purge_at}},
+        upsert=True)
+    claimed = email_budget.find_one_and_update(
+        {'_id': hour, 'count': {'$lt': limit}}, {'$inc': {'count': 1}})
+    return claimed is not None
+
+
+# Sent emails database
+
+sent_emails = db['sent_emails']
+sent_emails.create_index([('subdomain', 1), ('date', 1)], background=True)
+sent_emails.create_index([('_purge_at', 1)],
+                         expireAfterSeconds=0,
+                         background=True)
+
+
+def sent_emails_insert(subdomain, email):
+    sent_emails.insert_one(
+        dict(email,
+             subdomain=subdomain,
+             date=now(),
+             _purge_at=datetime.datetime.now(datetime.timezone.utc) +
+             datetime.timedelta(days=1)))
+
+
+def sent_emails_count(subdomain, since):
+    return sent_emails.count_documents({
+        'subdomain': subdomain,
+        'date': {
+            '$gte': since
+        }
+    })
+
+
+# DKIM keys database
+
+dkim_keys = db['dkim_keys']
+dkim_keys.create_index([('subdomain', 1)], unique=True, background=True)
+
+
+def dkim_get_key(subdomain):
+    return dkim_keys.find_one({'subdomain': subdomain}, {'_id': False})
+
+
+def dkim_set_key(subdomain, selector, private_key, record):
+    key = {'selector': selector, 'private_key': private_key, 'record': record}
+    dkim_keys.update_one({'subdomain': subdomain}, {'$set': key}, upsert=True)
+
+
+def dkim_set_mx(subdomain, mx):
+    dkim_keys.update_one({'subdomain': subdomain}, {'$set': {'mx': mx}},
+                         upsert=True)
+
+
+def dkim_delete(subdomain):
+    dkim_keys.delete_many({'subdomain': subdomain})
+
+
+# Encryption keys database
+
+encryption_keys = db['encryption_keys']
+
+
+def encryption_get_key(subdomain):
+    return encryption_keys.find_one({'subdomain': subdomain})
+
+
+def encryption_set_key(subdomain, public_key, private_key):
+    encryption_keys.insert_one({
+        'subdomain': subdomain,
+        'public_key': public_key,
+        'private_key': private_key
+    })
+
+
+def encryption_delete_key(subdomain):
+    encryption_keys.delete_many({'subdomain': subdomain})
+
+
+# Interactsh database
+
+interactsh = db['interactsh']
+interactsh_interactions = db['interactsh_interactions']
+interactsh.create_index([('correlation_id', 1)], unique=True, background=True)
+interactsh_interactions.create_index([('correlation_id', 1)],
+                                     background=True)
+for db_collection in (interactsh, interactsh_interactions):
+    db_collection.create_index([('_purge_at', 1)],
+                               expireAfterSeconds=0,
+                               background=True)
+
+
+def interactsh_purge_date():
+    return datetime.datetime.now(datetime.timezone.utc) + datetime.timedelta(
+        days=setting('INTERACTSH_RETENTION_DAYS'))
+
+
+def interactsh_register(correlation_id, secret, public_key):
+    existing = interactsh.find_one({'correlation_id': correlation_id})
+    # a correlation id stays with whoever registered it first
+    if existing is not None and existing['secret'] != secret:
+        return False
+    interactsh.update_one(
+        {'correlation_id': correlation_id},
+        {'$set': {
+            'secret': secret,
+            'public_key': public_key,
+            '_purge_at': interactsh_purge_date()
+        }},
+        upsert=True)
+    return True
+
+
+def interactsh_get(correlation_id, secret):
+    return interactsh.find_one_and_update(
+        {
+            'correlation_id': correlation_id,
+            'secret': secret
+        }, {'$set': {
+            '_purge_at': interactsh_purge_date()
+        }})
+
+
+def interactsh_exists(correlation_id):
+    return interactsh.find_one({'correlation_id': correlation_id},
+                               {'_id': True}) is not None
+
+
+def interactsh_deregister(correlation_id, secret):
+    if interactsh.delete_one({
+            'correlation_id': correlation_id,
+            'secret': secret
+    }).deleted_count:
+        interactsh_interactions.delete_many(
+            {'correlation_id': correlation_id})
+        return True
+    return False
+
+
+def interactsh_insert_interaction(correlation_id, interaction):
+    interactsh_interactions.insert_one({
+        'correlation_id': correlation_id,
+        'interaction': interaction,
+        '_purge_at': interactsh_purge_date()
+    })
+
+
+def interactsh_poll(correlation_id):
+    found = list(
+        interactsh_interactions.find({'correlation_id': correlation_id},
+                                     sort=[('_id', 1)]))
+    # interactions are handed out once, like the reference server does
+    interactsh_interactions.delete_many(
+        {'_id': {
+            '$in': [x['_id'] for x in found]
+        }})
+    return [x['interaction'] for x in found]
+
+
+# Server keys database
+
+server_keys = db['server_keys']
+
+
+def server_keys_get_or_create(name, private_key):
+    # the first worker to store a key wins, the others use that one
+    try:
+        server_keys.update_one({'_id': name},
+                               {'$setOnInsert': {
+                                   'private_key': private_key
+                               }},
+                               upsert=True)
+    except DuplicateKeyError:
+        pass
+    return server_keys.find_one({'_id': name})['private_key']
+
+
+# Upload sessions database
+
+uploads = db['uploads']
+upload_parts = db['upload_parts']
+uploads.create_index([('upload_id', 1)], unique=True, background=True)
+uploads.create_index([('subdomain', 1), ('updated', 1)], background=True)
+upload_parts.create_index([('upload_id', 1), ('part', 1)],
+                          unique=True,
+                          background=True)
+# abandoned sessions and their parts are expired by mongo
+for db_collection in (uploads, upload_parts):
+    db_collection.create_index([('_purge_at', 1)],
+                               expireAfterSeconds=0,
+                               background=True)
+
+
+def upload_purge_date():
+    return datetime.datetime.now(datetime.timezone.utc) + datetime.timedelta(
+        hours=setting('UPLOAD_RETENTION_HOURS'))
+
+
+def uploads_insert(subdomain, upload_id, size, part_size, file_settings):
+    uploads.insert_one({
+        'subdomain': subdomain,
+        'upload_id': upload_id,
+        'size': size,
+        'part_size': part_size,
+        'file': file_settings,
+        'received': {},
+        'updated': now(),
+        '_purge_at': upload_purge_date()
+    })
+
+
+def uploads_count(subdomain):
+    return uploads.count_documents({'subdomain': subdomain})
+
+
+def uploads_get(upload_id, subdomain):
+    return uploads.find_one({
+        'upload_id': upload_id,
+        'subdomain': subdomain
+    }, {'_id': False})
+
+
+def uploads_put_part(upload_id, part, data):
+    # re-sending a part replaces it, so interrupted uploads can resume
+    upload_parts.update_one(
+        {
+            'upload_id': upload_id,
+            'part': part
+        }, {'$set': {
+            'data': data,
+            '_purge_at': upload_purge_date()
+        }},
+        upsert=True)
+    return uploads.find_one_and_update(
+        {'upload_id': upload_id}, {
+            '$set': {
+                'received.' + str(part): len(data),
+                'updated': now(),
+                '_purge_at': upload_purge_date()
+            }
+        },
+        projection={'_id': False},
+        return_document=ReturnDocument.AFTER)
+
+
+def uploads_get_data(upload_id):
+    return b''.join(
+        x['data'] for x in upload_parts.find({'upload_id': upload_id},
+                                             sort=[('part', 1)]))
+
+
+def uploads_delete(upload_id):
+    uploads.delete_many({'upload_id': upload_id})
+    upload_parts.delete_many({'upload_id': upload_id})
+
+
+def uploads_get_updated_since(subdomain, time):
+    return list(
+        uploads.find({
+            'subdomain': subdomain,
+            'updated': {
+                '$gte': time
+            }
+        }, {'_id': False}))
+
+
+def get_trash(subdomain):
+    l = []
+    find = {'uid': subdomain, '_deleted': True}
+    for rtype, db_collection in (('HTTP', http), ('DNS', collection)):
+        for x in db_collection.find(find, {'_deleted': False}):
+            x['_id'] = str(x['_id'])
+            x['raw'] = str(base64.b64encode(x['raw']), 'utf-8')
+            if '_purge_at' in x:
+                # pymongo returns naive datetimes in UTC
+                x['_purge_at'] = int(x['_purge_at'].replace(
+                    tzinfo=datetime.timezone.utc).timestamp())
+            x['rtype'] = rtype
+            l.append(x)
+    return l
+
+
+def restore_request_from_db(_id, subdomain):
+    find = {'_id': ObjectId(_id), 'uid': subdomain, '_deleted': True}
+    update = {
+        '$set': {
+            '_deleted': False,
+            '_restored_date': now()
+        },
+        '$unset': {
+            '_deleted_date': '',
+            '_purge_at': ''
+        }
+    }
+    if http.update_one(find, update).modified_count:
+        return 'HTTP'
+    if collection.update_one(find, update).modified_count:
+        return 'DNS'
+    return None
+
+
 def delete_request_from_db(_id, subdomain, dtype):
     if dtype == 'HTTP':
         http_delete_request(_id, subdomain)
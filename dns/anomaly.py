@@ -0,0 +1,136 @@
+import collections
+import threading
+import time
+
+# queries are bucketed into windows and compared against an EWMA baseline
+WINDOW_SECONDS = 10
+EWMA_ALPHA = 0.3
+# baselines are forgotten after this long without a query, and kept for at
+# most this many sessions, oldest first
+IDLE_SECONDS = 3600
+MAX_TRACKED = 10000
+
+DEFAULT_SETTINGS = {
+    'spike_factor': 5.0,
+    'min_queries': 20,
+    'unique_labels': 50,
+}
+
+
+class Window:
+    def __init__(self, start):
+        self.start = start
+        self.count = 0
+        self.labels = set()
+
+
+class AnomalyDetector:
+    def __init__(self, get_settings, raise_alert, session_exists):
+        self.get_settings = get_settings
+        self.raise_alert = raise_alert
+        self.session_exists = session_exists
+        self.lock = threading.Lock()
+        self.windows = {}
+        self.ewma = {}
+        # ordered by when the last window of each session closed
+        self.ends = collections.OrderedDict()
+        self.thread = None
+
+    def observe(self, uid, label):
+        now = time.time()
+        with self.lock:
+            if self.thread is None:
+                self.thread = threading.Thread(target=self.run, daemon=True)
+                self.thread.start()
+            closed = self.close(uid, now)
+            tracked = uid in self.windows or uid in self.ends
+
+        # anyone can query made up names, only real sessions get any state
+        if tracked or self.session_exists(uid):
+            with self.lock:
+                window = self.windows.get(uid)
+                if window is None and self.make_room(now):
+                    self.decay(uid, now)
+                    window = Window(now)
+                    self.windows[uid] = window
+                if window is not None:
+                    window.count += 1
+                    window.labels.add(label)
+
+        if closed is not None:
+            self.evaluate(uid, *closed)
+
+    def run(self):
+        # a burst followed by silence must still be evaluated
+        while True:
+            time.sleep(WINDOW_SECONDS)
+            self.flush()
+
+    def flush(self):
+        now = time.time()
+        with self.lock:
+            closed = [(uid, self.close(uid, now)) for uid in list(self.windows)]
+            while self.ends and now - next(iter(
+                    self.ends.values())) > IDLE_SECONDS:
+                self.forget(next(iter(self.ends)))
+        for uid, window in closed:
+            if window is not None:
+                self.evaluate(uid, *window)
+
+    def close(self, uid, now):
+        # called with the lock held, returns the window and its baseline
+        window = self.windows.get(uid)
+        if window is None or now - window.start < WINDOW_SECONDS:
+            return None
+        del self.windows[uid]
+
+        previous = self.ewma.get(uid)
+        rate = window.count / WINDOW_SECONDS
+        if previous is None:
+            self.ewma[uid] = rate
+        else:
+            self.ewma[uid] = EWMA_ALPHA * rate + (1 - EWMA_ALPHA) * previous
+        self.ends[uid] = window.start + WINDOW_SECONDS
+        self.ends.move_to_end(uid)
+        return window, previous
+
+    def forget(self, uid):
+        self.ends.pop(uid, None)
+        self.ewma.pop(uid, None)
+
+    def make_room(self, now):
+        # called with the lock held; the least recently active baselines go
+        # first, sessions in the middle of a window are never dropped
+        while len(self.windows) + len(self.ends) >= MAX_TRACKED:
+            if not self.ends:
+                return False
+            self.forget(next(iter(self.ends)))
+        return True
+
+    def decay(self, uid, now):
+        # windows without a single query count as a rate of zero
+        end = self.ends.get(uid)
+        if end is None or uid not in self.ewma:
+            return
+        idle = int((now - end) // WINDOW_SECONDS)
+        if idle > 0:
+            self.ewma[uid] *= (1 - EWMA_ALPHA)**idle
+
+    def evaluate(self, uid, window, baseline):
+        settings = dict(DEFAULT_SETTINGS, **(self.get_settings(uid) or {}))
+        rate = window.count / WINDOW_SECONDS
+
+        if baseline is not None and window.count >= settings['min_queries'] \
+                and rate > baseline * settings['spike_factor']:
+            self.raise_alert(
+                uid, 'spike', {
+                    'rate': round(rate, 2),
+                    'baseline': round(baseline, 2),
+                    'queries': window.count
+                })
+
+        if len(window.labels) >= settings['unique_labels']:
+            self.raise_alert(uid, 'unique_labels', {
+                'labels': len(window.labels),
+                'queries': window.count
+            })
@@ -0,0 +1,190 @@
+import base64
+import hashlib
+import os
+import struct
+import time
+
+from cryptography.hazmat.primitives import hashes, serialization
+from cryptography.hazmat.primitives.asymmetric import ec
+from cryptography.hazmat.primitives.asymmetric.utils import decode_dss_signature
+from dnslib import QTYPE, RR, RD, CNAME, NS, PTR
+from dnslib.label import DNSBuffer
+
+DNSSEC = os.environ.get('DNSSEC', 'false').lower() == 'true'
+DNSSEC_KEYS_DIR = os.environ.get('DNSSEC_KEYS_DIR', 'dnssec')
+ZONE = os.environ.get('DOMAIN', 'requestrepo.com').lower().rstrip('.')
+
+# ECDSA P-256 with SHA-256
+ALGORITHM = 13
+KSK_FLAGS = 257
+ZSK_FLAGS = 256
+DNSKEY_TTL = 3600
+NEGATIVE_TTL = 60
+# signatures are made per answer, so a short validity is enough
+SIGNATURE_VALIDITY = 7 * 24 * 3600
+SIGNATURE_BACKDATE = 3600
+
+
+def name_wire(name):
+    # canonical form, lowercase and without compression
+    labels = [x for x in str(name).lower().rstrip('.').split('.') if x]
+    return b''.join(bytes([len(x)]) + x.encode() for x in labels) + b'\x00'
+
+
+def in_zone(name):
+    name = str(name).lower().rstrip('.')
+    return name == ZONE or name.endswith('.' + ZONE)
+
+
+def rdata_wire(rdata):
+    if isinstance(rdata, (CNAME, NS, PTR)):
+        return name_wire(rdata.label)
+    buffer = DNSBuffer()
+    rdata.pack(buffer)
+    return buffer.data
+
+
+def load_key(path):
+    if os.path.exists(path):
+        with open(path, 'rb') as f:
+            return serialization.load_pem_private_key(f.read(), None)
+    key = ec.generate_private_key(ec.SECP256R1())
+    os.makedirs(os.path.dirname(path) or '.', exist_ok=True)
+    with open(path, 'wb') as f:
+        f.write(
+            key.private_bytes(serialization.Encoding.PEM,
+                              serialization.PrivateFormat.PKCS8,
+                              serialization.NoEncryption()))
+    return key
+
+
+def dnskey_rdata(flags, key):
+    point = key.public_key().public_bytes(
+        serialization.Encoding.X962,
+        serialization.PublicFormat.UncompressedPoint)
+    # the uncompressed point without its 0x04 prefix
+    return struct.pack('!HBB', flags, 3, ALGORITHM) + point[1:]
+
+
+def key_tag(rdata):
+    total = 0
+    for i, byte in enumerate(rdata):
+        total += byte << 8 if i % 2 == 0 else byte
+    total += (total >> 16) & 0xFFFF
+    return total & 0xFFFF
+
+
+class ZoneKey:
+    def __init__(self, flags, path):
+        self.flags = flags
+        self.key = load_key(path)
+        self.rdata = dnskey_rdata(flags, self.key)
+        self.tag = key_tag(self.rdata)
+
+    def sign(self, data):
+        r, s = decode_dss_signature(
+            self.key.sign(data, ec.ECDSA(hashes.SHA256())))
+        return r.to_bytes(32, 'big') + s.to_bytes(32, 'big')
+
+
+ksk = None
+zsk = None
+
+
+def load_keys():
+    global ksk, zsk
+    ksk = ZoneKey(KSK_FLAGS, os.path.join(DNSSEC_KEYS_DIR, 'ksk.pem'))
+    zsk = ZoneKey(ZSK_FLAGS, os.path.join(DNSSEC_KEYS_DIR, 'zsk.pem'))
+
+
+def dnskey_records(rname=None):
+    return [
+        RR(rname or ZONE + '.', QTYPE.DNSKEY, ttl=DNSKEY_TTL,
+           rdata=RD(key.rdata))
+        for key in (ksk, zsk)
+    ]
+
+
+def ds_record(key):
+    digest = hashlib.sha256(name_wire(ZONE) + key.rdata).hexdigest().upper()
+    return f'{ZONE}. IN DS {key.tag} {ALGORITHM} 2 {digest}'
+
+
+def dnskey_record(key):
+    public_key = base64.b64encode(key.rdata[4:]).decode()
+    return f'{ZONE}. {DNSKEY_TTL} IN DNSKEY {key.flags} 3 {ALGORITHM} {public_key}'
+
+
+def public_material():
+    # what the operator hands to the registrar, published for /api/admin/dnssec
+    return {
+        'zone': ZONE,
+        'algorithm': ALGORITHM,
+        'dnskey': [dnskey_record(ksk), dnskey_record(zsk)],
+        'ds': [ds_record(ksk)]
+    }
+
+
+def sign_rrset(rrset, key):
+    first = rrset[0]
+    now = int(time.time())
+    labels = len([x for x in str(first.rname).rstrip('.').split('.') if x])
+    header = struct.pack('!HBBIIIH', first.rtype, ALGORITHM, labels,
+                         first.ttl, now + SIGNATURE_VALIDITY,
+                         now - SIGNATURE_BACKDATE, key.tag) + name_wire(ZONE)
+    owner = name_wire(first.rname)
+    records = sorted(rdata_wire(rr.rdata) for rr in rrset)
+    data = header + b''.join(
+        owner + struct.pack('!HHIH', first.rtype, 1, first.ttl, len(x)) + x
+        for x in records)
+    return RR(first.rname,
+              QTYPE.RRSIG,
+              ttl=first.ttl,
+              rdata=RD(header + key.sign(data)))
+
+
+def type_bitmap(types):
+    # a single window, every type served here is below 256
+    bitmap = bytearray(max(types) // 8 + 1)
+    for rtype in types:
+        bitmap[rtype // 8] |= 0x80 >> (rtype % 8)
+    return bytes([0, len(bitmap)]) + bytes(bitmap)
+
+
+def soa_record():
+    rdata = name_wire('ns1.' + ZONE) + name_wire('admin.' + ZONE) + struct.pack(
+        '!IIIII', int(time.time()), 3600, 600, 86400, NEGATIVE_TTL)
+    return RR(ZONE + '.', QTYPE.SOA, ttl=NEGATIVE_TTL, rdata=RD(rdata))
+
+
+def denial_records(qname, qtype):
+    # "black lies": the name exists, just not with the type that was asked for
+    types = {QTYPE.A, QTYPE.AAAA, QTYPE.TXT, QTYPE.RRSIG, QTYPE.NSEC} - {qtype}
+    # the next name is \000.qname, the name right after qname in canonical order
+    rdata = b'\x01\x00' + name_wire(qname) + type_bitmap(sorted(types))
+    return RR(qname, QTYPE.NSEC, ttl=NEGATIVE_TTL, rdata=RD(rdata))
+
+
+def wants_dnssec(request):
+    return any(rr.rtype == QTYPE.OPT and rr.ttl & 0x8000 for rr in request.ar)
+
+
+def sign_reply(request, reply):
+    qname, qtype = reply.q.qname, reply.q.qtype
+    if not in_zone(qname):
+        return reply
+
+    if not reply.rr and reply.header.rcode in (0, 3):
+        reply.header.rcode = 0
+        reply.auth = [soa_record(), denial_records(qname, qtype)]
+
+    for section in (reply.rr, reply.auth):
+        rrsets = {}
+        for rr in section:
+            if in_zone(rr.rname) and rr.rtype != QTYPE.RRSIG:
+                rrsets.setdefault((str(rr.rname).lower(), rr.rtype),
+                                  []).append(rr)
+        for (_, rtype), rrset in rrsets.items():
+            section.append(
+                sign_rrset(rrset, ksk if rtype == QTYPE.DNSKEY else zsk))
+    return reply
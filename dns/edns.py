@@ -0,0 +1,63 @@
+import ipaddress
+import struct
+
+from dnslib import QTYPE, RR, EDNSOption
+
+# what resolvers may send us over UDP, small enough to avoid fragmentation
+EDNS_PAYLOAD_SIZE = 1232
+DO_FLAG = 0x8000
+CLIENT_SUBNET = 8
+
+
+def find_opt(request):
+    for rr in request.ar:
+        if rr.rtype == QTYPE.OPT:
+            return rr
+
+
+def parse_client_subnet(data):
+    family, source, scope = struct.unpack('!HBB', data[:4])
+    if family == 1:
+        address = ipaddress.IPv4Address(data[4:8].ljust(4, b'\x00'))
+    elif family == 2:
+        address = ipaddress.IPv6Address(data[4:20].ljust(16, b'\x00'))
+    else:
+        raise ValueError(f'unknown address family {family}')
+    return {
+        'subnet': f'{address}/{source}',
+        'source_prefix': source,
+        'scope_prefix': scope
+    }
+
+
+def parse_edns(request):
+    opt = find_opt(request)
+    if opt is None:
+        return None
+    edns = {
+        'udp_size': opt.rclass,
+        'version': (opt.ttl >> 16) & 0xFF,
+        'do': bool(opt.ttl & DO_FLAG),
+        'options': [option.code for option in opt.rdata]
+    }
+    for option in opt.rdata:
+        if option.code == CLIENT_SUBNET:
+            try:
+                edns['client_subnet'] = parse_client_subnet(option.data)
+            except (struct.error, ValueError):
+                pass
+    return edns
+
+
+def opt_record(request, do=False):
+    # the client subnet is echoed with a zero scope, answers don't depend on it
+    options = [
+        EDNSOption(CLIENT_SUBNET, option.data[:3] + b'\x00' + option.data[4:])
+        for option in find_opt(request).rdata
+        if option.code == CLIENT_SUBNET and len(option.data) >= 4
+    ]
+    return RR([],
+              QTYPE.OPT,
+              rclass=EDNS_PAYLOAD_SIZE,
+              ttl=DO_FLAG if do else 0,
+              rdata=options)
@@ -0,0 +1,37 @@
+import base64
+import json
+import os
+from cryptography.hazmat.primitives import hashes, serialization
+from cryptography.hazmat.primitives.asymmetric import padding
+from cryptography.hazmat.primitives.ciphers.aead import AESGCM
+
+OAEP = padding.OAEP(mgf=padding.MGF1(algorithm=hashes.SHA256()),
+                    algorithm=hashes.SHA256(),
+                    label=None)
+
+DNS_ENCRYPTED_FIELDS = ['raw', 'name', 'answers']
+
+
+def encrypt_fields(public_pem, dic, fields):
+    payload = {}
+    for field in fields:
+        if field not in dic:
+            continue
+        value = dic[field]
+        if type(value) is bytes:
+            value = str(base64.b64encode(value), 'utf-8')
+        payload[field] = value
+        del dic[field]
+
+    data_key = AESGCM.generate_key(bit_length=256)
+    nonce = os.urandom(12)
+    data = AESGCM(data_key).encrypt(nonce, json.dumps(payload).encode(), None)
+    wrapped_key = serialization.load_pem_public_key(public_pem).encrypt(
+        data_key, OAEP)
+
+    dic['raw'] = b''
+    dic['encrypted'] = {
+        'key': str(base64.b64encode(wrapped_key), 'utf-8'),
+        'nonce': str(base64.b64encode(nonce), 'utf-8'),
+        'data': str(base64.b64encode(data), 'utf-8')
+    }
@@ -1,7 +1,8 @@
 import os
-from pymongo import MongoClient
+from pymongo import MongoClient, ReturnDocument, UpdateOne
 import urllib.parse
 import re
+import datetime
 
 if 'MONGODB_DATABASE' in os.environ:
     MONGODB_DATABASE = os.environ['MONGODB_DATABASE']
@@ -36,6 +37,211 @@ def insert_into_db(value):
     client.close()
 
 
+def get_encryption_key(subdomain):
+    client = MongoClient('mongodb://%s:%s@%s' % (username, password, MONGODB_HOSTNAME), 27017)
+    db = client[MONGODB_DATABASE]
+
+    keys = db['encryption_keys']
+    result = keys.find_one({'subdomain':subdomain})
+    client.close()
+    return result
+
+
+def session_exists(subdomain):
+    client = MongoClient('mongodb://%s:%s@%s' % (username, password, MONGODB_HOSTNAME), 27017)
+    db = client[MONGODB_DATABASE]
+
+    users = db['users']
+    result = users.find_one({'subdomain':subdomain}, {'_id':True})
+    client.close()
+    return result is not None
+
+
+def get_alias(alias):
+    client = MongoClient('mongodb://%s:%s@%s' % (username, password, MONGODB_HOSTNAME), 27017)
+    db = client[MONGODB_DATABASE]
+
+    aliases = db['aliases']
+    result = aliases.find_one({'alias':alias})
+    client.close()
+    return result
+
+
+def match_payload_hits(subdomain, candidates, date):
+    client = MongoClient('mongodb://%s:%s@%s' % (username, password, MONGODB_HOSTNAME), 27017)
+    db = client[MONGODB_DATABASE]
+
+    payloads = db['payloads']
+    cids = [x['cid'] for x in payloads.find({'subdomain':subdomain, 'cid':{'$in':candidates}}, {'cid':True})]
+    if cids:
+        payloads.update_many({'cid':{'$in':cids}}, {'$min':{'first_hit':date}, '$max':{'last_hit':date}, '$inc':{'hits':1}})
+    client.close()
+    return cids
+
+
+def get_anomaly_settings(subdomain):
+    client = MongoClient('mongodb://%s:%s@%s' % (username, password, MONGODB_HOSTNAME), 27017)
+    db = client[MONGODB_DATABASE]
+
+    settings = db['anomaly_settings']
+    result = settings.find_one({'subdomain':subdomain}, {'_id':False, 'subdomain':False})
+    client.close()
+    return result
+
+
+def insert_alert(subdomain, kind, details):
+    client = MongoClient('mongodb://%s:%s@%s' % (username, password, MONGODB_HOSTNAME), 27017)
+    db = client[MONGODB_DATABASE]
+
+    alerts = db['alerts']
+    alerts.insert_one({'subdomain':subdomain, 'kind':kind, 'details':details, 'date':int(datetime.datetime.now(datetime.timezone.utc).timestamp())})
+    client.close()
+
+
+def get_dns_settings(subdomain):
+    client = MongoClient('mongodb://%s:%s@%s' % (username, password, MONGODB_HOSTNAME), 27017)
+    db = client[MONGODB_DATABASE]
+
+    settings = db['dns_settings']
+    result = settings.find_one({'subdomain':subdomain})
+    client.close()
+    return result
+
+
+def interactsh_registered(correlation_id):
+    client = MongoClient('mongodb://%s:%s@%s' % (username, password, MONGODB_HOSTNAME), 27017)
+    db = client[MONGODB_DATABASE]
+
+    interactsh = db['interactsh']
+    result = interactsh.find_one({'correlation_id':correlation_id}, {'_id':True}) is not None
+    client.close()
+    return result
+
+
+def insert_interactsh_interaction(correlation_id, interaction):
+    client = MongoClient('mongodb://%s:%s@%s' % (username, password, MONGODB_HOSTNAME), 27017)
+    db = client[MONGODB_DATABASE]
+
+    retention = int(os.environ.get('INTERACTSH_RETENTION_DAYS', 30))
+    interactions = db['interactsh_interactions']
+    interactions.insert_one({'correlation_id':correlation_id, 'interaction':interaction, '_purge_at':datetime.datetime.now(datetime.timezone.utc) + datetime.timedelta(days=retention)})
+    client.close()
+
+
+def insert_error(subdomain, step, error):
+    client = MongoClient('mongodb://%s:%s@%s' % (username, password, MONGODB_HOSTNAME), 27017)
+    db = client[MONGODB_DATABASE]
+
+    retention = int(os.environ.get('ERRORS_RETENTION_DAYS', 7))
+    errors = db['capture_errors']
+    date = datetime.datetime.now(datetime.timezone.utc)
+    errors.insert_one({'subdomain':subdomain, 'source':'dns', 'step':step, 'error':error, 'date':int(date.timestamp()), '_purge_at':date + datetime.timedelta(days=retention)})
+    client.close()
+
+
+def record_stats(subdomain, date, ip):
+    client = MongoClient('mongodb://%s:%s@%s' % (username, password, MONGODB_HOSTNAME), 27017)
+    db = client[MONGODB_DATABASE]
+
+    stats = db['stats']
+    counters = [('protocol', 'DNS'), ('hour', date // 3600 * 3600), ('ip', ip)]
+    stats.bulk_write([UpdateOne({'subdomain':subdomain, 'kind':kind, 'key':key}, {'$inc':{'count':1}}, upsert=True) for kind, key in counters], ordered=False)
+    client.close()
+
+
+def find_canaries(candidates):
+    client = MongoClient('mongodb://%s:%s@%s' % (username, password, MONGODB_HOSTNAME), 27017)
+    db = client[MONGODB_DATABASE]
+
+    settings = db['metadata_settings']
+    result = list(settings.find({'values':{'$in':candidates}}, {'_id':False, 'subdomain':True, 'values':True}))
+    client.close()
+    return result
+
+
+def get_blocklist(subdomain):
+    client = MongoClient('mongodb://%s:%s@%s' % (username, password, MONGODB_HOSTNAME), 27017)
+    db = client[MONGODB_DATABASE]
+
+    blocklists = db['blocklists']
+    result = blocklists.find_one({'subdomain':subdomain}, {'_id':False, 'subdomain':False})
+    client.close()
+    return result
+
+
+def get_log_levels():
+    client = MongoClient('mongodb://%s:%s@%s' % (username, password, MONGODB_HOSTNAME), 27017)
+    db = client[MONGODB_DATABASE]
+
+    log_levels = db['log_levels']
+    result = log_levels.find_one({'_id':'overrides', '_purge_at':{'$gt':datetime.datetime.now(datetime.timezone.utc)}})
+    client.close()
+    return result
+
+
+def set_dnssec_material(material):
+    client = MongoClient('mongodb://%s:%s@%s' % (username, password, MONGODB_HOSTNAME), 27017)
+    db = client[MONGODB_DATABASE]
+
+    dnssec = db['dnssec']
+    dnssec.replace_one({'_id':'keys'}, material, upsert=True)
+    client.close()
+
+
+def get_notifications(subdomain):
+    client = MongoClient('mongodb://%s:%s@%s' % (username, password, MONGODB_HOSTNAME), 27017)
+    db = client[MONGODB_DATABASE]
+
+    notifications = db['notifications']
+    result = notifications.find_one({'subdomain':subdomain})
+    client.close()
+    return result
+
+
+def get_email_alert(subdomain):
+    client = MongoClient('mongodb://%s:%s@%s' % (username, password, MONGODB_HOSTNAME), 27017)
+    db = client[MONGODB_DATABASE]
+
+    email_alerts = db['email_alerts']
+    result = email_alerts.find_one({'subdomain':subdomain})
+    client.close()
+    return result
+
+
+def claim_email_budget(limit):
+    client = MongoClient('mongodb://%s:%s@%s' % (username, password, MONGODB_HOSTNAME), 27017)
+    db = client[MONGODB_DATABASE]
+
+    # the backend owns the collection and its expiry, see email_budget_claim
+    email_budget = db['email_budget']
+    hour = int(datetime.datetime.now(datetime.timezone.utc).timestamp()) // 3600
+    email_budget.update_one({'_id':hour}, {'$setOnInsert':{'count':0, '_purge_at':datetime.datetime.now(datetime.timezone.utc) + datetime.timedelta(hours=2)}}, upsert=True)
+    result = email_budget.find_one_and_update({'_id':hour, 'count':{'$lt':limit}}, {'$inc':{'count':1}})
+    client.close()
+    return result is not None
+
+
+def claim_email_alert(subdomain, cooldown):
+    return claim_cooldown('email_alerts', subdomain, cooldown)
+
+
+def claim_notification(subdomain, cooldown):
+    return claim_cooldown('notifications', subdomain, cooldown)
+
+
+def claim_cooldown(collection_name, subdomain, cooldown):
+    client = MongoClient('mongodb://%s:%s@%s' % (username, password, MONGODB_HOSTNAME), 27017)
+    db = client[MONGODB_DATABASE]
+
+    cooldowns = db[collection_name]
+    date = int(datetime.datetime.now(datetime.timezone.utc).timestamp())
+    previous = cooldowns.find_one_and_update({'subdomain':subdomain, '$or':[{'last_sent':{'$exists':False}}, {'last_sent':{'$lte':date - cooldown}}]}, {'$set':{'last_sent':date, 'suppressed':0}}, return_document=ReturnDocument.BEFORE)
+    if previous is None:
+        cooldowns.update_one({'subdomain':subdomain}, {'$inc':{'suppressed':1}})
+    client.close()
+    return None if previous is None else previous.get('suppressed', 0)
+
+
 def get_dns_record(domain, dtype):
     client = MongoClient('mongodb://%s:%s@%s' % (username, password, MONGODB_HOSTNAME), 27017)
     db = client[MONGODB_DATABASE]
@@ -46,9 +252,30 @@ def get_dns_record(domain, dtype):
     return result
 
 
+def get_dkim_record(subdomain):
+    client = MongoClient('mongodb://%s:%s@%s' % (username, password, MONGODB_HOSTNAME), 27017)
+    db = client[MONGODB_DATABASE]
+
+    dkim_keys = db['dkim_keys']
+    result = dkim_keys.find_one({'subdomain':subdomain}, {'_id':False, 'selector':True, 'record':True, 'mx':True})
+    client.close()
+    return result
+
+
+def find_dns_records(domains, dtype):
+    client = MongoClient('mongodb://%s:%s@%s' % (username, password, MONGODB_HOSTNAME), 27017)
+    db = client[MONGODB_DATABASE]
+
+    ddns = db['ddns']
+    result = list(ddns.find({'domain':{'$in':domains}, 'type':dtype}))
+    client.close()
+    return result
+
+
 
 #REGXPRESSION = '^\\.?[0-9a-z]{8}\\.requestrepo\\.com\\.?$'
-REGXPRESSION = '^(.*)(\\.?[0-9a-z]{8}\\.requestrepo\\.com\\.?)$'
+DOMAIN = os.environ.get('DOMAIN', 'requestrepo.com').lower().rstrip('.')
+REGXPRESSION = '^(.*)(\\.?[0-9a-z]{8}\\.' + re.escape(DOMAIN) + '\\.?)$'
 def update_dns_record(subdomain, domain, dtype, newval):
     client = MongoClient('mongodb://%s:%s@%s' % (username, password, MONGODB_HOSTNAME), 27017)
     db = client[MONGODB_DATABASE]
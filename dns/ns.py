@@ -1,16 +1,31 @@
 #!/usr/bin/env python3
 import sys
 import datetime
+import logging
 import time
 import os
 from time import sleep
 import re
 import random
+import signal
 
 from dnslib import DNSLabel, QTYPE, RD, RR, RCODE
 from dnslib import A, AAAA, CNAME, MX, NS, SOA, TXT
 from dnslib.server import DNSServer
-from mongolog import insert_into_db, update_dns_record, get_dns_record
+from mongolog import insert_into_db, update_dns_record, get_dns_record, find_dns_records, get_dkim_record, get_encryption_key, session_exists, get_alias, match_payload_hits, get_anomaly_settings, insert_alert, get_dns_settings, get_notifications, claim_notification, get_email_alert, claim_email_alert, claim_email_budget, interactsh_registered, insert_interactsh_interaction, insert_error, record_stats, find_canaries, get_blocklist, get_log_levels, set_dnssec_material
+from anomaly import AnomalyDetector
+from gelf import gelf_message, gelf_send
+from elastic import elastic_sink
+from notify import notify
+from mailer import email_alert
+from encryption import encrypt_fields, DNS_ENCRYPTED_FIELDS
+from settings import setting, reload_settings
+from logconfig import setup_logging, watch_levels
+from blocklist import block_action
+from edns import find_opt, parse_edns, opt_record
+from dnssec import DNSSEC, ZONE, load_keys, dnskey_records, public_material, wants_dnssec, sign_reply
+
+log = logging.getLogger('ns')
 
 EPOCH = datetime.datetime(1970, 1, 1)
 SERIAL = int(datetime.datetime.now(datetime.timezone.utc).timestamp())
@@ -47,7 +62,7 @@ class Record:
                  **kwargs):
         if isinstance(rdata_type, RD):
             # actually an instance, not a type
-            self._rtype = TYPE_LOOKUP[rdata_type.__class__]
+            self._rtype = TYPE_LOOKUP.get(rdata_type.__class__)
             rdata = rdata_type
         else:
             self._rtype = TYPE_LOOKUP[rdata_type]
@@ -94,26 +109,113 @@ class Record:
         return '{} {}'.format(QTYPE[self._rtype], self.kwargs)
 
 
+# answered directly and never logged, so uptime checks don't create noise
+HEALTH_DNS_NAME = os.environ.get('HEALTH_DNS_NAME',
+                                 '_health.' + ZONE).lower().rstrip('.')
+
+# answered for TXT queries on the apex domain only
+DEFAULT_TXT = os.environ.get('DEFAULT_TXT',
+                             '3r_c8OKexhD8zYQUx6QKjIlnkn6E_YB_vdzgZ5Xbpjk')
+
 if 'SERVER_IP' in os.environ:
     SERVER_IP = os.environ['SERVER_IP']
 else:
     SERVER_IP = '127.0.0.1'
 
 #REGXPRESSION = '^\\.?[0-9a-z]{8}\\.requestrepo\\.com\\.?$'
-REGXPRESSION = '^(.+\\.)?(([0-9a-z]{8})\\.requestrepo\\.com\\.?)$'
+REGXPRESSION = '^(.+\\.)?(([0-9a-z]{8})\\.' + re.escape(ZONE) + '\\.?)$'
+ALIAS_REGXPRESSION = '^(.+\\.)?([0-9a-z][0-9a-z\\-]{1,30}[0-9a-z])\\.' + re.escape(ZONE) + '\\.?$'
 
 
-def save_into_db(reply, ip, raw):
-    name = str(reply.q.qname)
+def resolve_alias(name):
+    if re.search(REGXPRESSION, name.lower()):
+        return name
+    match = re.search(ALIAS_REGXPRESSION, name.lower())
+    if match is None:
+        return name
+    alias = get_alias(match.group(2))
+    if alias is None:
+        return name
+    return (match.group(1) or '') + alias['subdomain'] + '.' + ZONE + '.'
+
+
+def lookup_record(name, dtype):
+    # the record of the name itself, or of the closest wildcard above it
+    match = re.search(REGXPRESSION, name)
+    if match is None or match.group(1) is None:
+        return get_dns_record(name, dtype)
+    labels = match.group(1).rstrip('.').split('.')
+    base = match.group(2).rstrip('.') + '.'
+    candidates = [name] + [
+        '.'.join(['*'] + labels[i:]) + '.' + base
+        for i in range(1, len(labels))
+    ] + ['*.' + base]
+    found = {x['domain']: x for x in find_dns_records(candidates, dtype)}
+    for candidate in candidates:
+        if candidate in found:
+            return found[candidate]
+
+
+def dkim_record(name):
+    # keys of sessions sending emails, see /api/v2/emails/dkim
+    key = get_dkim_record(get_uid(name))
+    # provisioned sessions only have an MX record until their first send
+    if key is None or 'record' not in key:
+        return None
+    if name != '{}._domainkey.{}.{}.'.format(key['selector'], get_uid(name),
+                                             ZONE):
+        return None
+    return key['record']
+
+
+CORRELATION_ID_LENGTH = 12
+
+detector = AnomalyDetector(get_anomaly_settings, insert_alert, session_exists)
+
+
+def get_uid(name):
     uid = re.search(REGXPRESSION, name.lower())
     if uid == None:
-        uid = "Bad"
-    else:
-        uid = uid.group(3)
-        if uid[0] == '.':
-            uid = uid[1:9]
-        else:
-            uid = uid[:8]
+        return "Bad"
+    uid = uid.group(3)
+    if uid[0] == '.':
+        return uid[1:9]
+    return uid[:8]
+
+
+def record_error(uid, step, ex):
+    log.error('%s failed for %s: %s', step, uid, ex)
+    # the error feed tells users why a capture is missing or incomplete
+    try:
+        insert_error(None if uid == "Bad" else uid, step, str(ex))
+    except Exception as ex:
+        log.error('could not record error: %s', ex)
+
+
+def get_block_action(ip, uid):
+    # DNS queries come from resolvers, so only the networks apply
+    operator = {
+        'networks': setting('BLOCKLIST_NETWORKS'),
+        'action': setting('BLOCKLIST_ACTION')
+    }
+    try:
+        session = get_blocklist(uid) if uid != "Bad" else None
+    except Exception as ex:
+        record_error(uid, 'blocklist', ex)
+        session = None
+    return block_action(ip, None, operator, session)
+
+
+def save_into_db(reply, ip, raw, edns=None):
+    name = str(reply.q.qname)
+    uid = get_uid(resolve_alias(name))
+
+    answers = [{
+        "name": str(rr.rname),
+        "type": QTYPE[rr.rtype],
+        "ttl": rr.ttl,
+        "value": str(rr.rdata)
+    } for rr in reply.rr]
 
     data = {
         "date": int(datetime.datetime.now(datetime.timezone.utc).timestamp()),
@@ -121,37 +223,195 @@ def save_into_db(reply, ip, raw):
         "type": QTYPE[reply.q.qtype],
         "name": name,
         "uid": uid,
-        "reply": str(reply),
+        "answers": answers,
         "raw": raw
     }
+    if edns is not None:
+        # the client subnet points at the network behind the resolver
+        data["edns"] = edns
+
+    if uid != "Bad":
+        # the leftmost label is the one exfil tooling varies
+        detector.observe(uid, name.lower().split('.')[0])
+
+    candidates = [
+        label for label in name.lower().split('.')
+        if len(label) == CORRELATION_ID_LENGTH and label.isalnum()
+    ]
+    if candidates and uid != "Bad":
+        correlation_ids = match_payload_hits(uid, candidates, data["date"])
+        if correlation_ids:
+            data["correlation_ids"] = correlation_ids
+
+    if uid != "Bad":
+        try:
+            record_stats(uid, data["date"], ip)
+        except Exception as ex:
+            record_error(uid, 'stats', ex)
+
+    # canary AWS key ids from the metadata simulation, lowercased by resolvers
+    canaries = [
+        label.upper() for label in name.lower().split('.')
+        if re.match('^(akia|asia)[0-9a-z]{16}$', label)
+    ]
+    if canaries:
+        try:
+            for owner in find_canaries(canaries):
+                insert_alert(owner['subdomain'], 'canary_used', {
+                    'rtype': 'DNS',
+                    'subdomain': None if uid == "Bad" else uid,
+                    'ip': ip,
+                    'values': [x for x in owner['values'] if x in canaries]
+                })
+        except Exception as ex:
+            record_error(uid, 'canaries', ex)
+
+    key = get_encryption_key(uid)
+    if key is not None:
+        encrypt_fields(key['public_key'], data, DNS_ENCRYPTED_FIELDS)
+
     insert_into_db(data)
 
+    try:
+        gelf_send(
+            gelf_message(
+                ZONE, 'DNS {} {}'.format(data['type'], data.get('name', '')),
+                data['date'], {
+                    'request_id': str(data['_id']),
+                    'rtype': 'DNS',
+                    'uid': uid,
+                    'ip': ip,
+                    'type': data['type'],
+                    'name': data.get('name')
+                }))
+    except Exception as ex:
+        record_error(uid, 'gelf', ex)
+
+    if elastic_sink is not None:
+        elastic_sink.index('dns', data)
+
+    if uid != "Bad":
+        try:
+            notify(get_notifications, claim_notification, uid, {
+                'rtype': 'DNS',
+                'subdomain': uid,
+                'ip': ip,
+                'summary': '{} {}'.format(data['type'], data.get('name', ''))
+            })
+            # the queried name is not sent out for encrypted sessions
+            if key is None:
+                summary = '{} {} from {}'.format(data['type'], name, ip)
+            else:
+                summary = '{} from {}'.format(data['type'], ip)
+            email_alert(get_email_alert, claim_email_alert, claim_email_budget,
+                        uid, 'DNS', name, summary)
+        except Exception as ex:
+            record_error(uid, 'notifications', ex)
+
+
+# the interactsh protocol, see backend/interactsh.py
+INTERACTSH = os.environ.get('INTERACTSH', 'false').lower() == 'true'
+INTERACTSH_LABEL_LENGTH = 33
+INTERACTSH_CORRELATION_ID_LENGTH = 20
+
+
+def save_interactsh(request, reply, ip):
+    name = str(reply.q.qname).lower().rstrip('.')
+    if not name.endswith('.' + ZONE):
+        return
+    for label in name[:-len('.' + ZONE)].split('.'):
+        if len(label) == INTERACTSH_LABEL_LENGTH and label.isalnum():
+            correlation_id = label[:INTERACTSH_CORRELATION_ID_LENGTH]
+            if interactsh_registered(correlation_id):
+                insert_interactsh_interaction(correlation_id, {
+                    'protocol': 'dns',
+                    'unique-id': label,
+                    'full-id': label,
+                    'q-type': QTYPE[reply.q.qtype],
+                    'raw-request': str(request),
+                    'raw-response': str(reply),
+                    'remote-address': ip,
+                    'timestamp': datetime.datetime.now(datetime.timezone.utc).isoformat().replace('+00:00', 'Z')
+                })
+            return
+
 
 class Resolver:
     def __init__(self):
         self.server_ip = SERVER_IP
 
     def resolve(self, request, handler):
+        if DNSSEC and request.q.qtype == QTYPE.DNSKEY and str(
+                request.q.qname).lower().rstrip('.') == ZONE:
+            reply = request.reply()
+            # owner names keep the casing of the question, see answer()
+            for rr in dnskey_records(request.q.qname):
+                reply.add_answer(rr)
+        else:
+            reply = self.answer(request, handler)
+
+        signed = DNSSEC and wants_dnssec(request)
+        if signed:
+            try:
+                sign_reply(request, reply)
+            except Exception as ex:
+                log.error('could not sign reply: %s', ex)
+        if find_opt(request) is not None:
+            reply.add_ar(opt_record(request, signed))
+        return reply
+
+    def answer(self, request, handler):
         reply = request.reply()
 
+        if HEALTH_DNS_NAME and str(
+                reply.q.qname).lower().rstrip('.') == HEALTH_DNS_NAME:
+            if reply.q.qtype in (QTYPE.A, QTYPE.ANY):
+                reply.add_answer(Record(A, self.server_ip).as_rr(reply.q.qname))
+            elif reply.q.qtype == QTYPE.TXT:
+                reply.add_answer(Record(TXT, 'ok').as_rr(reply.q.qname))
+            return reply
+
+        # resolvers using 0x20 randomize the casing of the name, records are
+        # looked up lowercased while answers keep the name exactly as asked
+        qname = resolve_alias(str(reply.q.qname)).lower()
+        log.debug('%s %s from %s', QTYPE[reply.q.qtype], qname,
+                  handler.client_address[0])
+
+        action = get_block_action(handler.client_address[0], get_uid(qname))
+        if action == 'drop':
+            reply.header.rcode = RCODE.REFUSED
+            return reply
+
         # We assume that the data in the DB is correct (using server side checks)
         new_record = None
+        data = None
 
         if QTYPE[reply.q.qtype] == 'CNAME':
-            data = get_dns_record(str(reply.q.qname), 'CNAME')
+            data = lookup_record(qname, 'CNAME')
             if data == None:
-                new_record = Record(CNAME, 'requestrepo.com.')
+                new_record = Record(CNAME, ZONE + '.')
             else:
                 new_record = Record(CNAME, data['value'])
         elif QTYPE[reply.q.qtype] == 'TXT':
-            data = get_dns_record(str(reply.q.qname), 'TXT')
-            if data == None:
+            # looked up first, a wildcard TXT record would shadow the selector
+            key = dkim_record(qname) if '._domainkey.' in qname else None
+            data = lookup_record(qname, 'TXT') if key == None else None
+            if key != None:
+                # longer than one TXT string, so split in chunks of 255
                 new_record = Record(
-                    TXT, '3r_c8OKexhD8zYQUx6QKjIlnkn6E_YB_vdzgZ5Xbpjk')
-            else:
+                    TXT, [key[i:i + 255] for i in range(0, len(key), 255)])
+            elif data != None:
                 new_record = Record(TXT, data['value'])
+            elif qname.lower().rstrip('.') == ZONE:
+                new_record = Record(TXT, DEFAULT_TXT)
+            else:
+                settings = get_dns_settings(get_uid(qname)) or {}
+                if settings.get('txt_mode') == 'value':
+                    new_record = Record(TXT, settings['txt_value'])
+                elif settings.get('txt_mode') == 'nxdomain':
+                    reply.header.rcode = RCODE.NXDOMAIN
         elif QTYPE[reply.q.qtype] == 'A':
-            data = get_dns_record(str(reply.q.qname), 'A')
+            data = lookup_record(qname, 'A')
             if data == None:
                 new_record = Record(A, self.server_ip)
             else:
@@ -179,7 +439,7 @@ class Resolver:
                         update_dns_record(data['subdomain'], data['domain'],
                                           'A', ips)
         elif QTYPE[reply.q.qtype] == 'AAAA':
-            data = get_dns_record(str(reply.q.qname), 'AAAA')
+            data = lookup_record(qname, 'AAAA')
             if data == None:
                 try:
                     new_record = Record(AAAA, self.server_ip)
@@ -200,7 +460,7 @@ class Resolver:
                             ips[idx] = new_ips
                             ips = '%'.join(ips)
                             update_dns_record(data['subdomain'],
-                                              str(reply.q.qname), 'AAAA', ips)
+                                              data['domain'], 'AAAA', ips)
                         else:
                             new_record = Record(AAAA, ips[idx])
                     else:
@@ -208,29 +468,114 @@ class Resolver:
                         new_record = Record(AAAA, ips[0])
                         ips = '/'.join(ips[1:] + [ips[0]])
                         update_dns_record(data['subdomain'],
-                                          str(reply.q.qname), 'AAAA', ips)
+                                          data['domain'], 'AAAA', ips)
+        elif QTYPE[reply.q.qtype] == 'MX':
+            # provisioned sessions receive mail on the operator's exchanger
+            uid = get_uid(qname)
+            key = get_dkim_record(uid) if uid else None
+            if key != None and key.get('mx') and qname.lower(
+            ) == uid + '.' + ZONE + '.':
+                new_record = Record(MX, key['mx'].rstrip('.') + '.', 10)
+        elif QTYPE[reply.q.qtype] in ('HTTPS', 'SVCB'):
+            data = lookup_record(qname, QTYPE[reply.q.qtype])
+            if data != None:
+                try:
+                    rr = RR.fromZone('{} 1 IN {} {}'.format(
+                        qname, QTYPE[reply.q.qtype], data['value']))[0]
+                    new_record = Record(rr.rdata, rtype=rr.rtype)
+                except Exception as ex:
+                    record_error(get_uid(qname), 'svcb', ex)
 
         if new_record != None:
+            # records made by the rebinding helper carry their own TTL
+            if data != None and data.get('ttl') is not None:
+                new_record.kwargs['ttl'] = data['ttl']
             reply.add_answer(new_record.try_rr(request.q))
+
+        if action == 'discard':
+            return reply
+
+        # every query type is logged, even the ones without an answer
+        try:
+            save_into_db(reply, handler.client_address[0], handler.request[0],
+                         parse_edns(request))
+        except Exception as ex:
+            record_error(get_uid(qname), 'store', ex)
+
+        if INTERACTSH:
             try:
-                save_into_db(reply, handler.client_address[0],
-                             handler.request[0])
+                save_interactsh(request, reply, handler.client_address[0])
             except Exception as ex:
-                print(ex)
-                pass
+                log.error('interactsh interaction failed: %s', ex)
 
         return reply
 
 
+DNS_ADDRESS = os.environ.get('DNS_ADDRESS', '0.0.0.0')
+# port 0 binds an ephemeral port, handy for tests
+DNS_PORT = int(os.environ.get('DNS_PORT', 53))
+
+# attempts at finding an ephemeral port that is free for both transports
+DNS_BIND_ATTEMPTS = 10
+
+
+def make_servers(resolver, port):
+    # TCP binds the port UDP actually got, so with port 0 both transports
+    # still answer on the same one
+    for attempt in range(DNS_BIND_ATTEMPTS if port == 0 else 1):
+        udp = DNSServer(resolver, port=port, address=DNS_ADDRESS, tcp=False)
+        try:
+            tcp = DNSServer(resolver,
+                            port=udp.server.server_address[1],
+                            address=DNS_ADDRESS,
+                            tcp=True)
+        except OSError:
+            udp.server.server_close()
+            if port != 0 or attempt == DNS_BIND_ATTEMPTS - 1:
+                raise
+            continue
+        return [tcp, udp]
+
+
 resolver = Resolver()
-servers = [
-    DNSServer(resolver, port=53, address='0.0.0.0', tcp=True),
-    DNSServer(resolver, port=53, address='0.0.0.0', tcp=False),
-]
+servers = make_servers(resolver, DNS_PORT)
+
+
+def bound_addresses():
+    tcp, udp = servers
+    return {
+        'tcp': tcp.server.server_address,
+        'udp': udp.server.server_address
+    }
+
+
+def reload_on_signal(signum, frame):
+    try:
+        log.info('settings reloaded: %s', reload_settings())
+    except (OSError, ValueError) as ex:
+        log.error('settings reload failed: %s', ex)
+
+
+def log_overrides():
+    overrides = get_log_levels()
+    if overrides is None:
+        return {}
+    return {x['module']: x['level'] for x in overrides['levels']}
+
 
 if __name__ == '__main__':
+    setup_logging()
+    watch_levels(log_overrides)
+    signal.signal(signal.SIGHUP, reload_on_signal)
+    if DNSSEC:
+        load_keys()
+        try:
+            set_dnssec_material(public_material())
+        except Exception as ex:
+            log.error('could not publish DNSSEC keys: %s', ex)
     for s in servers:
         s.start_thread()
+    log.info('DNS server listening on %s', bound_addresses())
 
     try:
         while 1:
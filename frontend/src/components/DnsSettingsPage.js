@@ -28,14 +28,14 @@ export class DnsSettingsPage extends Component {
         if (typeof value !== 'string') value = '';
         if (typeof type !== 'number') type = 0;
         const { dnsRecords } = this.state;
-        dnsRecords.push({ 'domain': domain, 'type': type, 'value': value, 'subdomain': this.props.user.subdomain });
+        dnsRecords.push({ 'domain': domain, 'type': type, 'value': value, 'ttl': null, 'subdomain': this.props.user.subdomain });
         this.setState({ dnsRecords: dnsRecords });
     }
 
-    handleRecordInputChange(index, domain, type, value, toDelete) {
+    handleRecordInputChange(index, domain, type, value, ttl, toDelete) {
         const dnsRecords = this.state.dnsRecords;
         if (toDelete === false) {
-            dnsRecords[index] = { domain: domain, type: type, value: value };
+            dnsRecords[index] = { domain: domain, type: type, value: value, ttl: ttl };
         } else {
             dnsRecords.splice(index, 1);
         }
@@ -80,7 +80,7 @@ export class DnsSettingsPage extends Component {
         dnsRecords = this.state.dnsRecords.map((element) => {
             try {
                 if (typeof element.type === 'string') {
-                    element.type = ['A', 'AAAA', 'CNAME', 'TXT'].indexOf(element.type);
+                    element.type = ['A', 'AAAA', 'CNAME', 'TXT', 'HTTPS', 'SVCB'].indexOf(element.type);
                 }
                 if (element.domain.lastIndexOf(this.props.user.subdomain + '.' + 'requestrepo.com') >= 0) {
                     element.domain = element.domain.substr(0, element.domain.lastIndexOf(this.props.user.subdomain + '.' + 'requestrepo.com') - 1);
@@ -98,6 +98,7 @@ export class DnsSettingsPage extends Component {
                     type={element['type']}
                     domain={element['domain']}
                     value={element['value']}
+                    ttl={element['ttl']}
                     subdomain={element['subdomain'] ? element['subdomain'] : this.props.user.subdomain}
                     handleRecordInputChange={this.handleRecordInputChange}
                 />
@@ -120,7 +121,7 @@ export class DnsSettingsPage extends Component {
                     </div>
                 </div>
                 <div className="grid">
-                    <p>You can use / to cycle through the IPs or % to select a random IP (e.g. 127.0.0.1/8.8.8.8 or 127.0.0.1%8.8.8.8)</p>
+                    <p>You can use / to cycle through the IPs or % to select a random IP (e.g. 127.0.0.1/8.8.8.8 or 127.0.0.1%8.8.8.8). Records answer with a TTL of 1 unless one is set</p>
                 </div>
                 <div className="grid">
                     <div className="col-12">
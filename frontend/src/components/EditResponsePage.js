@@ -1,6 +1,7 @@
 import React, { Component } from 'react';
 import { Button } from 'primereact/button';
 import { InputText } from 'primereact/inputtext';
+import { Checkbox } from 'primereact/checkbox';
 import { AutoComplete } from 'primereact/autocomplete';
 import { HeaderService } from '../service/HeaderService';
 import AceEditor from 'react-ace';
@@ -20,7 +21,11 @@ export class EditResponsePage extends Component {
 			headers: (this.props.headers ? this.props.headers : []),
 			content: (this.props.content ? this.props.content : ""),
 			fetched: (this.props.fetched ? this.props.fetched : false),
-			statusCode: (this.props.statusCode ? this.props.statusCode : 200)
+			statusCode: (this.props.statusCode ? this.props.statusCode : 200),
+			nosniff: false,
+			download: false,
+			variants: {},
+			paths: {}
 		};
 
 		if (!this.state.fetched) {
@@ -30,6 +35,9 @@ export class EditResponsePage extends Component {
 					this.setState({ content: atob(res['raw']) });
 				} catch { }
 				this.setState({ statusCode: res['status_code'] });
+				this.setState({ nosniff: res['nosniff'] === true, download: res['download'] === true });
+				// language variants and path responses are kept as they are, the editor only changes the catch-all
+				this.setState({ variants: res['variants'] || {}, paths: res['paths'] || {} });
 				this.setState({ fetched: true });
 			});
 		}
@@ -63,6 +71,10 @@ export class EditResponsePage extends Component {
 		});
 		obj['status_code'] = this.state.statusCode;
 		obj['raw'] = btoa(this.state.content);
+		obj['nosniff'] = this.state.nosniff;
+		obj['download'] = this.state.download;
+		obj['variants'] = this.state.variants;
+		obj['paths'] = this.state.paths;
 		Utils.updateFile(obj).then(res => {
 			if (res.error) {
 				this.props.toast.error(res.error, {
@@ -88,6 +100,8 @@ export class EditResponsePage extends Component {
 						this.setState({ content: atob(res['raw']) });
 					} catch { }
 					this.setState({ statusCode: res['status_code'] });
+					this.setState({ nosniff: res['nosniff'] === true, download: res['download'] === true });
+					this.setState({ variants: res['variants'] || {}, paths: res['paths'] || {} });
 					this.setState({ fetched: true });
 				});
 			}
@@ -185,6 +199,12 @@ export class EditResponsePage extends Component {
 							}
 						}
 						} />
+						<div style={{ marginTop: '10px' }}>
+							<Checkbox inputId="nosniff" checked={this.state.nosniff} onChange={(e) => this.setState({ nosniff: e.checked })} />
+							<label htmlFor="nosniff" style={{ marginLeft: '5px', marginRight: '20px' }}>Send X-Content-Type-Options: nosniff</label>
+							<Checkbox inputId="download" checked={this.state.download} onChange={(e) => this.setState({ download: e.checked })} />
+							<label htmlFor="download" style={{ marginLeft: '5px' }}>Force download (Content-Disposition from the path)</label>
+						</div>
 					</div>
 					<div className="col-12">
 						<div className="grid">
@@ -11,6 +11,7 @@ export class RecordInput extends Component {
             type: (this.props.type ? this.props.type : 0),
             domain: (this.props.domain ? this.props.domain : ""),
             value: (this.props.value ? this.props.value : ""),
+            ttl: (typeof this.props.ttl === 'number' ? this.props.ttl : null),
             index: (this.props.index ? this.props.index : 0)
         };
         this.changeEvent = this.changeEvent.bind(this);
@@ -18,16 +19,21 @@ export class RecordInput extends Component {
 
     changeEvent(event, action) {
         if (action === 'domain') {
-            this.props.handleRecordInputChange(this.props.index, event.target.value||'', this.state.type, this.state.value, false);
+            this.props.handleRecordInputChange(this.props.index, event.target.value||'', this.state.type, this.state.value, this.state.ttl, false);
             this.setState({domain:event.target.value||''});
         } else if (action === 'type') {
-            this.props.handleRecordInputChange(this.props.index, this.state.domain, event.value, this.state.value, false);
+            this.props.handleRecordInputChange(this.props.index, this.state.domain, event.value, this.state.value, this.state.ttl, false);
             this.setState({type:event.value});
         } else if (action === 'value') {
-            this.props.handleRecordInputChange(this.props.index, this.state.domain, this.state.type, event.target.value||'', false);
+            this.props.handleRecordInputChange(this.props.index, this.state.domain, this.state.type, event.target.value||'', this.state.ttl, false);
             this.setState({value:event.target.value||''});
+        } else if (action === 'ttl') {
+            // left empty for the default
+            const ttl = event.target.value === '' ? null : parseInt(event.target.value, 10);
+            this.props.handleRecordInputChange(this.props.index, this.state.domain, this.state.type, this.state.value, ttl, false);
+            this.setState({ttl:ttl});
         } else if (action === 'delete') {
-            this.props.handleRecordInputChange(this.props.index, this.state.domain, this.state.type, this.state.value, true);
+            this.props.handleRecordInputChange(this.props.index, this.state.domain, this.state.type, this.state.value, this.state.ttl, true);
         }
     }
 
@@ -36,12 +42,13 @@ export class RecordInput extends Component {
             domain: newProps.domain,
             type: newProps.type,
             value: newProps.value,
+            ttl: (typeof newProps.ttl === 'number' ? newProps.ttl : null),
             index: newProps.index
         });
     }
 
     shouldComponentUpdate(nextProps) {
-        return this.state.domain != nextProps.domain || this.state.type != nextProps.type || this.state.value != nextProps.value || this.state.index != nextProps.index;
+        return this.state.domain != nextProps.domain || this.state.type != nextProps.type || this.state.value != nextProps.value || this.state.ttl != nextProps.ttl || this.state.index != nextProps.index;
     }
 
     render() {
@@ -49,7 +56,9 @@ export class RecordInput extends Component {
             {label: 'A', value: 0},
             {label: 'AAAA', value: 1},
             {label: 'CNAME', value: 2},
-            {label: 'TXT', value: 3}
+            {label: 'TXT', value: 3},
+            {label: 'HTTPS', value: 4},
+            {label: 'SVCB', value: 5}
         ];
 
         return (
@@ -71,6 +80,10 @@ export class RecordInput extends Component {
                         onClick={event => this.changeEvent(event, 'delete')}
                     />
                 </div>
+                <div className="col-2">
+                    <label>TTL: </label>
+                    <InputText style={{maxWidth:"60%"}} placeholder="1" value={this.state.ttl === null ? '' : this.state.ttl} onChange={(e) => {if (/^[0-9]{0,4}$/.test(e.target.value) && (e.target.value === '' || parseInt(e.target.value, 10) <= 3600)) this.changeEvent(e, 'ttl')}} />
+                </div>
             </div>
         );
     }
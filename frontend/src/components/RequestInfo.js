@@ -32,18 +32,32 @@ export class RequestInfo extends Component {
 
     render() {
         let request = this.props.request;
+        if (request.encrypted !== undefined) {
+            return (
+                <div className="grid">
+                    <div className="col-12">
+                        <h1>Encrypted request</h1>
+                        <p>This request is encrypted at rest. Send your passphrase in the X-Encryption-Passphrase header to the API to read it.</p>
+                    </div>
+                </div>
+            );
+        }
+
         let data = atob(request.raw);
 
-        let headerKeys;
-        if (request.headers)
-            headerKeys = Object.keys(request.headers);
+        // every header line with repeats, older captures only have the joined headers
+        let headerPairs = [];
+        if (request.header_list)
+            headerPairs = request.header_list;
+        else if (request.headers)
+            headerPairs = Object.keys(request.headers).map(item => [item, request.headers[item]]);
 
         if (request.name===undefined) {
             data = request.method+ " " + request.path + " " + request.protocol + "\r\n";
             data += 'Host' + ": " + request.headers['Host'] + "\r\n";
-            headerKeys.map( (item, index) => {
-                if (item !== 'Host') {
-                    data += item + ": " + request.headers[item] + "\r\n";
+            headerPairs.map( ([name, value], index) => {
+                if (name !== 'Host') {
+                    data += name + ": " + value + "\r\n";
                 }
             });
             data += "\r\n";
@@ -79,6 +93,70 @@ export class RequestInfo extends Component {
                             <td className="req-table-a">Date</td>
                             <td className="req-table-b">{this.convertUTCDateToLocalDate(request.date).toLocaleString()}</td>
                         </tr>
+                        {request.count > 1 &&
+                        <tr>
+                            <td className="req-table-a">Repeated</td>
+                            <td className="req-table-b">{request.count} times, last on {this.convertUTCDateToLocalDate(request.last).toLocaleString()}</td>
+                        </tr>
+                        }
+                        {request.port !== undefined &&
+                        <tr>
+                            <td className="req-table-a">Port</td>
+                            <td className="req-table-b">{request.port}</td>
+                        </tr>
+                        }
+                        {request.management === true &&
+                        <tr>
+                            <td className="req-table-a">Origin</td>
+                            <td className="req-table-b">Management (sent with this session's token)</td>
+                        </tr>
+                        }
+                        {request.language !== undefined &&
+                        <tr>
+                            <td className="req-table-a">Served variant</td>
+                            <td className="req-table-b">{request.language}</td>
+                        </tr>
+                        }
+                        {request.trace !== undefined &&
+                        <tr>
+                            <td className="req-table-a">Trace ID</td>
+                            <td className="req-table-b">{request.trace.trace_id}</td>
+                        </tr>
+                        }
+                        {request.tls !== undefined &&
+                        <tr>
+                            <td className="req-table-a">TLS</td>
+                            <td className="req-table-b">{request.tls.protocol} {request.tls.cipher}{request.tls.resumed ? " (resumed)" : ""}</td>
+                        </tr>
+                        }
+                        {request.ja3 !== undefined &&
+                        <tr>
+                            <td className="req-table-a">JA3</td>
+                            <td className="req-table-b">{request.ja3}</td>
+                        </tr>
+                        }
+                        {request.ja4 !== undefined &&
+                        <tr>
+                            <td className="req-table-a">JA4</td>
+                            <td className="req-table-b">{request.ja4}</td>
+                        </tr>
+                        }
+                        {request.findings !== undefined &&
+                        <tr>
+                            <td className="req-table-a">Findings</td>
+                            <td className="req-table-b">
+                                {request.findings.map( (finding, index) => {
+                                    return (<div key={index}>{finding.kind} in {finding.location}{finding.name ? " (" + finding.name + ")" : ""}: {finding.preview}</div>);
+                                })}
+                            </td>
+                        </tr>
+                        }
+                        {request.bot_score !== undefined &&
+                        <tr>
+                            <td className="req-table-a">Bot score</td>
+                            <td className="req-table-b">{request.bot_score}</td>
+                        </tr>
+                        }
                         <tr>
                             <td className="req-table-a">Path</td>
                             <td className="req-table-b">{request.path}</td>
@@ -95,10 +173,10 @@ export class RequestInfo extends Component {
                     <table className="req-table">
                         <tbody>
                         {
-                            headerKeys.map( (item, index) => {
+                            headerPairs.map( ([name, value], index) => {
                                 return (<tr key={index}>
-                                    <td className="req-table-a">{item}</td>
-                                    <td className="req-table-b">{request.headers[item]}</td>
+                                    <td className="req-table-a">{name}</td>
+                                    <td className="req-table-b">{value}</td>
                                 </tr>);
                             })
                         }
@@ -133,6 +211,42 @@ export class RequestInfo extends Component {
                         : <p>(empty)</p>
                     }
                 </div>
+                {request.parsed !== undefined &&
+                <div className="col-12">
+                    <h1>Parsed Body</h1>
+                    {request.parsed.type === 'json' &&
+                        <pre style={{"maxHeight":"400px"}}>{JSON.stringify(request.parsed.value, null, 2)}</pre>
+                    }
+                    {request.parsed.type === 'form' &&
+                        <table className="req-table">
+                            <tbody>
+                            {
+                                request.parsed.fields.map( (field, index) => {
+                                    return (<tr key={index}>
+                                        <td className="req-table-a">{field.name}</td>
+                                        <td className="req-table-b">{field.value}</td>
+                                    </tr>);
+                                })
+                            }
+                            </tbody>
+                        </table>
+                    }
+                    {request.parsed.type === 'multipart' &&
+                        <table className="req-table">
+                            <tbody>
+                            {
+                                request.parsed.parts.map( (part, index) => {
+                                    return (<tr key={index}>
+                                        <td className="req-table-a">{part.name}{part.filename !== undefined ? " (" + part.filename + ")" : ""}</td>
+                                        <td className="req-table-b">{part.value !== undefined ? part.value : "(" + part.size + " bytes" + (part.content_type ? ", " + part.content_type : "") + ")"}</td>
+                                    </tr>);
+                                })
+                            }
+                            </tbody>
+                        </table>
+                    }
+                </div>
+                }
                 <div className="col-12 raw-req">
                     <h1>Raw request</h1>
                     <InputText type="text" style={{"width":"100%"}} value={btoa(data)}/>
@@ -166,12 +280,38 @@ export class RequestInfo extends Component {
                             <td className="req-table-a">Type</td>
                             <td className="req-table-b">{request.type}</td>
                         </tr>
+                        {request.edns !== undefined &&
+                        <tr>
+                            <td className="req-table-a">EDNS</td>
+                            <td className="req-table-b">version {request.edns.version}, {request.edns.udp_size} bytes{request.edns.do ? ", DNSSEC OK" : ""}</td>
+                        </tr>
+                        }
+                        {request.edns !== undefined && request.edns.client_subnet !== undefined &&
+                        <tr>
+                            <td className="req-table-a">Client subnet</td>
+                            <td className="req-table-b">{request.edns.client_subnet.subnet}</td>
+                        </tr>
+                        }
                         </tbody>
                     </table>
                 </div>
                 <div className="col-12">
                     <h1>Reply</h1>
-                    <pre style={{"overflowWrap":"break-word"}}>{request.reply}</pre>
+                    {request.answers !== undefined
+                        ? <table className="req-table">
+                            <tbody>
+                            {
+                                request.answers.map( (answer, index) => {
+                                    return (<tr key={index}>
+                                        <td className="req-table-a">{answer.name}</td>
+                                        <td className="req-table-b">{answer.ttl} IN {answer.type} {answer.value}</td>
+                                    </tr>);
+                                })
+                            }
+                            </tbody>
+                          </table>
+                        : <pre style={{"overflowWrap":"break-word"}}>{request.reply}</pre>
+                    }
                 </div>
                 <div className="col-12 raw-req">
                     <h1>Raw request</h1>
@@ -0,0 +1,29 @@
+// picks the certificate for the SNI name, see backend/certs.py
+const fs = require('fs');
+
+const MINTED = /^([a-z0-9_-]+\.)+[a-z0-9_-]+\.[a-z0-9]+\.requestrepo\.com$/;
+
+function minted(r) {
+    const name = (r.variables.ssl_server_name || '').toLowerCase();
+    if (!MINTED.test(name)) {
+        return null;
+    }
+    // minted files hold the chain and the key
+    const path = '/etc/nginx/certs/' + name + '.pem';
+    try {
+        fs.accessSync(path, fs.constants.R_OK);
+        return path;
+    } catch (e) {
+        return null;
+    }
+}
+
+function cert_file(r) {
+    return minted(r) || '/etc/nginx/fullchain.pem';
+}
+
+function key_file(r) {
+    return minted(r) || '/etc/nginx/privkey.pem';
+}
+
+export default { cert_file, key_file };
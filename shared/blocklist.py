@@ -0,0 +1,39 @@
+import ipaddress
+import re
+
+# discard serves the request but keeps it out of the logs, drop refuses it
+BLOCK_ACTIONS = ['discard', 'drop']
+BLOCKLIST_MAX_ENTRIES = 100
+
+
+def valid_network(network):
+    try:
+        ipaddress.ip_network(network, strict=False)
+    except (TypeError, ValueError):
+        return False
+    return True
+
+
+def valid_country(country):
+    return type(country) is str and re.match('^[A-Za-z]{2}$',
+                                             country) is not None
+
+
+def is_blocked(ip, country, blocklist):
+    if country and country.upper() in blocklist.get('countries', []):
+        return True
+    try:
+        address = ipaddress.ip_address(ip)
+    except ValueError:
+        return False
+    return any(address in ipaddress.ip_network(network, strict=False)
+               for network in blocklist.get('networks', [])
+               if valid_network(network))
+
+
+def block_action(ip, country, *blocklists):
+    # the first list that matches decides, the operator's comes first
+    for blocklist in blocklists:
+        if blocklist and is_blocked(ip, country, blocklist):
+            return blocklist.get('action', 'discard')
+    return None
@@ -0,0 +1,168 @@
+import base64
+import json
+import logging
+import os
+import queue
+import threading
+import time
+import urllib.request
+
+# base url of an Elasticsearch or OpenSearch cluster; unset disables the sink
+ELASTICSEARCH_URL = os.environ.get('ELASTICSEARCH_URL')
+ELASTICSEARCH_USERNAME = os.environ.get('ELASTICSEARCH_USERNAME')
+ELASTICSEARCH_PASSWORD = os.environ.get('ELASTICSEARCH_PASSWORD')
+ELASTICSEARCH_INDEX_PREFIX = os.environ.get('ELASTICSEARCH_INDEX_PREFIX',
+                                            'requestrepo')
+
+BATCH_SIZE = 200
+FLUSH_INTERVAL = 2
+MAX_RETRIES = 5
+QUEUE_SIZE = 10000
+
+log = logging.getLogger(__name__)
+
+MAPPINGS = {
+    'http': {
+        'uid': {'type': 'keyword'},
+        'ip': {'type': 'ip'},
+        'date': {'type': 'date', 'format': 'epoch_second'},
+        'method': {'type': 'keyword'},
+        'path': {'type': 'keyword', 'ignore_above': 2048},
+        'query': {'type': 'text'},
+        'url': {'type': 'keyword', 'ignore_above': 4096},
+        'protocol': {'type': 'keyword'},
+        'headers': {'type': 'flattened'},
+        'raw': {'type': 'binary'},
+        'bot_score': {'type': 'float'},
+        'ja3': {'type': 'keyword'},
+        'ja4': {'type': 'keyword'},
+    },
+    'dns': {
+        'uid': {'type': 'keyword'},
+        'ip': {'type': 'ip'},
+        'date': {'type': 'date', 'format': 'epoch_second'},
+        'type': {'type': 'keyword'},
+        'name': {'type': 'keyword'},
+        'answers': {'type': 'object', 'enabled': False},
+        'raw': {'type': 'binary'},
+    },
+}
+
+
+class ElasticsearchSink:
+    def __init__(self, url):
+        self.url = url.rstrip('/')
+        self.queue = queue.Queue(maxsize=QUEUE_SIZE)
+        self.dropped = 0
+        self.thread = None
+        self.lock = threading.Lock()
+
+    def request(self, method, path, body, content_type='application/json'):
+        req = urllib.request.Request(self.url + path,
+                                     data=body,
+                                     method=method)
+        req.add_header('Content-Type', content_type)
+        if ELASTICSEARCH_USERNAME:
+            credentials = '%s:%s' % (ELASTICSEARCH_USERNAME,
+                                     ELASTICSEARCH_PASSWORD or '')
+            req.add_header(
+                'Authorization',
+                'Basic ' + str(base64.b64encode(credentials.encode()), 'utf-8'))
+        with urllib.request.urlopen(req, timeout=10) as resp:
+            return json.loads(resp.read() or b'{}')
+
+    def headers_mapping(self):
+        # 'flattened' only exists on Elasticsearch, OpenSearch 2.7+ has
+        # 'flat_object' and anything older just stores the headers unindexed
+        version = self.request('GET', '/', None).get('version', {})
+        if version.get('distribution') != 'opensearch':
+            return {'type': 'flattened'}
+        number = tuple(
+            int(part) for part in version.get('number', '0').split('.')[:2]
+            if part.isdigit())
+        if number >= (2, 7):
+            return {'type': 'flat_object'}
+        return {'type': 'object', 'enabled': False}
+
+    def ensure_templates(self):
+        headers = self.headers_mapping()
+        for rtype, properties in MAPPINGS.items():
+            name = f'{ELASTICSEARCH_INDEX_PREFIX}-{rtype}'
+            if 'headers' in properties:
+                properties = dict(properties, headers=headers)
+            template = {
+                'index_patterns': [name + '*'],
+                'template': {
+                    'mappings': {
+                        'dynamic': True,
+                        'properties': properties
+                    }
+                }
+            }
+            self.request('PUT', f'/_index_template/{name}',
+                         json.dumps(template).encode())
+
+    def index(self, rtype, dic):
+        doc = {}
+        for key, value in dic.items():
+            if key.startswith('_'):
+                continue
+            if type(value) is bytes:
+                value = str(base64.b64encode(value), 'utf-8')
+            doc[key] = value
+        doc['request_id'] = str(dic['_id'])
+
+        with self.lock:
+            if self.thread is None:
+                self.thread = threading.Thread(target=self.run, daemon=True)
+                self.thread.start()
+        try:
+            self.queue.put_nowait((rtype, doc))
+        except queue.Full:
+            self.dropped += 1
+
+    def run(self):
+        try:
+            self.ensure_templates()
+        except Exception as ex:
+            log.error(f'could not install templates: {ex}')
+
+        while True:
+            batch = [self.queue.get()]
+            deadline = time.time() + FLUSH_INTERVAL
+            while len(batch) < BATCH_SIZE and time.time() < deadline:
+                try:
+                    batch.append(
+                        self.queue.get(timeout=max(0, deadline - time.time())))
+                except queue.Empty:
+                    break
+            self.send(batch)
+
+    def send(self, batch):
+        lines = []
+        for rtype, doc in batch:
+            lines.append(
+                json.dumps({
+                    'index': {
+                        '_index': f'{ELASTICSEARCH_INDEX_PREFIX}-{rtype}',
+                        '_id': doc['request_id']
+                    }
+                }))
+            lines.append(json.dumps(doc))
+        body = ('\n'.join(lines) + '\n').encode()
+
+        for attempt in range(MAX_RETRIES):
+            try:
+                result = self.request('POST', '/_bulk', body,
+                                      'application/x-ndjson')
+                if result.get('errors'):
+                    log.warning('some documents were rejected')
+                return
+            except Exception as ex:
+                log.warning(f'bulk request failed: {ex}')
+                time.sleep(2**attempt)
+        self.dropped += len(batch)
+
+
+elastic_sink = ElasticsearchSink(
+    ELASTICSEARCH_URL) if ELASTICSEARCH_URL else None
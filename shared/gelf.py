@@ -0,0 +1,86 @@
+import gzip
+import json
+import os
+import socket
+import threading
+
+# host:port of a Graylog GELF input; unset disables the output
+GELF_ADDRESS = os.environ.get('GELF_ADDRESS')
+GELF_PROTOCOL = os.environ.get('GELF_PROTOCOL', 'udp').lower()
+
+GELF_CHUNK_SIZE = 8192
+GELF_MAX_CHUNKS = 128
+GELF_CHUNK_MAGIC = b'\x1e\x0f'
+
+# the TCP connection is kept open between messages
+tcp_lock = threading.Lock()
+tcp_socket = None
+
+
+def gelf_message(host, short_message, timestamp, fields):
+    message = {
+        'version': '1.1',
+        'host': host,
+        'short_message': short_message,
+        'timestamp': timestamp,
+        'level': 6,
+    }
+    for key, value in fields.items():
+        if value is None or key == 'id':
+            continue
+        if type(value) not in (str, int, float):
+            value = json.dumps(value)
+        message['_' + key] = value
+    return message
+
+
+def gelf_chunks(data):
+    if len(data) <= GELF_CHUNK_SIZE:
+        return [data]
+
+    count = (len(data) + GELF_CHUNK_SIZE - 1) // GELF_CHUNK_SIZE
+    if count > GELF_MAX_CHUNKS:
+        raise ValueError('GELF message too large')
+
+    message_id = os.urandom(8)
+    return [
+        GELF_CHUNK_MAGIC + message_id + bytes([seq, count]) +
+        data[seq * GELF_CHUNK_SIZE:(seq + 1) * GELF_CHUNK_SIZE]
+        for seq in range(count)
+    ]
+
+
+def gelf_send_tcp(address, frame):
+    global tcp_socket
+    with tcp_lock:
+        # a connection closed by the server is only noticed when writing to
+        # it, so a failed write is retried once on a new one
+        for attempt in range(2):
+            if tcp_socket is None:
+                tcp_socket = socket.create_connection(address, timeout=2)
+            try:
+                tcp_socket.sendall(frame)
+                return
+            except OSError:
+                tcp_socket.close()
+                tcp_socket = None
+                if attempt:
+                    raise
+
+
+def gelf_send(message):
+    if not GELF_ADDRESS:
+        return
+
+    host, _, port = GELF_ADDRESS.rpartition(':')
+    address = (host, int(port))
+    data = json.dumps(message).encode()
+
+    if GELF_PROTOCOL == 'tcp':
+        # TCP frames are null-terminated and can't be compressed
+        gelf_send_tcp(address, data + b'\x00')
+        return
+
+    with socket.socket(socket.AF_INET, socket.SOCK_DGRAM) as sock:
+        for chunk in gelf_chunks(gzip.compress(data)):
+            sock.sendto(chunk, address)
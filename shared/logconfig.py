@@ -0,0 +1,81 @@
+import datetime
+import json
+import logging
+import os
+import sys
+import threading
+import time
+
+# text or json, one object per line for log shippers
+LOG_FORMAT = os.getenv('LOG_FORMAT', 'text').lower()
+LOG_LEVEL = os.getenv('LOG_LEVEL', 'INFO').upper()
+# per module levels, e.g. "ns=DEBUG,notify=WARNING"
+LOG_LEVELS = os.getenv('LOG_LEVELS', '')
+LOG_LEVELS_INTERVAL = 5
+LEVELS = ['DEBUG', 'INFO', 'WARNING', 'ERROR', 'CRITICAL']
+
+log = logging.getLogger('logconfig')
+
+
+class JsonFormatter(logging.Formatter):
+    def format(self, record):
+        entry = {
+            'time':
+            datetime.datetime.fromtimestamp(
+                record.created, datetime.timezone.utc).isoformat(),
+            'level': record.levelname,
+            'module': record.name,
+            'message': record.getMessage()
+        }
+        if record.exc_info:
+            entry['exception'] = self.formatException(record.exc_info)
+        return json.dumps(entry)
+
+
+def parse_levels(value):
+    levels = {}
+    for item in value.split(','):
+        name, _, level = item.strip().partition('=')
+        if name and level.upper() in LEVELS:
+            levels[name] = level.upper()
+    return levels
+
+
+configured = parse_levels(LOG_LEVELS)
+applied = set()
+
+
+def setup_logging():
+    handler = logging.StreamHandler(sys.stdout)
+    if LOG_FORMAT == 'json':
+        handler.setFormatter(JsonFormatter())
+    else:
+        handler.setFormatter(
+            logging.Formatter('%(asctime)s %(levelname)s %(name)s: %(message)s'))
+    root = logging.getLogger()
+    root.handlers = [handler]
+    root.setLevel(LOG_LEVEL if LOG_LEVEL in LEVELS else 'INFO')
+    apply_levels({})
+
+
+def apply_levels(overrides):
+    # overrides win over LOG_LEVELS, modules they no longer name fall back
+    levels = dict(configured, **overrides)
+    for name in applied - set(levels):
+        logging.getLogger(name).setLevel(logging.NOTSET)
+    for name, level in levels.items():
+        logging.getLogger(name).setLevel(level)
+    applied.clear()
+    applied.update(levels)
+
+
+def watch_levels(get_overrides):
+    def run():
+        while True:
+            try:
+                apply_levels(get_overrides())
+            except Exception as ex:
+                log.warning(f'could not load log levels: {ex}')
+            time.sleep(LOG_LEVELS_INTERVAL)
+
+    threading.Thread(target=run, daemon=True).start()
@@ -0,0 +1,88 @@
+import logging
+import smtplib
+import threading
+from email.message import EmailMessage
+from settings import setting
+
+log = logging.getLogger(__name__)
+
+
+def alert_matches(alert_filter, rtype, path):
+    if alert_filter.get('type') and alert_filter['type'].upper() != rtype:
+        return False
+    if alert_filter.get('path_contains') and alert_filter[
+            'path_contains'] not in (path or ''):
+        return False
+    return True
+
+
+def relay_connection():
+    smtp = smtplib.SMTP(setting('SMTP_RELAY_HOST'),
+                        setting('SMTP_RELAY_PORT'),
+                        timeout=10)
+    try:
+        if setting('SMTP_RELAY_STARTTLS'):
+            smtp.starttls()
+        if setting('SMTP_RELAY_USERNAME'):
+            smtp.login(setting('SMTP_RELAY_USERNAME'),
+                       setting('SMTP_RELAY_PASSWORD') or '')
+    except Exception:
+        smtp.close()
+        raise
+    return smtp
+
+
+def send_email(to, subject, body):
+    message = EmailMessage()
+    message['From'] = setting('SMTP_FROM')
+    message['To'] = to
+    message['Subject'] = subject
+    message.set_content(body)
+
+    try:
+        with relay_connection() as smtp:
+            smtp.send_message(message)
+    except Exception as ex:
+        log.warning(f'alert email to {to} failed: {ex}')
+
+
+def confirmation_email(claim_budget, to, reason, link):
+    if not claim_budget(setting('EMAIL_MAX_PER_HOUR')):
+        log.warning(f'hourly email budget spent, not confirming {to}')
+        return False
+
+    body = f'{reason}.\n\nOpen {link} to confirm this address, or ignore this email.\n'
+    threading.Thread(target=send_email,
+                     args=(to, '[requestrepo] Confirm your email address',
+                           body),
+                     daemon=True).start()
+    return True
+
+
+def email_alert(get_alert, claim, claim_budget, subdomain, rtype, path,
+                summary):
+    if not setting('SMTP_RELAY_HOST'):
+        return
+
+    alert = get_alert(subdomain)
+    # recipients confirm the address before anything is sent to it
+    if not alert or not alert.get('email') or not alert.get('confirmed'):
+        return
+    if not alert_matches(alert.get('filter') or {}, rtype, path):
+        return
+
+    suppressed = claim(subdomain, setting('EMAIL_ALERT_COOLDOWN'))
+    if suppressed is None:
+        return
+    if not claim_budget(setting('EMAIL_MAX_PER_HOUR')):
+        log.warning(f'hourly email budget spent, dropping alert for {subdomain}')
+        return
+
+    body = f'A {rtype} request matching your alert arrived on {subdomain}:\n\n{summary}\n'
+    if suppressed:
+        body += f'\n{suppressed} more matching requests arrived during the cooldown.\n'
+    threading.Thread(target=send_email,
+                     args=(alert['email'],
+                           f'[requestrepo] {rtype} request on {subdomain}',
+                           body),
+                     daemon=True).start()
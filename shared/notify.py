@@ -0,0 +1,66 @@
+import json
+import logging
+import string
+import threading
+import urllib.request
+from settings import setting
+
+log = logging.getLogger(__name__)
+
+DEFAULT_TEMPLATE = 'New $rtype request on $subdomain from $ip: $summary'
+
+WEBHOOK_PREFIXES = {
+    'slack': ['https://hooks.slack.com/'],
+    'discord': [
+        'https://discord.com/api/webhooks/',
+        'https://discordapp.com/api/webhooks/'
+    ],
+}
+
+
+def valid_webhook(kind, url):
+    return type(url) is str and len(url) <= 512 and any(
+        url.startswith(prefix) for prefix in WEBHOOK_PREFIXES[kind])
+
+
+def render_message(settings, fields, suppressed):
+    template = string.Template(settings.get('template') or DEFAULT_TEMPLATE)
+    message = template.safe_substitute(fields)
+    if suppressed:
+        message += f' (+{suppressed} more during cooldown)'
+    return message[:1900]
+
+
+def post_webhook(url, payload):
+    req = urllib.request.Request(url,
+                                 data=json.dumps(payload).encode(),
+                                 method='POST')
+    req.add_header('Content-Type', 'application/json')
+    try:
+        urllib.request.urlopen(req, timeout=5).close()
+    except Exception as ex:
+        log.warning(f'notification to {url[:40]} failed: {ex}')
+
+
+def send_notifications(settings, fields, suppressed):
+    message = render_message(settings, fields, suppressed)
+    if settings.get('slack'):
+        post_webhook(settings['slack'], {'text': message})
+    if settings.get('discord'):
+        post_webhook(settings['discord'], {'content': message})
+
+
+def notify(get_settings, claim, subdomain, fields):
+    settings = get_settings(subdomain)
+    if not settings or not (settings.get('slack') or settings.get('discord')):
+        return
+
+    cooldown = settings.get('cooldown', setting('NOTIFY_COOLDOWN'))
+    suppressed = claim(subdomain, cooldown)
+    if suppressed is None:
+        return
+
+    # webhooks are slow, never hold up the capture on them
+    threading.Thread(target=send_notifications,
+                     args=(settings, fields, suppressed),
+                     daemon=True).start()
@@ -0,0 +1,163 @@
+import json
+import logging
+import os
+import threading
+import time
+try:
+    import tomllib
+except ImportError:
+    tomllib = None
+
+# TOML or JSON file with the settings below, it takes precedence over the
+# environment and is picked up again when it changes, so no restart is needed.
+# Tables are joined to their keys, [smtp] relay_host is SMTP_RELAY_HOST
+SETTINGS_FILE = os.getenv('SETTINGS_FILE')
+SETTINGS_CHECK_INTERVAL = 5
+
+SETTINGS = {
+    'FETCH_DEFAULT_LIMIT': (int, 100),
+    'FETCH_MAX_LIMIT': (int, 1000),
+    'EVENTS_MAX_SUBSCRIPTIONS': (int, 20),
+    'UPLOAD_MAX_SIZE': (int, 10 * 1024 * 1024),
+    # unfinished upload sessions a subdomain can hold at once
+    'UPLOAD_MAX_SESSIONS': (int, 5),
+    'PROVISION_MAX_COUNT': (int, 500),
+    'TRASH_RETENTION_DAYS': (int, 7),
+    'ERRORS_RETENTION_DAYS': (int, 7),
+    'INTERACTSH_RETENTION_DAYS': (int, 30),
+    'UPLOAD_RETENTION_HOURS': (int, 24),
+    'NOTIFY_COOLDOWN': (int, 60),
+    'EMAIL_ALERT_COOLDOWN': (int, 300),
+    # every email the instance sends counts against this, across all sessions
+    'EMAIL_MAX_PER_HOUR': (int, 100),
+    # outbound relay used for alert emails; unset disables email alerts
+    'SMTP_RELAY_HOST': (str, None),
+    'SMTP_RELAY_PORT': (int, 587),
+    'SMTP_RELAY_USERNAME': (str, None),
+    'SMTP_RELAY_PASSWORD': (str, None),
+    'SMTP_RELAY_STARTTLS': (bool, True),
+    'SMTP_FROM': (str, 'alerts@requestrepo.com'),
+    # sessions sending their own emails through the relay, see /api/v2/emails
+    'EMAIL_SEND_ENABLED': (bool, False),
+    'EMAIL_SEND_MAX_PER_HOUR': (int, 10),
+    # new sessions get a DKIM key and an MX record pointing at this host
+    'EMAIL_PROVISION_ENABLED': (bool, False),
+    'EMAIL_PROVISION_MX_HOST': (str, None),
+    # captures from these networks and countries are dropped for every session
+    'BLOCKLIST_NETWORKS': (list, []),
+    'BLOCKLIST_COUNTRIES': (list, []),
+    'BLOCKLIST_ACTION': (str, 'discard'),
+    # captures sent with the session's own token, kept out of sinks and stats
+    'FORWARD_MANAGEMENT': (bool, False),
+    # what captures are answered as, one of requestrepo, nginx, apache, iis;
+    # the header overrides the Server value of the profile
+    'SERVER_PROFILE': (str, 'requestrepo'),
+    'SERVER_HEADER': (str, None),
+}
+SECRET_SETTINGS = {'SMTP_RELAY_PASSWORD'}
+
+log = logging.getLogger(__name__)
+
+lock = threading.Lock()
+values = {}
+loaded_mtime = None
+checked = 0
+
+
+def parse_setting(kind, value):
+    if kind is bool:
+        return value if type(value) is bool else str(value).lower() == 'true'
+    if kind is int and type(value) is bool:
+        raise ValueError('expected an integer')
+    if kind is list:
+        if type(value) is str:
+            value = value.split(',')
+        return [str(x).strip() for x in value if str(x).strip()]
+    return kind(value)
+
+
+def flatten_settings(content, prefix=''):
+    flat = {}
+    for key, value in content.items():
+        name = prefix + str(key).upper()
+        if type(value) is dict:
+            flat.update(flatten_settings(value, name + '_'))
+        else:
+            flat[name] = value
+    return flat
+
+
+def read_settings_file(path):
+    if path.endswith('.toml'):
+        if tomllib is None:
+            raise ValueError('TOML settings need Python 3.11 or newer')
+        with open(path, 'rb') as f:
+            content = tomllib.load(f)
+    else:
+        with open(path) as f:
+            content = json.load(f)
+    if type(content) is not dict:
+        raise ValueError('the settings file should hold an object')
+    return flatten_settings(content)
+
+
+def load_settings():
+    loaded = {}
+    for name, (kind, default) in SETTINGS.items():
+        value = os.getenv(name)
+        loaded[name] = default if value is None else parse_setting(kind, value)
+
+    mtime = None
+    if SETTINGS_FILE and os.path.exists(SETTINGS_FILE):
+        mtime = os.path.getmtime(SETTINGS_FILE)
+        content = read_settings_file(SETTINGS_FILE)
+        unknown = sorted(name for name in content if name not in SETTINGS)
+        if unknown:
+            raise ValueError(f'unknown settings {", ".join(unknown)}')
+        for name, value in content.items():
+            try:
+                loaded[name] = None if value is None else parse_setting(
+                    SETTINGS[name][0], value)
+            except (TypeError, ValueError):
+                raise ValueError(f'invalid value for {name}')
+    return loaded, mtime
+
+
+def reload_settings():
+    # a broken file keeps the previous values, the error goes to the caller
+    global values, loaded_mtime
+    with lock:
+        loaded, mtime = load_settings()
+        changed = sorted(name for name in SETTINGS
+                         if values.get(name) != loaded[name])
+        values = loaded
+        loaded_mtime = mtime
+    return changed
+
+
+def setting(name):
+    global checked
+    if not values or time.time() - checked > SETTINGS_CHECK_INTERVAL:
+        checked = time.time()
+        try:
+            mtime = None
+            if SETTINGS_FILE and os.path.exists(SETTINGS_FILE):
+                mtime = os.path.getmtime(SETTINGS_FILE)
+            if not values or mtime != loaded_mtime:
+                initial = not values
+                changed = reload_settings()
+                if changed and not initial:
+                    log.info(f'settings reloaded: {", ".join(changed)}')
+        except (OSError, ValueError) as ex:
+            log.error(f'settings reload failed: {ex}')
+            if not values:
+                raise
+    return values[name]
+
+
+def settings_status():
+    setting('FETCH_DEFAULT_LIMIT')
+    return {
+        name: '***' if name in SECRET_SETTINGS and value else value
+        for name, value in values.items()
+    }
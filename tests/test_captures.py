@@ -0,0 +1,186 @@
+"""End-to-end capture tests against a running stack.
+
+Start the services with `docker-compose up --build`, then run
+`python -m unittest discover tests`. The domain and routing options are
+read from /api/v2/instance, so the tests follow whatever the stack was
+started with and skip what it has turned off.
+"""
+import base64
+import http.client
+import json
+import os
+import socket
+import struct
+import subprocess
+import time
+import unittest
+import urllib.error
+import urllib.parse
+import urllib.request
+
+BASE_URL = os.getenv('E2E_BASE_URL', 'http://localhost')
+DNS_ADDRESS = os.getenv('E2E_DNS_ADDRESS', '127.0.0.1:53')
+# boots the compose stack when set, and tears it down afterwards
+COMPOSE = os.getenv('E2E_COMPOSE', 'false').lower() == 'true'
+
+
+def api(method, path, token=None, body=None, host=None):
+    headers = {}
+    if token:
+        headers['Cookie'] = 'token=' + token
+    if host:
+        headers['Host'] = host
+    data = None
+    if body is not None:
+        data = json.dumps(body).encode()
+        headers['Content-Type'] = 'application/json'
+    req = urllib.request.Request(BASE_URL + path,
+                                 data=data,
+                                 headers=headers,
+                                 method=method)
+    try:
+        with urllib.request.urlopen(req) as resp:
+            return resp.status, resp.read()
+    except urllib.error.HTTPError as e:
+        return e.code, e.read()
+
+
+def dns_query(name, qtype):
+    host, _, port = DNS_ADDRESS.rpartition(':')
+    query_id = struct.unpack('>H', os.urandom(2))[0]
+    question = b''.join(
+        bytes([len(label)]) + label.encode()
+        for label in name.split('.')) + b'\x00'
+    packet = struct.pack('>HHHHHH', query_id, 0x0100, 1, 0, 0,
+                         0) + question + struct.pack('>HH', qtype, 1)
+    with socket.socket(socket.AF_INET, socket.SOCK_DGRAM) as sock:
+        sock.settimeout(2)
+        sock.sendto(packet, (host, int(port)))
+        return sock.recv(512)
+
+
+class CaptureTest(unittest.TestCase):
+    @classmethod
+    def setUpClass(cls):
+        if COMPOSE:
+            subprocess.run(['docker-compose', 'up', '-d', '--build'],
+                           check=True)
+        for _ in range(60):
+            try:
+                if api('GET', '/healthz')[0] == 200:
+                    break
+            except OSError:
+                pass
+            time.sleep(1)
+        else:
+            raise unittest.SkipTest(f'no requestrepo instance at {BASE_URL}')
+        cls.instance = json.loads(api('GET', '/api/v2/instance')[1])
+        cls.domain = cls.instance['domain']
+
+    @classmethod
+    def tearDownClass(cls):
+        if COMPOSE:
+            subprocess.run(['docker-compose', 'down'], check=False)
+
+    def setUp(self):
+        status, token = api('POST', '/api/get_token', body={})
+        self.assertEqual(status, 200)
+        self.token = token.decode()
+        payload = self.token.split('.')[1]
+        payload += '=' * (-len(payload) % 4)
+        self.subdomain = json.loads(
+            base64.urlsafe_b64decode(payload))['subdomain']
+
+    def captures(self, rtype):
+        # captures are stored after the response is sent
+        for _ in range(20):
+            status, body = api('GET', '/api/get_requests', self.token)
+            found = json.loads(body)[rtype]
+            if found:
+                self.assertEqual(len(found), 1)
+                return found[0]
+            time.sleep(0.5)
+        self.fail(f'no {rtype} capture')
+
+    def test_http_get(self):
+        api('GET',
+            '/capture?a=1&b=two',
+            host=f'{self.subdomain}.{self.domain}')
+        capture = self.captures('http')
+        self.assertEqual(capture['uid'], self.subdomain)
+        self.assertEqual(capture['method'], 'GET')
+        self.assertEqual(capture['path'], '/capture?a=1&b=two')
+        self.assertEqual(capture['query'], '?a=1&b=two')
+        self.assertEqual(capture['routing'], 'subdomain')
+        self.assertEqual(capture['headers']['Host'],
+                         f'{self.subdomain}.{self.domain}')
+        self.assertEqual(capture['raw'], '')
+
+    def test_http_post_json(self):
+        body = {'user': 'admin', 'tags': ['a', 'b']}
+        api('POST',
+            '/capture/json',
+            body=body,
+            host=f'{self.subdomain}.{self.domain}')
+        capture = self.captures('http')
+        self.assertEqual(capture['method'], 'POST')
+        self.assertEqual(json.loads(base64.b64decode(capture['raw'])), body)
+        self.assertEqual(capture['parsed'], {'type': 'json', 'value': body})
+
+    def test_http_repeated_headers(self):
+        # urllib sends one line per header name
+        url = urllib.parse.urlsplit(BASE_URL)
+        connection = (http.client.HTTPSConnection if url.scheme == 'https'
+                      else http.client.HTTPConnection)(url.netloc)
+        connection.putrequest('GET', '/capture/repeated', skip_host=True)
+        connection.putheader('Host', f'{self.subdomain}.{self.domain}')
+        connection.putheader('X-Repeated', 'one')
+        connection.putheader('X-Repeated', 'two')
+        connection.endheaders()
+        connection.getresponse().read()
+        connection.close()
+        capture = self.captures('http')
+        self.assertEqual(
+            [x for x in capture['header_list'] if x[0] == 'X-Repeated'],
+            [['X-Repeated', 'one'], ['X-Repeated', 'two']])
+
+    def test_http_path_routing(self):
+        if not self.instance['routing']['path']:
+            self.skipTest('path routing is disabled on this instance')
+        api('GET', f'/{self.subdomain}/capture/path', host=self.domain)
+        capture = self.captures('http')
+        self.assertEqual(capture['uid'], self.subdomain)
+        self.assertEqual(capture['routing'], 'path')
+        self.assertEqual(capture['path'], f'/{self.subdomain}/capture/path')
+
+    def test_dns_a(self):
+        name = f'capture.{self.subdomain}.{self.domain}'
+        dns_query(name, 1)
+        capture = self.captures('dns')
+        self.assertEqual(capture['uid'], self.subdomain)
+        self.assertEqual(capture['type'], 'A')
+        self.assertEqual(capture['name'].lower(), name + '.')
+        # the packet as sent, query id included
+        self.assertIn(b'\x07capture', base64.b64decode(capture['raw']))
+
+    def test_dns_txt(self):
+        dns_query(f'capture.{self.subdomain}.{self.domain}', 16)
+        self.assertEqual(self.captures('dns')['type'], 'TXT')
+
+    def test_dns_custom_record(self):
+        status, _ = api('POST', '/api/update_dns_records', self.token, {
+            'records': [{
+                'domain': 'capture',
+                'type': 0,
+                'value': '1.2.3.4'
+            }]
+        })
+        self.assertEqual(status, 200)
+        dns_query(f'capture.{self.subdomain}.{self.domain}', 1)
+        answers = self.captures('dns')['answers']
+        self.assertEqual([(x['type'], x['value']) for x in answers],
+                         [('A', '1.2.3.4')])
+
+
+if __name__ == '__main__':
+    unittest.main()